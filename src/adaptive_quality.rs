@@ -0,0 +1,233 @@
+use std::time::Duration;
+
+use crate::{frame_timer::FrameStats, render_settings::DEFAULT_MARCH_STEPS};
+
+const SCALE_STEP: f32 = 0.1;
+const MARCH_STEP_STEP: u32 = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QualityLevel {
+    pub render_scale: f32,
+    pub max_march_steps: u32,
+}
+
+impl Default for QualityLevel {
+    fn default() -> Self {
+        Self {
+            render_scale: 1.0,
+            max_march_steps: DEFAULT_MARCH_STEPS,
+        }
+    }
+}
+
+/// Lowers the render scale, then the march step budget, while frames take longer than the
+/// target, and restores them in the opposite order once there is headroom again.
+///
+/// With vsync enabled frame times never drop below the refresh interval, so quality is only
+/// restored when the target is set above it. Starts disabled, as it changes what the user sees.
+pub struct AdaptiveQuality {
+    pub enabled: bool,
+    pub target_frame_time: Duration,
+    pub min_render_scale: f32,
+    pub min_march_steps: u32,
+    /// Step budget quality is restored to, the one the scene started with
+    pub max_march_steps: u32,
+    level: QualityLevel,
+    frames_since_change: usize,
+    settle_frames: usize,
+}
+
+impl AdaptiveQuality {
+    /// `settle_frames` should cover the frame timer's window, so stats from before a change
+    /// are not acted on twice.
+    pub fn new(target_fps: f32, settle_frames: usize) -> Self {
+        Self {
+            enabled: false,
+            target_frame_time: Duration::from_secs_f32(1.0 / target_fps),
+            min_render_scale: 0.3,
+            min_march_steps: 64,
+            max_march_steps: DEFAULT_MARCH_STEPS,
+            level: QualityLevel::default(),
+            frames_since_change: 0,
+            settle_frames,
+        }
+    }
+
+    /// Starts from and restores quality to a step budget other than the default
+    pub fn with_max_march_steps(self, max_march_steps: u32) -> Self {
+        Self {
+            max_march_steps,
+            level: QualityLevel {
+                max_march_steps,
+                ..self.level
+            },
+            ..self
+        }
+    }
+
+    /// Feeds the latest frame stats, returning the new quality level when it changes.
+    pub fn update(&mut self, stats: &FrameStats) -> Option<QualityLevel> {
+        self.frames_since_change += 1;
+
+        if !self.enabled || self.frames_since_change < self.settle_frames {
+            return None;
+        }
+
+        let frame_time = stats.average_frame_time().as_secs_f32();
+        let target = self.target_frame_time.as_secs_f32();
+
+        let level = if frame_time > target * 1.15 {
+            self.lower()
+        } else if frame_time < target * 0.8 {
+            self.raise()
+        } else {
+            self.level
+        };
+
+        if level == self.level {
+            return None;
+        }
+
+        self.level = level;
+        self.frames_since_change = 0;
+        Some(level)
+    }
+
+    fn lower(&self) -> QualityLevel {
+        let QualityLevel {
+            render_scale,
+            max_march_steps,
+        } = self.level;
+
+        if render_scale > self.min_render_scale {
+            QualityLevel {
+                render_scale: (render_scale - SCALE_STEP).max(self.min_render_scale),
+                ..self.level
+            }
+        } else {
+            QualityLevel {
+                max_march_steps: max_march_steps
+                    .saturating_sub(MARCH_STEP_STEP)
+                    .max(self.min_march_steps),
+                ..self.level
+            }
+        }
+    }
+
+    fn raise(&self) -> QualityLevel {
+        let QualityLevel {
+            render_scale,
+            max_march_steps,
+        } = self.level;
+
+        if max_march_steps < self.max_march_steps {
+            QualityLevel {
+                max_march_steps: (max_march_steps + MARCH_STEP_STEP).min(self.max_march_steps),
+                ..self.level
+            }
+        } else {
+            QualityLevel {
+                render_scale: (render_scale + SCALE_STEP).min(1.0),
+                ..self.level
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame_timer::TimeStats;
+
+    const SETTLE_FRAMES: usize = 4;
+
+    fn stats(milliseconds: u64) -> FrameStats {
+        FrameStats {
+            samples: 1,
+            frame_time: TimeStats::from_samples([Duration::from_millis(milliseconds)]),
+            cpu_time: TimeStats::from_samples([Duration::ZERO]),
+            gpu_time: None,
+            passes: Vec::new(),
+        }
+    }
+
+    fn enabled() -> AdaptiveQuality {
+        AdaptiveQuality {
+            enabled: true,
+            ..AdaptiveQuality::new(60.0, SETTLE_FRAMES)
+        }
+    }
+
+    /// Feeds frames of `milliseconds` until the level changes, if it does within a second's worth
+    fn next_change(quality: &mut AdaptiveQuality, milliseconds: u64) -> Option<QualityLevel> {
+        (0..60).find_map(|_| quality.update(&stats(milliseconds)))
+    }
+
+    #[test]
+    fn starts_disabled() {
+        let mut quality = AdaptiveQuality::new(60.0, SETTLE_FRAMES);
+        assert_eq!(next_change(&mut quality, 100), None);
+    }
+
+    #[test]
+    fn slow_frames_lower_the_scale_then_the_step_budget() {
+        let mut quality = enabled();
+
+        let first = next_change(&mut quality, 25).unwrap();
+        assert!((first.render_scale - 0.9).abs() < 1e-6);
+        assert_eq!(first.max_march_steps, DEFAULT_MARCH_STEPS);
+
+        let mut level = first;
+        while level.render_scale > quality.min_render_scale {
+            assert_eq!(level.max_march_steps, DEFAULT_MARCH_STEPS);
+            level = next_change(&mut quality, 25).unwrap();
+        }
+
+        let level = next_change(&mut quality, 25).unwrap();
+        assert_eq!(level.render_scale, quality.min_render_scale);
+        assert_eq!(level.max_march_steps, DEFAULT_MARCH_STEPS - MARCH_STEP_STEP);
+    }
+
+    #[test]
+    fn changes_wait_for_the_frame_window_to_settle() {
+        let mut quality = enabled();
+
+        for _ in 1..SETTLE_FRAMES {
+            assert_eq!(quality.update(&stats(25)), None);
+        }
+        assert!(quality.update(&stats(25)).is_some());
+        assert_eq!(quality.update(&stats(25)), None);
+    }
+
+    #[test]
+    fn fast_frames_restore_the_step_budget_then_the_scale() {
+        let mut quality = enabled();
+        let mut level = QualityLevel::default();
+        while level.max_march_steps == DEFAULT_MARCH_STEPS {
+            level = next_change(&mut quality, 25).unwrap();
+        }
+
+        let level = next_change(&mut quality, 10).unwrap();
+        assert_eq!(level.max_march_steps, DEFAULT_MARCH_STEPS);
+        assert_eq!(level.render_scale, quality.min_render_scale);
+
+        let level = next_change(&mut quality, 10).unwrap();
+        assert!(level.render_scale > quality.min_render_scale);
+
+        // Fully restored quality has nowhere further to go
+        while next_change(&mut quality, 10).is_some() {}
+        assert_eq!(quality.level, QualityLevel::default());
+    }
+
+    #[test]
+    fn frames_within_the_band_keep_the_level() {
+        let mut quality = enabled();
+        let lowered = next_change(&mut quality, 25).unwrap();
+
+        // Between 0.8 and 1.15 times the 16.7ms target
+        for milliseconds in [14, 16, 17, 19, 14, 19] {
+            assert_eq!(next_change(&mut quality, milliseconds), None);
+        }
+        assert_eq!(quality.level, lowered);
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+    process::{Child, ChildStdin, Command, Stdio},
+};
+
+use glam::Vec3;
+use pollster::FutureExt;
+
+use crate::{
+    camera_path::CameraPath,
+    light_buffers::LightBuffers,
+    render_settings::RenderSettings,
+    scene_descriptor::SceneDescriptorBuilder,
+    screenshot,
+    timeline::Timeline,
+    wgpu_context::{AntiAliasing, RenderPath, WgpuContext, CAPTURE_SAMPLES},
+};
+
+pub struct AnimateOptions {
+    /// Name recorded in the metadata of each image
+    pub scene_name: String,
+    pub frames: usize,
+    /// Seconds between frames
+    pub timestep: f32,
+    pub size: (u32, u32),
+    pub render_path: RenderPath,
+    /// Directory the numbered PNGs are written to, `None` to skip them
+    pub output: Option<PathBuf>,
+    /// Shell command receiving every frame as raw RGBA8 on stdin, such as an `ffmpeg`
+    /// invocation reading `-f rawvideo -pix_fmt rgba`
+    pub pipe: Option<String>,
+}
+
+impl Default for AnimateOptions {
+    fn default() -> Self {
+        Self {
+            scene_name: "default".into(),
+            frames: 120,
+            timestep: 1.0 / 30.0,
+            size: (1280, 720),
+            render_path: RenderPath::Fragment,
+            output: Some("frames".into()),
+            pipe: None,
+        }
+    }
+}
+
+/// An external encoder fed frames through its stdin
+struct FramePipe {
+    child: Child,
+    stdin: ChildStdin,
+}
+
+impl FramePipe {
+    fn spawn(command: &str) -> io::Result<Self> {
+        let mut child = Command::new("sh")
+            .args(["-c", command])
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("Stdin is piped");
+        Ok(Self { child, stdin })
+    }
+
+    /// Closes stdin so the encoder can finish, then waits for it
+    fn finish(self) -> io::Result<()> {
+        let Self { mut child, stdin } = self;
+        drop(stdin);
+
+        let status = child.wait()?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(io::Error::other(format!(
+                "Frame encoder exited with {status}"
+            ))),
+        }
+    }
+}
+
+/// Renders frames `0..frames` headlessly at a fixed timestep, each supersampled on its own, and
+/// writes each one out as it completes. The camera follows the timeline's camera tracks, or
+/// without any orbits once around the origin over the whole sequence.
+pub fn run(
+    options: &AnimateOptions,
+    scene: (SceneDescriptorBuilder, LightBuffers),
+    timeline: &Timeline,
+) -> io::Result<()> {
+    let mut ctx = WgpuContext::headless(options.size, options.render_path).block_on();
+    ctx.render_settings = RenderSettings::for_scene(&scene.0);
+    // Temporal history would carry each frame into the next, ghosting under motion
+    ctx.anti_aliasing = AntiAliasing::Supersample(CAPTURE_SAMPLES);
+    let path = CameraPath {
+        bob: 0.0,
+        ..CameraPath::orbit(Vec3::ZERO, 10.0, 3.0)
+    };
+
+    if let Some(output) = &options.output {
+        fs::create_dir_all(output)?;
+    }
+    let mut pipe = options.pipe.as_deref().map(FramePipe::spawn).transpose()?;
+
+    let duration = options.frames as f32 * options.timestep;
+    log::info!(
+        "Rendering {} frames ({duration:.2}s) at {}x{}",
+        options.frames,
+        options.size.0,
+        options.size.1
+    );
+
+    for frame in 0..options.frames {
+        let time = frame as f32 * options.timestep;
+        let camera = match timeline.animates_camera() {
+            true => timeline.camera(time, path.camera_at(0.0)),
+            false => path.camera_at(time / duration),
+        };
+
+        ctx.render(timeline.evaluate(time, &scene), &camera)
+            .expect("Headless rendering has no surface to lose");
+        let image = ctx
+            .read_output()
+            .expect("Headless contexts render into a readable target");
+
+        if let Some(pipe) = &mut pipe {
+            pipe.stdin.write_all(image.as_raw())?;
+        }
+
+        if let Some(output) = &options.output {
+            let mut metadata = screenshot::metadata(&options.scene_name, &camera);
+            metadata.push(("Frame", frame.to_string()));
+            metadata.push(("Time", time.to_string()));
+            screenshot::save_png(
+                &output.join(format!("frame_{frame:05}.png")),
+                &image,
+                &metadata,
+            )?;
+        }
+
+        log::debug!("Rendered frame {}/{}", frame + 1, options.frames);
+    }
+
+    if let Some(pipe) = pipe {
+        pipe.finish()?;
+    }
+    if let Some(output) = &options.output {
+        log::info!("Wrote {} frames to {}", options.frames, output.display());
+    }
+
+    Ok(())
+}
@@ -1,16 +1,30 @@
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use crate::{
-    camera::Camera, frame_timer::FrameTimer, input::Input, make_scene, wgpu_context::WgpuContext,
+    adaptive_quality::AdaptiveQuality,
+    camera::Camera,
+    frame_timer::{FrameStats, FrameTimer},
+    input::Input,
+    light_buffers::LightBuffers,
+    render_settings::{DebugView, RenderSettings},
+    scene_descriptor::SceneDescriptorBuilder,
+    screenshot,
+    timeline::{Playback, Timeline},
+    wgpu_context::{
+        post_process::{PostEffect, PostParameter},
+        RenderPath, WgpuContext,
+    },
 };
 use glam::{quat, vec3, Quat};
 use winit::{
     application::ApplicationHandler,
     event::WindowEvent,
-    keyboard::{self, NamedKey},
+    keyboard::{self, Key, NamedKey},
     window::Window,
 };
 
+const FRAME_TIMER_WINDOW: usize = 30;
+
 pub struct App<'a> {
     window: &'a Window,
     ctx: WgpuContext<'a>,
@@ -19,11 +33,31 @@ pub struct App<'a> {
     camera: Camera,
     input: Input,
     frame_timer: FrameTimer,
+    adaptive_quality: AdaptiveQuality,
+    scene: (SceneDescriptorBuilder, LightBuffers),
+    timeline: Timeline,
+    playback: Playback,
+    last_frame: Instant,
+    frame: usize,
+    /// Frames between logged stats, zero to disable
+    report_interval: usize,
+    /// Recorded in screenshot metadata
+    scene_name: String,
+    /// Multiple of the window size screenshots are rendered at
+    screenshot_scale: u32,
+    /// Post processing setting changed by the bracket keys
+    post_parameter: PostParameter,
 }
 
 impl<'a> App<'a> {
-    pub async fn create(window: &'a Window) -> Self {
-        let ctx = WgpuContext::new(window).await;
+    pub async fn create(
+        window: &'a Window,
+        scene: (SceneDescriptorBuilder, LightBuffers),
+        render_path: RenderPath,
+    ) -> Self {
+        let mut ctx = WgpuContext::new(window, render_path).await;
+        ctx.render_settings = RenderSettings::for_scene(&scene.0);
+        let max_march_steps = ctx.render_settings.max_march_steps;
         let input = Input::new();
 
         Self {
@@ -39,10 +73,68 @@ impl<'a> App<'a> {
                 1000.0,
             ),
             input,
-            frame_timer: FrameTimer::new(30),
+            frame_timer: FrameTimer::new(FRAME_TIMER_WINDOW),
+            adaptive_quality: AdaptiveQuality::new(60.0, FRAME_TIMER_WINDOW)
+                .with_max_march_steps(max_march_steps),
+            scene,
+            timeline: Timeline::new(),
+            playback: Playback::default(),
+            last_frame: Instant::now(),
+            frame: 0,
+            report_interval: FRAME_TIMER_WINDOW,
+            scene_name: "default".into(),
+            screenshot_scale: 1,
+            post_parameter: PostParameter::BloomThreshold,
         }
     }
 
+    pub fn set_report_interval(&mut self, frames: usize) {
+        self.report_interval = frames;
+    }
+
+    pub fn set_timeline(&mut self, timeline: Timeline) {
+        self.timeline = timeline;
+        self.playback = Playback::default();
+    }
+
+    /// Starts the timeline playing rather than waiting for space to be pressed
+    pub fn play(&mut self) {
+        self.playback.playing = true;
+    }
+
+    /// Lets the render scale and march step budget drop to hold 60fps, rather than waiting for
+    /// g to be pressed
+    pub fn enable_adaptive_quality(&mut self) {
+        self.adaptive_quality.enabled = true;
+    }
+
+    pub fn set_scene_name(&mut self, name: &str) {
+        self.scene_name = name.into();
+    }
+
+    pub fn set_screenshot_scale(&mut self, scale: u32) {
+        self.screenshot_scale = scale.max(1);
+    }
+
+    /// Renders the current view again offscreen and saves it as a timestamped PNG. Encoding
+    /// happens on another thread so large captures don't stall the window.
+    fn take_screenshot(&mut self) {
+        let scene = self.timeline.evaluate(self.playback.time, &self.scene);
+        let Some(image) = self.ctx.capture(scene, &self.camera, self.screenshot_scale) else {
+            log::error!("Failed to capture screenshot");
+            return;
+        };
+
+        let path = screenshot::timestamped_path("screenshot");
+        let metadata = screenshot::metadata(&self.scene_name, &self.camera);
+        std::thread::spawn(
+            move || match screenshot::save_png(&path, &image, &metadata) {
+                Ok(()) => log::info!("Saved screenshot to {}", path.display()),
+                Err(err) => log::error!("Failed to save screenshot to {}: {err}", path.display()),
+            },
+        );
+    }
+
     fn render_frame(&mut self) {
         let start = Instant::now();
         let rotation_input = self.input.camera_rotation();
@@ -68,12 +160,186 @@ impl<'a> App<'a> {
         let translation = self.camera.orientation.inverse() * translation;
         self.camera.position += translation;
 
-        self.ctx.render(make_scene(), &self.camera).unwrap();
-        println!("{:}", self.frame_timer.mark_frame());
-        let end = Instant::now();
-        let elapsed = end - start;
-        println!("Frame time: {:?}", elapsed);
+        let settings = &mut self.ctx.post_process_settings;
+        settings.focus_distance =
+            (settings.focus_distance + self.input.focus_adjustment()).max(0.1);
+
+        self.playback
+            .advance((start - self.last_frame).as_secs_f32(), &self.timeline);
+        self.last_frame = start;
+
+        // An animated camera takes over from the controls while playing
+        if self.playback.playing {
+            self.camera = self.timeline.camera(self.playback.time, self.camera);
+        }
+
+        let scene = self.timeline.evaluate(self.playback.time, &self.scene);
+        self.ctx.render(scene, &self.camera).unwrap();
+        let cpu_time = start.elapsed();
+        let gpu_timings = self.ctx.take_gpu_timings();
+        let gpu_time = gpu_timings
+            .as_ref()
+            .map(|passes| passes.iter().map(|(_, time)| *time).sum());
+        self.ctx.overlay.push_sample(cpu_time, gpu_time);
+
+        let stats = self.frame_timer.mark_frame(cpu_time, gpu_timings);
+        if self.ctx.overlay.enabled {
+            let text = self.overlay_text(&stats);
+            self.ctx.overlay.set_text(&text);
+        }
+
+        self.frame += 1;
+        if self.report_interval > 0 && self.frame.is_multiple_of(self.report_interval) {
+            log::info!("{}", stats);
+            if self.ctx.render_settings.collect_stats != 0 {
+                log::info!("{}", self.ctx.read_march_stats());
+            }
+        }
+
+        if let Some(quality) = self.adaptive_quality.update(&stats) {
+            log::info!("Adjusting quality: {:?}", quality);
+            self.ctx.set_render_scale(quality.render_scale);
+            self.ctx.render_settings.max_march_steps = quality.max_march_steps;
+        }
+    }
+
+    fn overlay_text(&self, stats: &FrameStats) -> String {
+        let ms = |duration: Duration| duration.as_secs_f32() * 1000.0;
+        let frame_ms = ms(stats.frame_time.mean);
+        let gpu = match &stats.gpu_time {
+            Some(gpu_time) => format!("{:.2}MS", ms(gpu_time.mean)),
+            None => "N/A".into(),
+        };
+
+        let (width, height) = self.ctx.size;
+        let (render_width, render_height) = self.ctx.render_size();
+        let position = self.camera.position;
+        let (scene, _) = &self.scene;
+
+        [
+            format!(
+                "FPS {:.1}  FRAME {:.2}MS",
+                1000.0 / frame_ms.max(0.001),
+                frame_ms
+            ),
+            format!("CPU {:.2}MS  GPU {}", ms(stats.cpu_time.mean), gpu),
+            format!("SIZE {width}X{height}  RENDER {render_width}X{render_height}"),
+            format!("POS {:.2} {:.2} {:.2}", position.x, position.y, position.z),
+            format!("YAW {:.1}  PITCH {:.1}", self.yaw, self.pitch),
+            format!(
+                "SPHERES {}  CUBOIDS {}  SHAPES {}  MEDIA {}",
+                scene.spheres.len(),
+                scene.cuboids.len(),
+                scene.shapes.len(),
+                scene.media.len()
+            ),
+        ]
+        .join("\n")
+    }
+
+    fn on_key_pressed(&mut self, key: &Key) {
+        if let Some(view) = self.input.debug_view(key) {
+            // Pressing the key of the current view again returns to the lit image
+            let settings = &mut self.ctx.render_settings;
+            if settings.debug_view() == view {
+                settings.set_debug_view(DebugView::None);
+            } else {
+                settings.set_debug_view(view);
+            }
+            log::info!("Debug view: {:?}", settings.debug_view());
+            return;
+        }
+
+        if *key == Key::Named(NamedKey::Space) {
+            self.playback.toggle();
+            log::info!(
+                "Timeline {} at {:.2}s",
+                if self.playback.playing {
+                    "playing"
+                } else {
+                    "paused"
+                },
+                self.playback.time
+            );
+            return;
+        }
+
+        let Key::Character(character) = key else {
+            return;
+        };
+
+        let settings = &mut self.ctx.post_process_settings;
+
+        match character.as_str() {
+            "b" => settings.toggle(PostEffect::Bloom),
+            "f" => settings.toggle(PostEffect::DepthOfField),
+            "c" => settings.toggle(PostEffect::ChromaticAberration),
+            "v" => settings.toggle(PostEffect::Vignette),
+            "j" => {
+                self.post_parameter = self.post_parameter.next();
+                log::info!(
+                    "Adjusting {:?}: {:.3}",
+                    self.post_parameter,
+                    settings.get(self.post_parameter)
+                );
+            }
+            "[" | "]" => {
+                let steps = if character.as_str() == "[" { -1.0 } else { 1.0 };
+                settings.adjust(self.post_parameter, steps);
+                log::info!(
+                    "{:?}: {:.3}",
+                    self.post_parameter,
+                    settings.get(self.post_parameter)
+                );
+            }
+            "h" => {
+                let settings = &mut self.ctx.render_settings;
+                settings.use_bvh = 1 - settings.use_bvh;
+                log::info!("Bounding volume hierarchy: {}", settings.use_bvh != 0);
+            }
+            "k" => {
+                let settings = &mut self.ctx.render_settings;
+                settings.cone_prepass = 1 - settings.cone_prepass;
+                log::info!("Cone marching pre-pass: {}", settings.cone_prepass != 0);
+            }
+            "m" => {
+                let settings = &mut self.ctx.render_settings;
+                settings.collect_stats = 1 - settings.collect_stats;
+                log::info!("March statistics: {}", settings.collect_stats != 0);
+            }
+            "r" => {
+                let settings = &mut self.ctx.render_settings;
+                settings.set_march_strategy(settings.march_strategy().next());
+                log::info!("March strategy: {:?}", settings.march_strategy());
+            }
+            "u" => {
+                let settings = &mut self.ctx.render_settings;
+                settings.pixel_footprint = if settings.pixel_footprint > 0.0 {
+                    0.0
+                } else {
+                    1.0
+                };
+                log::info!("Hit threshold footprint: {} px", settings.pixel_footprint);
+            }
+            "p" => self.take_screenshot(),
+            "," | "." => {
+                let step = if character.as_str() == "," { -0.1 } else { 0.1 };
+                self.playback.scrub(step, &self.timeline);
+                log::info!("Timeline at {:.2}s", self.playback.time);
+            }
+            "o" => self.ctx.overlay.enabled = !self.ctx.overlay.enabled,
+            "g" => {
+                self.adaptive_quality.enabled = !self.adaptive_quality.enabled;
+                log::info!("Adaptive quality: {}", self.adaptive_quality.enabled);
+            }
+            "t" => {
+                self.ctx.anti_aliasing = self.ctx.anti_aliasing.next();
+                log::info!("Anti-aliasing: {:?}", self.ctx.anti_aliasing);
+            }
+            _ => {}
+        }
     }
+
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         self.ctx.resize(self.window, new_size);
     }
@@ -109,6 +375,9 @@ impl ApplicationHandler for App<'_> {
                 if matches!(event.logical_key, keyboard::Key::Named(NamedKey::Escape)) {
                     event_loop.exit()
                 }
+                if event.state.is_pressed() && !event.repeat {
+                    self.on_key_pressed(&event.logical_key);
+                }
                 self.input
                     .keyboard
                     .on_keyboard_button(event.logical_key, event.state);
@@ -0,0 +1,215 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use glam::UVec3;
+use pollster::FutureExt;
+
+use crate::{
+    bvh::{Aabb, Bvh},
+    scene_descriptor::{distance_grid::DistanceGrid, SceneDescriptorBuilder},
+    wgpu_context::bake::bake_grid,
+};
+
+/// Changes whenever the cache file layout or what goes into the key does
+const CACHE_VERSION: u32 = 1;
+const CACHE_MAGIC: &[u8; 8] = b"SDFGRID\0";
+
+/// Where a bake is evaluated. Both sample the same points and give the same grid up to
+/// floating point differences.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BakeMethod {
+    /// Evaluates the scene on the CPU, through its bounding volume hierarchy
+    Cpu,
+    /// Evaluates `map()` with a compute pass
+    Compute,
+}
+
+#[derive(Clone, Debug)]
+pub struct BakeOptions {
+    pub method: BakeMethod,
+    /// Cells along the longest side of the bounds
+    pub resolution: u32,
+    /// Region baked, the subtree's bounds padded by a couple of cells when absent
+    pub bounds: Option<Aabb>,
+    /// Directory baked grids are cached in, keyed by the hash of what was baked
+    pub cache: Option<PathBuf>,
+}
+
+impl Default for BakeOptions {
+    fn default() -> Self {
+        Self {
+            method: BakeMethod::Cpu,
+            resolution: 128,
+            bounds: None,
+            cache: Some(".sdf-cache".into()),
+        }
+    }
+}
+
+/// 64 bit FNV-1a, which unlike the standard library's hashers is stable between builds, so
+/// it can name files
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+/// Hash of everything that affects the baked values: the subtree's objects, any grids and
+/// heightmaps they sample, and the layout of the grid
+fn subtree_hash(subtree: &SceneDescriptorBuilder, grid: &DistanceGrid) -> u64 {
+    let mut hash = Fnv::new();
+    hash.write(&CACHE_VERSION.to_le_bytes());
+    hash.write(bytemuck::cast_slice(&[grid.bounds.min, grid.bounds.max]));
+    hash.write(bytemuck::bytes_of(&grid.resolution));
+    hash.write(bytemuck::cast_slice(&subtree.spheres));
+    hash.write(bytemuck::cast_slice(&subtree.cuboids));
+    hash.write(bytemuck::cast_slice(&subtree.shapes));
+    hash.write(bytemuck::cast_slice(&subtree.operands.spheres));
+    hash.write(bytemuck::cast_slice(&subtree.operands.cuboids));
+    hash.write(bytemuck::cast_slice(&subtree.operands.shapes));
+    for sampled in &subtree.grids {
+        hash.write(bytemuck::cast_slice(&[
+            sampled.bounds.min,
+            sampled.bounds.max,
+        ]));
+        hash.write(bytemuck::bytes_of(&sampled.resolution));
+        hash.write(bytemuck::cast_slice(&sampled.values));
+    }
+    for heightmap in &subtree.heightmaps {
+        hash.write(bytemuck::bytes_of(&heightmap.size));
+        hash.write(bytemuck::cast_slice(&heightmap.values));
+    }
+    hash.0
+}
+
+fn read_cached(path: &Path, grid: &DistanceGrid) -> io::Result<Vec<f32>> {
+    let bytes = fs::read(path)?;
+    let header = CACHE_MAGIC.len() + size_of::<[f32; 6]>() + size_of::<UVec3>();
+    let expected = header + grid.resolution.element_product() as usize * size_of::<f32>();
+
+    // The key covers the layout, so anything that doesn't match is a corrupt file
+    if bytes.len() != expected || !bytes.starts_with(CACHE_MAGIC) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} is not a baked grid of the expected size",
+                path.display()
+            ),
+        ));
+    }
+
+    Ok(bytes[header..]
+        .chunks_exact(4)
+        .map(|value| f32::from_le_bytes(value.try_into().unwrap()))
+        .collect())
+}
+
+fn write_cached(path: &Path, grid: &DistanceGrid) -> io::Result<()> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+
+    let mut writer = io::BufWriter::new(fs::File::create(path)?);
+    writer.write_all(CACHE_MAGIC)?;
+    for value in grid
+        .bounds
+        .min
+        .to_array()
+        .into_iter()
+        .chain(grid.bounds.max.to_array())
+    {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    for count in grid.resolution.to_array() {
+        writer.write_all(&count.to_le_bytes())?;
+    }
+    for value in &grid.values {
+        writer.write_all(&value.to_le_bytes())?;
+    }
+    writer.flush()
+}
+
+/// Samples the distance to `subtree`'s objects into a grid, so a shape can stand in for an
+/// expensive union or fractal and be sampled instead of evaluated every march step. Bakes
+/// found in the cache are loaded rather than evaluated again.
+pub fn bake(subtree: &SceneDescriptorBuilder, options: &BakeOptions) -> DistanceGrid {
+    let bounds = options
+        .bounds
+        .unwrap_or_else(|| DistanceGrid::pad(subtree.bounds(), options.resolution));
+    let grid = DistanceGrid::empty(bounds, options.resolution);
+
+    let cache_path = options
+        .cache
+        .as_ref()
+        .map(|directory| directory.join(format!("{:016x}.sdf", subtree_hash(subtree, &grid))));
+
+    if let Some(path) = cache_path.as_ref().filter(|path| path.exists()) {
+        match read_cached(path, &grid) {
+            Ok(values) => {
+                log::info!("Loaded baked grid from {}", path.display());
+                return DistanceGrid { values, ..grid };
+            }
+            Err(error) => log::warn!("Rebaking, failed to read {}: {error}", path.display()),
+        }
+    }
+
+    let start = Instant::now();
+    let grid = match options.method {
+        BakeMethod::Cpu => {
+            let bvh = Bvh::build(subtree);
+            DistanceGrid::sample(
+                |point| bvh.distance(subtree, point),
+                bounds,
+                options.resolution,
+            )
+        }
+        BakeMethod::Compute => bake_grid(subtree, grid).block_on(),
+    };
+
+    log::info!(
+        "Baked a {}x{}x{} grid with {:?} in {:.2}s",
+        grid.resolution.x,
+        grid.resolution.y,
+        grid.resolution.z,
+        options.method,
+        start.elapsed().as_secs_f32()
+    );
+
+    if let Some(path) = cache_path {
+        match write_cached(&path, &grid) {
+            Ok(()) => log::info!("Cached baked grid to {}", path.display()),
+            Err(error) => log::warn!("Failed to cache baked grid: {error}"),
+        }
+    }
+
+    grid
+}
+
+/// Replaces every object of `scene` with a single shape sampling them baked into a grid,
+/// leaving media and fog as they are
+pub fn bake_scene(scene: &mut SceneDescriptorBuilder, options: &BakeOptions) {
+    let subtree = SceneDescriptorBuilder {
+        spheres: std::mem::take(&mut scene.spheres),
+        cuboids: std::mem::take(&mut scene.cuboids),
+        shapes: std::mem::take(&mut scene.shapes),
+        grids: std::mem::take(&mut scene.grids),
+        heightmaps: std::mem::take(&mut scene.heightmaps),
+        operands: std::mem::take(&mut scene.operands),
+        ..Default::default()
+    };
+
+    let shape = scene.add_grid(bake(&subtree, options));
+    scene.shapes.push(shape);
+}
@@ -0,0 +1,175 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use glam::Vec3;
+use pollster::FutureExt;
+
+use crate::{
+    camera_path::CameraPath,
+    frame_timer::{FrameStats, FrameTimer, TimeStats},
+    light_buffers::LightBuffers,
+    scene_descriptor::SceneDescriptorBuilder,
+    wgpu_context::{RenderPath, WgpuContext},
+};
+
+pub struct BenchOptions {
+    /// Name recorded in the report
+    pub scene_name: String,
+    /// Frames measured, at least one
+    pub frames: usize,
+    /// Frames rendered before measuring, so pipeline and buffer creation is not counted
+    pub warmup_frames: usize,
+    pub size: (u32, u32),
+    pub render_path: RenderPath,
+    /// Written as JSON when the extension is `json`, CSV otherwise
+    pub output: PathBuf,
+}
+
+impl Default for BenchOptions {
+    fn default() -> Self {
+        Self {
+            scene_name: "default".into(),
+            frames: 300,
+            warmup_frames: 10,
+            size: (1280, 720),
+            render_path: RenderPath::Fragment,
+            output: "bench.csv".into(),
+        }
+    }
+}
+
+pub struct BenchReport {
+    pub scene_name: String,
+    pub size: (u32, u32),
+    pub render_path: RenderPath,
+    pub stats: FrameStats,
+}
+
+/// Renders the scene headlessly along a fixed orbit, waiting on the GPU after every frame so
+/// frame times are not hidden by queued work, and writes the resulting statistics.
+pub fn run(
+    options: &BenchOptions,
+    scene: (SceneDescriptorBuilder, LightBuffers),
+) -> io::Result<BenchReport> {
+    let mut ctx = WgpuContext::headless(options.size, options.render_path).block_on();
+    let path = CameraPath::orbit(Vec3::ZERO, 10.0, 3.0);
+
+    let total_frames = options.warmup_frames + options.frames;
+    let mut frame_timer = FrameTimer::new(options.frames);
+    let mut stats = None;
+
+    for frame in 0..total_frames {
+        if frame == options.warmup_frames {
+            frame_timer = FrameTimer::new(options.frames);
+        }
+
+        let start = Instant::now();
+        let camera = path.camera_at(frame as f32 / total_frames as f32);
+
+        ctx.render(scene.clone(), &camera)
+            .expect("Headless rendering has no surface to lose");
+        let cpu_time = start.elapsed();
+
+        ctx.wait_idle();
+
+        // GPU timings lag a frame behind, so the first measured frame reports a warmup frame
+        let gpu_timings = ctx
+            .take_gpu_timings()
+            .filter(|_| frame > options.warmup_frames);
+        stats = Some(frame_timer.mark_frame(cpu_time, gpu_timings));
+    }
+
+    let report = BenchReport {
+        scene_name: options.scene_name.clone(),
+        size: options.size,
+        render_path: options.render_path,
+        stats: stats.expect("Benchmarks render at least one frame"),
+    };
+
+    log::info!("{}", report.stats);
+    report.write(&options.output)?;
+    log::info!("Wrote benchmark report to {}", options.output.display());
+
+    Ok(report)
+}
+
+impl BenchReport {
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => self.write_json(&mut writer)?,
+            _ => self.write_csv(&mut writer)?,
+        }
+        writer.flush()
+    }
+
+    /// Frame, CPU and GPU times followed by each GPU pass
+    fn metrics(&self) -> Vec<(&str, TimeStats)> {
+        let stats = &self.stats;
+        let mut metrics = vec![("frame", stats.frame_time), ("cpu", stats.cpu_time)];
+        metrics.extend(stats.gpu_time.map(|gpu_time| ("gpu", gpu_time)));
+        metrics.extend(stats.passes.iter().map(|(label, time)| (*label, *time)));
+        metrics
+    }
+
+    /// One row per metric. The scene name is quoted, as it may hold commas or quotes.
+    pub fn write_csv(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(
+            writer,
+            "scene,render_path,width,height,frames,metric,mean_ms,p50_ms,p95_ms,p99_ms,min_ms,max_ms"
+        )?;
+
+        for (metric, time) in self.metrics() {
+            let [mean, p50, p95, p99, min, max] = milliseconds(&time);
+            writeln!(
+                writer,
+                "{},{:?},{},{},{},{},{mean:.4},{p50:.4},{p95:.4},{p99:.4},{min:.4},{max:.4}",
+                csv_field(&self.scene_name),
+                self.render_path,
+                self.size.0,
+                self.size.1,
+                self.stats.samples,
+                metric,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_json(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "{{")?;
+        writeln!(writer, "  \"scene\": {:?},", self.scene_name)?;
+        writeln!(writer, "  \"render_path\": \"{:?}\",", self.render_path)?;
+        writeln!(writer, "  \"width\": {},", self.size.0)?;
+        writeln!(writer, "  \"height\": {},", self.size.1)?;
+        writeln!(writer, "  \"frames\": {},", self.stats.samples)?;
+        writeln!(writer, "  \"metrics\": {{")?;
+
+        let metrics = self.metrics();
+        for (index, (metric, time)) in metrics.iter().enumerate() {
+            let [mean, p50, p95, p99, min, max] = milliseconds(time);
+            let separator = if index + 1 < metrics.len() { "," } else { "" };
+            writeln!(
+                writer,
+                "    {metric:?}: {{ \"mean_ms\": {mean:.4}, \"p50_ms\": {p50:.4}, \"p95_ms\": {p95:.4}, \"p99_ms\": {p99:.4}, \"min_ms\": {min:.4}, \"max_ms\": {max:.4} }}{separator}"
+            )?;
+        }
+
+        writeln!(writer, "  }}")?;
+        writeln!(writer, "}}")
+    }
+}
+
+/// `field` quoted for CSV, with any quotes inside it doubled
+fn csv_field(field: &str) -> String {
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+fn milliseconds(time: &TimeStats) -> [f64; 6] {
+    [time.mean, time.p50, time.p95, time.p99, time.min, time.max]
+        .map(|duration| duration.as_secs_f64() * 1000.0)
+}
@@ -0,0 +1,383 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3, Vec4};
+
+use crate::scene_descriptor::{SceneDescriptorBuilder, MAX_SIGNED_DISTANCE};
+
+/// Set on primitive indices that refer to a cuboid rather than a sphere
+pub const CUBOID_FLAG: u32 = 1 << 31;
+/// Set on primitive indices that refer to a shape
+pub const SHAPE_FLAG: u32 = 1 << 30;
+
+const LEAF_SIZE: usize = 4;
+/// Nodes traversal can hold at once, matches `BVH_STACK_SIZE` in the shader
+const STACK_SIZE: usize = 32;
+/// Deepest a node is placed, so traversal never overflows its stack. Each level above a node
+/// leaves at most one sibling waiting, and visiting it pushes its two children.
+const MAX_DEPTH: usize = STACK_SIZE - 2;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub const EMPTY: Self = Self {
+        min: Vec3::INFINITY,
+        max: Vec3::NEG_INFINITY,
+    };
+
+    /// World space bounds of a local space box, given the world to local transform objects store
+    pub fn from_local(transform: Mat4, half_extents: Vec3) -> Self {
+        let to_world = transform.inverse();
+        let mut bounds = Self::EMPTY;
+        for corner in 0..8 {
+            let sign = Vec3::new(
+                if corner & 1 == 0 { -1.0 } else { 1.0 },
+                if corner & 2 == 0 { -1.0 } else { 1.0 },
+                if corner & 4 == 0 { -1.0 } else { 1.0 },
+            );
+            bounds = bounds.grow(to_world.transform_point3(sign * half_extents));
+        }
+        bounds
+    }
+
+    pub fn grow(self, point: Vec3) -> Self {
+        Self {
+            min: self.min.min(point),
+            max: self.max.max(point),
+        }
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// Distance from `point` to the nearest point of the box, zero inside it
+    pub fn distance(&self, point: Vec3) -> f32 {
+        (self.min - point)
+            .max(point - self.max)
+            .max(Vec3::ZERO)
+            .length()
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// Center in xyz and radius in w of a sphere enclosing the box
+    pub fn bounding_sphere(&self) -> Vec4 {
+        self.center().extend((self.max - self.min).length() * 0.5)
+    }
+}
+
+/// Leaves have a non zero `count` of primitives starting at `left_or_first`, internal nodes
+/// have their two children at `left_or_first` and `left_or_first + 1`.
+#[repr(C, align(16))]
+#[derive(Clone, Copy, Debug)]
+pub struct BvhNode {
+    pub min: Vec3,
+    pub left_or_first: u32,
+    pub max: Vec3,
+    pub count: u32,
+}
+
+unsafe impl Pod for BvhNode {}
+unsafe impl Zeroable for BvhNode {}
+
+/// Matches `evaluate_sdf_primitive` in the shader
+fn primitive_distance(scene: &SceneDescriptorBuilder, primitive: u32, point: Vec3) -> f32 {
+    let index = (primitive & !(CUBOID_FLAG | SHAPE_FLAG)) as usize;
+    if primitive & CUBOID_FLAG != 0 {
+        scene.cuboids[index].distance(point)
+    } else if primitive & SHAPE_FLAG != 0 {
+        scene.shapes[index].distance(point, scene)
+    } else {
+        scene.spheres[index].distance(point)
+    }
+}
+
+/// Bounding spheres of every object, spheres first then cuboids then shapes
+pub fn bounding_spheres(scene: &SceneDescriptorBuilder) -> Vec<Vec4> {
+    scene
+        .spheres
+        .iter()
+        .map(|sphere| sphere.bounds().bounding_sphere())
+        .chain(
+            scene
+                .cuboids
+                .iter()
+                .map(|cuboid| cuboid.bounds().bounding_sphere()),
+        )
+        .chain(
+            scene
+                .shapes
+                .iter()
+                .map(|shape| shape.bounds(scene).bounding_sphere()),
+        )
+        .collect()
+}
+
+/// Bounding volume hierarchy over the scene's objects, built on the CPU and traversed by `map()`.
+pub struct Bvh {
+    pub nodes: Vec<BvhNode>,
+    pub primitives: Vec<u32>,
+}
+
+impl Bvh {
+    pub fn build(scene: &SceneDescriptorBuilder) -> Self {
+        let mut items: Vec<(u32, Aabb)> = scene
+            .spheres
+            .iter()
+            .enumerate()
+            .map(|(index, sphere)| (index as u32, sphere.bounds()))
+            .chain(
+                scene
+                    .cuboids
+                    .iter()
+                    .enumerate()
+                    .map(|(index, cuboid)| (index as u32 | CUBOID_FLAG, cuboid.bounds())),
+            )
+            .chain(
+                scene
+                    .shapes
+                    .iter()
+                    .enumerate()
+                    .map(|(index, shape)| (index as u32 | SHAPE_FLAG, shape.bounds(scene))),
+            )
+            .collect();
+
+        let mut bvh = Self {
+            nodes: vec![BvhNode::zeroed()],
+            primitives: Vec::with_capacity(items.len()),
+        };
+
+        if !items.is_empty() {
+            bvh.subdivide(0, 0, &mut items);
+        }
+
+        bvh
+    }
+
+    /// Distance to the nearest object of `scene`, which the hierarchy was built for, visiting
+    /// nodes the same way `evaluate_sdf_bvh` does
+    pub fn distance(&self, scene: &SceneDescriptorBuilder, point: Vec3) -> f32 {
+        let mut nearest = MAX_SIGNED_DISTANCE;
+        if self.primitives.is_empty() {
+            return nearest;
+        }
+
+        let bounds = |node: &BvhNode| Aabb {
+            min: node.min,
+            max: node.max,
+        };
+
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index as usize];
+            if bounds(node).distance(point) >= nearest {
+                continue;
+            }
+
+            if node.count > 0 {
+                let first = node.left_or_first as usize;
+                for &primitive in &self.primitives[first..first + node.count as usize] {
+                    nearest = nearest.min(primitive_distance(scene, primitive, point));
+                }
+                continue;
+            }
+
+            // The nearer child is pushed last so it is visited first
+            let (left, right) = (node.left_or_first, node.left_or_first + 1);
+            let left_distance = bounds(&self.nodes[left as usize]).distance(point);
+            let right_distance = bounds(&self.nodes[right as usize]).distance(point);
+            match left_distance < right_distance {
+                true => stack.extend([right, left]),
+                false => stack.extend([left, right]),
+            }
+        }
+
+        nearest
+    }
+
+    /// Splits `items` under the node at `node_index`, which is `depth` below the root. Nodes at
+    /// `MAX_DEPTH` become leaves however many items they hold.
+    fn subdivide(&mut self, node_index: usize, depth: usize, items: &mut [(u32, Aabb)]) {
+        let bounds = items
+            .iter()
+            .fold(Aabb::EMPTY, |bounds, (_, item)| bounds.union(*item));
+
+        if items.len() <= LEAF_SIZE || depth >= MAX_DEPTH {
+            self.nodes[node_index] = BvhNode {
+                min: bounds.min,
+                left_or_first: self.primitives.len() as u32,
+                max: bounds.max,
+                count: items.len() as u32,
+            };
+            self.primitives.extend(items.iter().map(|(index, _)| index));
+            return;
+        }
+
+        // Median split along the longest axis of the centroids
+        let centroid_bounds = items
+            .iter()
+            .fold(Aabb::EMPTY, |bounds, (_, item)| bounds.grow(item.center()));
+        let extent = centroid_bounds.max - centroid_bounds.min;
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        };
+
+        let middle = items.len() / 2;
+        items.select_nth_unstable_by(middle, |(_, a), (_, b)| {
+            a.center()[axis].total_cmp(&b.center()[axis])
+        });
+
+        let left_index = self.nodes.len();
+        self.nodes.push(BvhNode::zeroed());
+        self.nodes.push(BvhNode::zeroed());
+
+        self.nodes[node_index] = BvhNode {
+            min: bounds.min,
+            left_or_first: left_index as u32,
+            max: bounds.max,
+            count: 0,
+        };
+
+        let (left, right) = items.split_at_mut(middle);
+        self.subdivide(left_index, depth + 1, left);
+        self.subdivide(left_index + 1, depth + 1, right);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{make_benchmark_scene, make_operator_scene};
+
+    /// Points spread over a cube of side `size` about the origin, from a fixed seed
+    fn random_points(count: usize, size: f32) -> Vec<Vec3> {
+        let mut state = 0x2545_f491_u32;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32 - 0.5
+        };
+        (0..count)
+            .map(|_| Vec3::new(next(), next(), next()) * size)
+            .collect()
+    }
+
+    fn depth(bvh: &Bvh, node_index: usize) -> usize {
+        let node = &bvh.nodes[node_index];
+        match node.count {
+            0 => {
+                let left = node.left_or_first as usize;
+                1 + depth(bvh, left).max(depth(bvh, left + 1))
+            }
+            _ => 0,
+        }
+    }
+
+    #[test]
+    fn distance_matches_brute_force() {
+        let (scene, _) = make_benchmark_scene(200);
+        let bvh = Bvh::build(&scene);
+
+        for point in random_points(500, 20.0) {
+            let expected = scene.distance(point);
+            let distance = bvh.distance(&scene, point);
+            assert!(
+                (distance - expected).abs() < 1e-5,
+                "{distance} rather than {expected} at {point}"
+            );
+        }
+    }
+
+    #[test]
+    fn distance_is_bounded_with_shapes() {
+        let (scene, _) = make_operator_scene();
+        let bvh = Bvh::build(&scene);
+        let primitives: Vec<u32> = (0..scene.spheres.len() as u32)
+            .chain((0..scene.cuboids.len() as u32).map(|index| index | CUBOID_FLAG))
+            .chain((0..scene.shapes.len() as u32).map(|index| index | SHAPE_FLAG))
+            .collect();
+
+        // Displaced shapes underestimate their distance, even below that of their bounds, so
+        // skipping them by their bounds can only give a nearer bound than the brute force one
+        for point in random_points(500, 8.0) {
+            let distance = bvh.distance(&scene, point);
+            let bound = primitives
+                .iter()
+                .map(|&primitive| {
+                    let index = (primitive & !(CUBOID_FLAG | SHAPE_FLAG)) as usize;
+                    let bounds = if primitive & CUBOID_FLAG != 0 {
+                        scene.cuboids[index].bounds()
+                    } else if primitive & SHAPE_FLAG != 0 {
+                        scene.shapes[index].bounds(&scene)
+                    } else {
+                        scene.spheres[index].bounds()
+                    };
+                    primitive_distance(&scene, primitive, point).max(bounds.distance(point))
+                })
+                .fold(MAX_SIGNED_DISTANCE, f32::min);
+
+            assert!(distance >= scene.distance(point) - 1e-5);
+            assert!(
+                distance <= bound + 1e-5,
+                "{distance} beyond {bound} at {point}"
+            );
+        }
+    }
+
+    #[test]
+    fn empty_scene_is_far_away() {
+        let scene = SceneDescriptorBuilder::default();
+        let bvh = Bvh::build(&scene);
+
+        assert_eq!(bvh.distance(&scene, Vec3::ZERO), MAX_SIGNED_DISTANCE);
+    }
+
+    #[test]
+    fn every_primitive_is_in_one_leaf() {
+        let (scene, _) = make_benchmark_scene(200);
+        let bvh = Bvh::build(&scene);
+
+        let mut primitives = bvh.primitives.clone();
+        primitives.sort_unstable();
+        primitives.dedup();
+        assert_eq!(primitives.len(), 200 + 1);
+        assert_eq!(bvh.primitives.len(), primitives.len());
+        assert!(depth(&bvh, 0) <= MAX_DEPTH);
+    }
+
+    #[test]
+    fn nodes_at_max_depth_are_leaves() {
+        let mut items: Vec<(u32, Aabb)> = (0..10)
+            .map(|index| {
+                let center = Vec3::X * index as f32;
+                (
+                    index,
+                    Aabb::from_local(Mat4::from_translation(-center), Vec3::ONE),
+                )
+            })
+            .collect();
+        let mut bvh = Bvh {
+            nodes: vec![BvhNode::zeroed()],
+            primitives: Vec::new(),
+        };
+        bvh.subdivide(0, MAX_DEPTH, &mut items);
+
+        assert_eq!(bvh.nodes.len(), 1);
+        assert_eq!(bvh.nodes[0].count, 10);
+        assert_eq!(bvh.nodes[0].min, Vec3::new(-1.0, -1.0, -1.0));
+        assert_eq!(bvh.nodes[0].max, Vec3::new(10.0, 1.0, 1.0));
+    }
+}
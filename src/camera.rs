@@ -24,4 +24,17 @@ impl Camera {
             clip_far,
         }
     }
+
+    /// Turns the camera to face `target`, with no roll
+    pub fn look_at(self, target: Vec3) -> Self {
+        let forward = (target - self.position).normalize_or(Vec3::Z);
+        let pitch = forward.y.clamp(-1.0, 1.0).asin();
+        let yaw = (-forward.x).atan2(forward.z);
+
+        // Same composition as the interactive controls, pitch applied after yaw
+        Self {
+            orientation: Quat::from_rotation_x(pitch) * Quat::from_rotation_y(yaw),
+            ..self
+        }
+    }
 }
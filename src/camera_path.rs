@@ -0,0 +1,38 @@
+use std::f32::consts::TAU;
+
+use glam::{quat, vec3, Vec3};
+
+use crate::camera::Camera;
+
+/// A closed flythrough circling a point, so benchmark runs see the same views every time
+pub struct CameraPath {
+    pub center: Vec3,
+    pub radius: f32,
+    pub height: f32,
+    /// Vertical travel over the loop, above and below `height`
+    pub bob: f32,
+}
+
+impl CameraPath {
+    pub fn orbit(center: Vec3, radius: f32, height: f32) -> Self {
+        Self {
+            center,
+            radius,
+            height,
+            bob: height * 0.5,
+        }
+    }
+
+    /// The camera `t` of the way around the loop, wrapping every whole number
+    pub fn camera_at(&self, t: f32) -> Camera {
+        let angle = t.fract() * TAU;
+        let position = self.center
+            + vec3(
+                angle.sin() * self.radius,
+                self.height + (angle * 2.0).sin() * self.bob,
+                -angle.cos() * self.radius,
+            );
+
+        Camera::new(0.5, position, quat(0.0, 0.0, 0.0, 1.0), 0.001, 1000.0).look_at(self.center)
+    }
+}
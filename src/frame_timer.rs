@@ -4,73 +4,236 @@ use std::{
     time::{Duration, Instant},
 };
 
+/// GPU time of each labelled pass in a frame
+pub type PassTimings = Vec<(&'static str, Duration)>;
+
 pub struct FrameTimer {
     buffer_size: usize,
     frames: VecDeque<Duration>,
+    cpu_times: VecDeque<Duration>,
+    gpu_frames: VecDeque<PassTimings>,
     last_frame: Instant,
 }
 
-pub struct FrameStats {
-    samples: usize,
-    total_frame_time: Duration,
-    min_frame_time: Duration,
-    max_frame_time: Duration,
+/// Distribution of a set of durations
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TimeStats {
+    pub mean: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
 }
 
-impl From<&Duration> for FrameStats {
-    fn from(value: &Duration) -> Self {
+impl TimeStats {
+    pub fn from_samples(samples: impl IntoIterator<Item = Duration>) -> Self {
+        let mut sorted: Vec<Duration> = samples.into_iter().collect();
+        if sorted.is_empty() {
+            return Self::default();
+        }
+        sorted.sort_unstable();
+
+        // Nearest rank percentile
+        let percentile = |p: f32| {
+            let rank = (p * sorted.len() as f32).ceil() as usize;
+            sorted[rank.clamp(1, sorted.len()) - 1]
+        };
+
         Self {
-            samples: 1,
-            total_frame_time: *value,
-            min_frame_time: *value,
-            max_frame_time: *value,
+            mean: sorted.iter().sum::<Duration>() / sorted.len() as u32,
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            p50: percentile(0.50),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
         }
     }
 }
 
+impl Display for TimeStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ms = |duration: Duration| duration.as_secs_f32() * 1000.0;
+        write!(
+            f,
+            "{:.2}ms (p50: {:.2}ms, p95: {:.2}ms, p99: {:.2}ms, min: {:.2}ms, max: {:.2}ms)",
+            ms(self.mean),
+            ms(self.p50),
+            ms(self.p95),
+            ms(self.p99),
+            ms(self.min),
+            ms(self.max)
+        )
+    }
+}
+
+pub struct FrameStats {
+    pub samples: usize,
+    /// Wall time between frames
+    pub frame_time: TimeStats,
+    /// CPU time spent preparing and submitting each frame
+    pub cpu_time: TimeStats,
+    /// Sum of the timed GPU passes, absent without timestamp query support
+    pub gpu_time: Option<TimeStats>,
+    pub passes: Vec<(&'static str, TimeStats)>,
+}
+
 impl FrameTimer {
     pub fn new(buffer_size: usize) -> Self {
         let buffer_size = buffer_size.max(1);
         Self {
             buffer_size,
             frames: VecDeque::with_capacity(buffer_size),
+            cpu_times: VecDeque::with_capacity(buffer_size),
+            gpu_frames: VecDeque::with_capacity(buffer_size),
             last_frame: Instant::now(),
         }
     }
 
-    pub fn mark_frame(&mut self) -> FrameStats {
+    /// Records the end of a frame which took `cpu_time` to prepare, along with the GPU timings
+    /// of the latest frame read back, if any.
+    pub fn mark_frame(
+        &mut self,
+        cpu_time: Duration,
+        gpu_timings: Option<PassTimings>,
+    ) -> FrameStats {
         let now = Instant::now();
-        while self.frames.len() >= self.buffer_size {
-            self.frames.pop_back();
+        push_bounded(&mut self.frames, now - self.last_frame, self.buffer_size);
+        push_bounded(&mut self.cpu_times, cpu_time, self.buffer_size);
+        if let Some(timings) = gpu_timings {
+            push_bounded(&mut self.gpu_frames, timings, self.buffer_size);
         }
-        self.frames.push_front(now - self.last_frame);
         self.last_frame = now;
 
         self.stats()
     }
 
     fn stats(&self) -> FrameStats {
-        self.frames
-            .iter()
-            .map(|it| it.into())
-            .reduce(|a: FrameStats, b: FrameStats| FrameStats {
-                samples: a.samples + b.samples,
-                total_frame_time: a.total_frame_time + b.total_frame_time,
-                min_frame_time: a.min_frame_time.min(b.min_frame_time),
-                max_frame_time: a.max_frame_time.max(b.max_frame_time),
+        let gpu_time = (!self.gpu_frames.is_empty()).then(|| {
+            TimeStats::from_samples(
+                self.gpu_frames
+                    .iter()
+                    .map(|passes| passes.iter().map(|(_, time)| *time).sum()),
+            )
+        });
+
+        let mut labels: Vec<&'static str> = Vec::new();
+        for (label, _) in self.gpu_frames.iter().flatten() {
+            if !labels.contains(label) {
+                labels.push(label);
+            }
+        }
+
+        let passes = labels
+            .into_iter()
+            .map(|label| {
+                let samples = self.gpu_frames.iter().filter_map(|passes| {
+                    passes
+                        .iter()
+                        .find(|(existing, _)| *existing == label)
+                        .map(|(_, time)| *time)
+                });
+                (label, TimeStats::from_samples(samples))
             })
-            .unwrap()
+            .collect();
+
+        FrameStats {
+            samples: self.frames.len(),
+            frame_time: TimeStats::from_samples(self.frames.iter().copied()),
+            cpu_time: TimeStats::from_samples(self.cpu_times.iter().copied()),
+            gpu_time,
+            passes,
+        }
+    }
+}
+
+fn push_bounded<T>(queue: &mut VecDeque<T>, value: T, size: usize) {
+    while queue.len() >= size {
+        queue.pop_back();
+    }
+    queue.push_front(value);
+}
+
+impl FrameStats {
+    pub fn average_frame_time(&self) -> Duration {
+        self.frame_time.mean
     }
 }
 
 impl Display for FrameStats {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
+        writeln!(
             f,
-            "Frame time: {}ms (min: {}ms, max: {}ms)",
-            self.total_frame_time.as_millis() / self.samples as u128,
-            self.min_frame_time.as_millis(),
-            self.max_frame_time.as_millis()
-        )
+            "Frame time over {} frames: {}",
+            self.samples, self.frame_time
+        )?;
+        write!(f, "  CPU: {}", self.cpu_time)?;
+        if let Some(gpu_time) = &self.gpu_time {
+            write!(f, "\n  GPU: {}", gpu_time)?;
+        }
+        for (label, time) in &self.passes {
+            write!(f, "\n    {label}: {time}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(milliseconds: u64) -> Duration {
+        Duration::from_millis(milliseconds)
+    }
+
+    #[test]
+    fn percentiles_use_nearest_rank() {
+        // Shuffled so sorting is needed
+        let stats = TimeStats::from_samples((1..=100).map(|i| ms(i * 37 % 101)));
+
+        assert_eq!(stats.min, ms(1));
+        assert_eq!(stats.max, ms(100));
+        assert_eq!(stats.p50, ms(50));
+        assert_eq!(stats.p95, ms(95));
+        assert_eq!(stats.p99, ms(99));
+        assert_eq!(stats.mean, Duration::from_micros(50_500));
+    }
+
+    #[test]
+    fn few_samples_round_up() {
+        let stats = TimeStats::from_samples([ms(4), ms(1), ms(3), ms(2)]);
+
+        assert_eq!(stats.p50, ms(2));
+        assert_eq!(stats.p95, ms(4));
+        assert_eq!(stats.p99, ms(4));
+
+        let single = TimeStats::from_samples([ms(7)]);
+        assert_eq!((single.min, single.p50, single.p99), (ms(7), ms(7), ms(7)));
+    }
+
+    #[test]
+    fn no_samples_are_zero() {
+        let stats = TimeStats::from_samples([]);
+
+        assert_eq!(stats.mean, Duration::ZERO);
+        assert_eq!(stats.p99, Duration::ZERO);
+    }
+
+    #[test]
+    fn timer_keeps_latest_frames_and_passes() {
+        let mut timer = FrameTimer::new(3);
+        for frame in 1..=5 {
+            timer.mark_frame(ms(frame), Some(vec![("Main Pass", ms(frame * 2))]));
+        }
+        let stats = timer.mark_frame(ms(6), None);
+
+        assert_eq!(stats.samples, 3);
+        assert_eq!(stats.cpu_time.min, ms(4));
+        assert_eq!(stats.cpu_time.max, ms(6));
+        // GPU timings lag behind, the last frame had none read back
+        let gpu_time = stats.gpu_time.unwrap();
+        assert_eq!((gpu_time.min, gpu_time.max), (ms(6), ms(10)));
+        assert_eq!(stats.passes.len(), 1);
+        assert_eq!(stats.passes[0].0, "Main Pass");
     }
 }
@@ -4,6 +4,8 @@ use glam::{vec2, vec3, Vec2, Vec3};
 use keyboard_state::KeyboardState;
 use winit::keyboard::{Key, NamedKey};
 
+use crate::render_settings::DebugView;
+
 pub struct Input {
     pub keyboard: KeyboardState,
     movement_speed: f32,
@@ -48,4 +50,30 @@ impl Input {
 
         (positive - negative) * self.sensitivity
     }
+
+    pub fn focus_adjustment(&self) -> f32 {
+        let positive = self.keyboard.is_down(Key::Character("x".into())) as u32 as f32;
+        let negative = self.keyboard.is_down(Key::Character("z".into())) as u32 as f32;
+
+        (positive - negative) * self.movement_speed
+    }
+
+    /// Debug view bound to a number key, 0 returns to the lit image
+    pub fn debug_view(&self, key: &Key) -> Option<DebugView> {
+        let Key::Character(character) = key else {
+            return None;
+        };
+
+        match character.as_str() {
+            "0" => Some(DebugView::None),
+            "1" => Some(DebugView::Steps),
+            "2" => Some(DebugView::Normals),
+            "3" => Some(DebugView::Depth),
+            "4" => Some(DebugView::AmbientOcclusion),
+            "5" => Some(DebugView::Shadow),
+            "6" => Some(DebugView::ObjectId),
+            "7" => Some(DebugView::MaterialId),
+            _ => None,
+        }
+    }
 }
@@ -1,20 +1,39 @@
 #![feature(async_closure)]
 
+mod adaptive_quality;
+pub mod animate;
 pub mod app;
+pub mod bake;
+pub mod bench;
+mod bvh;
 mod camera;
+mod camera_path;
 mod frame_timer;
 mod input;
 mod light_buffers;
+pub mod mesh_export;
+mod render_settings;
 mod scene_buffer;
 mod scene_descriptor;
+mod screenshot;
+pub mod timeline;
 mod wgpu_context;
 
-use glam::vec3;
+pub use wgpu_context::RenderPath;
+
+use std::f32::consts::TAU;
+
+use glam::{uvec3, vec3, BVec3, Quat};
 use light_buffers::{Light, LightBufferBuilder, LightBuffers};
 use scene_descriptor::{
-    objects::{Cuboid, Sphere},
+    graph::SceneGraph,
+    media::{Fog, Medium},
+    noise::{Displacement, Noise},
+    objects::{Cuboid, Shape, Sphere},
+    operator::Operator,
     SceneDescriptorBuilder,
 };
+use timeline::{Channel, Interpolation, Target, Timeline, Track};
 
 pub fn make_scene() -> (SceneDescriptorBuilder, LightBuffers) {
     let mut scene_buffer = SceneDescriptorBuilder::default();
@@ -49,3 +68,224 @@ pub fn make_scene() -> (SceneDescriptorBuilder, LightBuffers) {
 
     (scene_buffer, light_buffer.build())
 }
+
+/// The scene from `make_scene` in height fog, with a block of scattering medium beside the
+/// sphere for the lights to shine through
+pub fn make_media_scene() -> (SceneDescriptorBuilder, LightBuffers) {
+    let (mut scene_buffer, lights) = make_scene();
+
+    scene_buffer.fog = Fog::new(vec3(1.0, 1.0, 1.0), 0.01, 0.5, -1.0);
+
+    scene_buffer.add_medium(
+        Medium::new(vec3(1.0, 1.0, 1.0), 0.4)
+            .translate(vec3(-3.0, 0.0, 0.0))
+            .with_scattering(vec3(0.9, 0.9, 1.0)),
+    );
+
+    (scene_buffer, lights)
+}
+
+/// Animation of the scene from `make_scene`, which is defined in code rather than loaded from a
+/// file, so its timeline lives alongside it
+pub fn make_timeline() -> Timeline {
+    let bob = Track::new()
+        .key(0.0, vec3(0.0, 0.0, 0.0), Interpolation::EASE_IN_OUT)
+        .key(2.0, vec3(0.0, 1.0, 0.0), Interpolation::EASE_IN_OUT)
+        .key(4.0, vec3(0.0, 0.0, 0.0), Interpolation::Linear);
+
+    let spin = (0..=3).fold(Track::new(), |track, step| {
+        let angle = step as f32 * TAU / 3.0;
+        track.key(
+            step as f32 * 4.0 / 3.0,
+            Quat::from_rotation_y(angle),
+            Interpolation::Slerp,
+        )
+    });
+
+    let pulse = Track::new()
+        .key(0.0, 1.0, Interpolation::Linear)
+        .key(1.0, 2.0, Interpolation::Linear)
+        .key(2.0, 1.0, Interpolation::Step)
+        .key(3.0, 0.5, Interpolation::Linear)
+        .key(4.0, 1.0, Interpolation::Linear);
+
+    let shift = Track::new()
+        .key(0.0, vec3(1.0, 0.2, 0.2), Interpolation::Linear)
+        .key(2.0, vec3(1.0, 0.8, 0.2), Interpolation::Linear)
+        .key(4.0, vec3(1.0, 0.2, 0.2), Interpolation::Linear);
+
+    Timeline::new()
+        .track(Target::Sphere(0), Channel::Position(bob))
+        .track(Target::Cuboid(0), Channel::Rotation(spin))
+        .track(Target::Light(0), Channel::Intensity(pulse))
+        .track(Target::Light(1), Channel::Colour(shift))
+        .looping()
+}
+
+/// One of the fractal shapes by name, sized to about the default scene's sphere
+pub fn make_fractal(name: &str) -> Option<Shape> {
+    match name {
+        "mandelbulb" => Some(Shape::mandelbulb(8.0)),
+        "menger" => Some(Shape::menger_sponge(3.0)),
+        "mandelbox" => Some(Shape::mandelbox(2.0).with_size(0.2)),
+        "sierpinski" => Some(Shape::sierpinski(2.0)),
+        _ => None,
+    }
+}
+
+/// The scene from `make_scene` with a showcase of the domain operators in front of it
+pub fn make_operator_scene() -> (SceneDescriptorBuilder, LightBuffers) {
+    let (mut scene_buffer, lights) = make_scene();
+
+    // A bar twisting a little over a turn from bottom to top
+    let twisted = scene_buffer.operate(Operator::Twist(1.2), Cuboid::new(vec3(0.3, 1.0, 0.3)));
+    scene_buffer
+        .shapes
+        .push(twisted.translate(vec3(-2.5, 0.0, 2.0)));
+
+    // A capsule shell, a hollowed sphere stretched along x
+    let shell = scene_buffer.operate(Operator::Onion(0.05), Sphere::new(0.4));
+    let capsule = scene_buffer.operate(Operator::Elongate(vec3(0.5, 0.0, 0.0)), shell);
+    scene_buffer
+        .shapes
+        .push(capsule.translate(vec3(0.0, 0.5, 2.5)));
+
+    // Eight cubes in a ring above the sphere
+    let ring = scene_buffer.operate(
+        Operator::Polar(8),
+        Cuboid::new(vec3(0.15, 0.15, 0.15)).translate(vec3(-1.2, 0.0, 0.0)),
+    );
+    scene_buffer
+        .shapes
+        .push(ring.translate(vec3(0.0, -2.0, 0.0)));
+
+    // A row of eleven marbles along the floor
+    let row = scene_buffer.operate(
+        Operator::RepeatLimited {
+            period: vec3(0.6, 0.0, 0.0),
+            count: uvec3(5, 0, 0),
+        },
+        Sphere::new(0.2),
+    );
+    scene_buffer.shapes.push(row.translate(vec3(0.0, 0.8, 3.0)));
+
+    // A bar bent into an arch, mirrored so both ends match
+    let arch = scene_buffer.operate(Operator::Bend(0.6), Cuboid::new(vec3(1.2, 0.1, 0.1)));
+    let arch = scene_buffer.operate(Operator::Mirror(BVec3::new(true, false, false)), arch);
+    scene_buffer
+        .shapes
+        .push(arch.translate(vec3(2.5, -0.5, 2.0)));
+
+    // A rock, a sphere roughened by a few octaves of gradient noise
+    let rock = scene_buffer.operate(
+        Operator::Displace(Displacement::new(Noise::Gradient, 0.2, 1.5).with_octaves(5)),
+        Sphere::new(0.5),
+    );
+    scene_buffer
+        .shapes
+        .push(rock.translate(vec3(1.5, 0.6, 1.0)));
+
+    // A cube covered in cellular bumps
+    let pebbled = scene_buffer.operate(
+        Operator::Displace(Displacement::new(Noise::Worley, 0.03, 6.0)),
+        Cuboid::new(vec3(0.3, 0.3, 0.3)),
+    );
+    scene_buffer
+        .shapes
+        .push(pebbled.translate(vec3(-1.0, 0.7, 1.5)));
+
+    (scene_buffer, lights)
+}
+
+/// The scene from `make_scene` ringed by copies of a table shared through a scene graph
+pub fn make_graph_scene() -> (SceneDescriptorBuilder, LightBuffers) {
+    let (mut scene_buffer, lights) = make_scene();
+    let mut graph = SceneGraph::default();
+
+    // A table standing on its origin, with a vase stretched up from a sphere on top
+    let table = graph.subtree("table");
+    graph.add(
+        table,
+        "top",
+        Cuboid::new(vec3(0.6, 0.05, 0.4)).translate(vec3(0.0, -0.5, 0.0)),
+    );
+    for (index, corner) in [(0.5, 0.3), (-0.5, 0.3), (0.5, -0.3), (-0.5, -0.3)]
+        .into_iter()
+        .enumerate()
+    {
+        graph.add(
+            table,
+            format!("leg {index}"),
+            Cuboid::new(vec3(0.04, 0.25, 0.04)).translate(vec3(-corner.0, -0.25, -corner.1)),
+        );
+    }
+    let vase = graph.add(table, "vase", Sphere::new(0.08));
+    graph
+        .node_mut(vase)
+        .translate(vec3(0.0, -0.71, 0.0))
+        .scale(vec3(1.0, 2.0, 1.0));
+
+    let tables = graph.group(graph.root(), "tables");
+    for index in 0..3 {
+        let angle = index as f32 * TAU / 3.0;
+        let copy = graph.instance(tables, format!("table {index}"), table);
+        graph
+            .node_mut(copy)
+            .translate(-vec3(angle.cos(), 0.0, angle.sin()) * 3.5)
+            .rotate(vec3(0.0, angle, 0.0));
+    }
+
+    // The last table is a smaller copy, still standing on its origin
+    if let Some(small) = graph.find("table 2") {
+        graph.node_mut(small).scale_uniform(0.7);
+    }
+
+    // Moving the group moves every table onto the floor
+    if let Some(tables) = graph.find("tables") {
+        graph.node_mut(tables).translate(vec3(0.0, 1.0, 0.0));
+    }
+
+    graph.resolve(&mut scene_buffer);
+    (scene_buffer, lights)
+}
+
+/// A grid of `count` small spheres and cuboids over a floor, for measuring how rendering cost
+/// scales with object count.
+pub fn make_benchmark_scene(count: usize) -> (SceneDescriptorBuilder, LightBuffers) {
+    let mut scene_buffer = SceneDescriptorBuilder::default();
+
+    let side = (count as f32).sqrt().ceil() as usize;
+    let offset = side as f32 * 0.5;
+
+    scene_buffer
+        .cuboids
+        .push(Cuboid::new(vec3(offset + 1.0, 1.0, offset + 1.0)).translate(vec3(0.0, 2.0, 0.0)));
+
+    for i in 0..count {
+        let position = vec3((i % side) as f32 - offset, 0.0, (i / side) as f32 - offset);
+
+        // Object transforms map world space to local space
+        if i % 2 == 0 {
+            scene_buffer
+                .spheres
+                .push(Sphere::new(0.3).translate(-position));
+        } else {
+            scene_buffer.cuboids.push(
+                Cuboid::new(vec3(0.25, 0.25, 0.25))
+                    .translate(-position)
+                    .rotate(vec3(0.0, i as f32, 0.0)),
+            );
+        }
+    }
+
+    let mut light_buffer = LightBufferBuilder::new();
+
+    light_buffer.add(Light {
+        position: vec3(0.0, 6.0, 0.0),
+        radius: 0.2,
+        color: vec3(1.0, 1.0, 1.0),
+        enabled: 1,
+    });
+
+    (scene_buffer, light_buffer.build())
+}
@@ -24,6 +24,14 @@ pub struct LightBuffers {
     lights: [u8; MAX_LIGHTS * LIGHT_SIZE],
     lights_length: u32,
 }
+
+impl LightBuffers {
+    /// Every light slot, including unused ones which stay disabled
+    pub fn lights_mut(&mut self) -> &mut [Light] {
+        bytemuck::cast_slice_mut(&mut self.lights)
+    }
+}
+
 pub struct LightBufferBuilder {
     lights: [Light; MAX_LIGHTS],
     index: usize,
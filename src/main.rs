@@ -1,8 +1,194 @@
+use std::path::Path;
+
+use glam::{vec2, vec3, Vec2};
 use pollster::FutureExt;
-use ray_marcher::app::App;
+use ray_marcher::{
+    animate::{self, AnimateOptions},
+    app::App,
+    bake::{self, BakeMethod, BakeOptions},
+    bench::{self, BenchOptions},
+    make_benchmark_scene, make_fractal, make_graph_scene, make_media_scene, make_operator_scene,
+    make_scene, make_timeline,
+    mesh_export::{self, Aabb, ExportOptions, Method},
+    timeline::Timeline,
+    RenderPath,
+};
 use winit::{event_loop::EventLoop, window::Window};
 
+const BENCHMARK_OBJECTS: usize = 4096;
+const MESH_RESOLUTION: u32 = 64;
+/// Footprint and height range terrain is given by default, covering the default scene's floor
+const TERRAIN_SIZE: Vec2 = vec2(20.0, 20.0);
+const TERRAIN_HEIGHTS: Vec2 = vec2(-1.5, 0.0);
+
 pub fn main() {
+    env_logger::init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let has_flag = |flag: &str| args.iter().any(|arg| arg == flag);
+    let flag_value = |flag: &str| {
+        args.iter()
+            .position(|arg| arg == flag)
+            .and_then(|index| args.get(index + 1))
+    };
+    // Values of every occurrence of a flag that can be repeated, in order
+    let flag_values = |flag: &str| -> Vec<&String> {
+        args.windows(2)
+            .filter(|pair| pair[0] == flag)
+            .map(|pair| &pair[1])
+            .collect()
+    };
+
+    let (scene_name, mut scene, mut timeline) = if has_flag("--benchmark-scene") {
+        (
+            "benchmark",
+            make_benchmark_scene(BENCHMARK_OBJECTS),
+            Timeline::new(),
+        )
+    } else if has_flag("--operator-scene") {
+        ("operators", make_operator_scene(), Timeline::new())
+    } else if has_flag("--graph-scene") {
+        ("graph", make_graph_scene(), Timeline::new())
+    } else if has_flag("--media-scene") {
+        ("media", make_media_scene(), Timeline::new())
+    } else {
+        ("default", make_scene(), make_timeline())
+    };
+
+    // Timelines from a file replace the scene's own, saving writes whichever is in use
+    if let Some(path) = flag_value("--timeline") {
+        timeline = Timeline::load(Path::new(path)).expect("Failed to load timeline");
+    }
+    if let Some(path) = flag_value("--save-timeline") {
+        timeline
+            .save(Path::new(path))
+            .expect("Failed to save timeline");
+    }
+
+    // Meshes are added in their own coordinates, alongside the scene's objects
+    if let Some(path) = flag_value("--mesh") {
+        let resolution = flag_value("--mesh-resolution").map_or(MESH_RESOLUTION, |resolution| {
+            resolution.parse().expect("Invalid mesh resolution")
+        });
+        let shape = scene
+            .0
+            .import_mesh(Path::new(path), resolution)
+            .expect("Failed to import mesh");
+        scene.0.shapes.push(shape);
+    }
+
+    // Grayscale images become terrain, across the floor unless sized otherwise and repeated
+    // when tiled
+    for terrain in flag_values("--terrain") {
+        let (path, size, heights, tiling) = parse_terrain(terrain);
+        let shape = scene
+            .0
+            .load_heightmap(Path::new(path), size, heights)
+            .expect("Failed to load heightmap");
+        scene.0.shapes.push(shape.with_tiling(Vec2::splat(tiling)));
+    }
+
+    // Fractals stand on the far side of the sphere from the cube
+    if let Some(name) = flag_value("--fractal") {
+        let shape = make_fractal(name).unwrap_or_else(|| panic!("Unknown fractal {name}"));
+        scene.0.shapes.push(shape.translate(vec3(-3.0, 0.0, 0.0)));
+    }
+
+    // Stands every object in for a single baked grid, which suits scenes of many small objects
+    if has_flag("--bake") {
+        let defaults = BakeOptions::default();
+        let options = BakeOptions {
+            method: match flag_value("--bake-method").map(String::as_str) {
+                Some("compute") => BakeMethod::Compute,
+                Some("cpu") | None => BakeMethod::Cpu,
+                Some(method) => panic!("Unknown bake method {method}"),
+            },
+            resolution: flag_value("--bake-resolution").map_or(defaults.resolution, |resolution| {
+                resolution.parse().expect("Invalid bake resolution")
+            }),
+            ..defaults
+        };
+        bake::bake_scene(&mut scene.0, &options);
+    }
+
+    let render_path = match has_flag("--compute") {
+        true => RenderPath::Compute,
+        false => RenderPath::Fragment,
+    };
+
+    if args.first().is_some_and(|command| command == "bench") {
+        let defaults = BenchOptions::default();
+        let options = BenchOptions {
+            scene_name: scene_name.into(),
+            frames: flag_value("--frames").map_or(defaults.frames, |frames| {
+                frames
+                    .parse()
+                    .ok()
+                    .filter(|&frames| frames > 0)
+                    .expect("Benchmarks measure at least one frame, --frames must be positive")
+            }),
+            warmup_frames: flag_value("--warmup").map_or(defaults.warmup_frames, |frames| {
+                frames.parse().expect("Invalid warmup frame count")
+            }),
+            size: flag_value("--size").map_or(defaults.size, |size| parse_size(size)),
+            render_path,
+            output: flag_value("--output").map_or(defaults.output, Into::into),
+        };
+
+        bench::run(&options, scene).expect("Failed to write benchmark report");
+        return;
+    }
+
+    if args.first().is_some_and(|command| command == "animate") {
+        let defaults = AnimateOptions::default();
+        let options = AnimateOptions {
+            scene_name: scene_name.into(),
+            frames: flag_value("--frames").map_or(defaults.frames, |frames| {
+                frames.parse().expect("Invalid frame count")
+            }),
+            timestep: flag_value("--fps").map_or(defaults.timestep, |fps| {
+                1.0 / fps.parse::<f32>().expect("Invalid frame rate")
+            }),
+            size: flag_value("--size").map_or(defaults.size, |size| parse_size(size)),
+            render_path,
+            output: match has_flag("--no-images") {
+                true => None,
+                false => {
+                    flag_value("--output").map_or(defaults.output, |output| Some(output.into()))
+                }
+            },
+            pipe: flag_value("--pipe").cloned(),
+        };
+
+        animate::run(&options, scene, &timeline).expect("Failed to export animation");
+        return;
+    }
+
+    if args.first().is_some_and(|command| command == "export-mesh") {
+        let defaults = ExportOptions::default();
+        let options = ExportOptions {
+            method: match flag_value("--method").map(String::as_str) {
+                Some("dual-contouring") => Method::DualContouring,
+                Some("marching-cubes") | None => Method::MarchingCubes,
+                Some(method) => panic!("Unknown meshing method {method}"),
+            },
+            resolution: flag_value("--resolution").map_or(defaults.resolution, |resolution| {
+                resolution.parse().expect("Invalid resolution")
+            }),
+            tolerance: flag_value("--tolerance")
+                .map(|tolerance| tolerance.parse().expect("Invalid tolerance")),
+            bounds: flag_value("--bounds").map(|bounds| parse_bounds(bounds)),
+            output: flag_value("--output").map_or(defaults.output, Into::into),
+        };
+
+        // Animated scenes are meshed as they are at the given time
+        let time = flag_value("--time").map_or(0.0, |time| time.parse().expect("Invalid time"));
+        let (scene, _) = timeline.evaluate(time, &scene);
+
+        mesh_export::run(&options, &scene).expect("Failed to write mesh");
+        return;
+    }
+
     let event_loop: EventLoop<()> = EventLoop::new().unwrap();
 
     #[allow(unused_mut)]
@@ -11,9 +197,80 @@ pub fn main() {
     #[allow(deprecated)]
     let window = event_loop.create_window(window_attributes).unwrap();
 
-    env_logger::init();
+    let mut app = App::create(&window, scene, render_path).block_on();
 
-    let mut app = App::create(&window).block_on();
+    if let Some(frames) = flag_value("--report-interval") {
+        app.set_report_interval(frames.parse().expect("Invalid report interval"));
+    }
+    if let Some(scale) = flag_value("--screenshot-scale") {
+        app.set_screenshot_scale(scale.parse().expect("Invalid screenshot scale"));
+    }
+    app.set_scene_name(scene_name);
+    app.set_timeline(timeline);
+    if has_flag("--play") {
+        app.play();
+    }
+    if has_flag("--adaptive-quality") {
+        app.enable_adaptive_quality();
+    }
 
     app.run(event_loop).block_on();
 }
+
+/// Parses a `WIDTHxHEIGHT` size
+fn parse_size(size: &str) -> (u32, u32) {
+    size.split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .expect("Sizes are given as WIDTHxHEIGHT")
+}
+
+/// Parses two numbers split by `separator`, panicking with `usage` otherwise
+fn parse_vec2(value: &str, separator: char, usage: &str) -> Vec2 {
+    value
+        .split_once(separator)
+        .and_then(|(x, y)| Some(vec2(x.trim().parse().ok()?, y.trim().parse().ok()?)))
+        .unwrap_or_else(|| panic!("{usage}"))
+}
+
+/// Parses `PATH[:WIDTHxDEPTH[:LOW,HIGH[:TILING]]]`, leaving out any settings not given. Only
+/// colons after the file name split it, so Windows drive letters stay part of the path.
+fn parse_terrain(terrain: &str) -> (&str, Vec2, Vec2, f32) {
+    let name_start = terrain.rfind(['/', '\\']).map_or(0, |index| index + 1);
+    let (path, settings) = match terrain[name_start..].find(':') {
+        Some(index) => terrain.split_at(name_start + index),
+        None => (terrain, ""),
+    };
+
+    let mut settings = settings.split(':').skip(1);
+    let size = settings.next().map_or(TERRAIN_SIZE, |size| {
+        parse_vec2(size, 'x', "Terrain sizes are given as WIDTHxDEPTH")
+    });
+    let heights = settings.next().map_or(TERRAIN_HEIGHTS, |heights| {
+        parse_vec2(heights, ',', "Terrain heights are given as LOW,HIGH")
+    });
+    let tiling = settings.next().map_or(1.0, |tiling| {
+        tiling.parse().expect("Invalid terrain tiling")
+    });
+    assert!(
+        settings.next().is_none(),
+        "Terrain is given as PATH[:WIDTHxDEPTH[:LOW,HIGH[:TILING]]]"
+    );
+
+    (path, size, heights, tiling)
+}
+
+/// Parses `MINX,MINY,MINZ,MAXX,MAXY,MAXZ` bounds
+fn parse_bounds(bounds: &str) -> Aabb {
+    let values: Vec<f32> = bounds
+        .split(',')
+        .map(|value| value.trim().parse().expect("Invalid bounds"))
+        .collect();
+    let [min_x, min_y, min_z, max_x, max_y, max_z] = values[..] else {
+        panic!("Bounds are given as MINX,MINY,MINZ,MAXX,MAXY,MAXZ");
+    };
+
+    Aabb {
+        min: vec3(min_x, min_y, min_z),
+        max: vec3(max_x, max_y, max_z),
+    }
+}
@@ -0,0 +1,90 @@
+pub mod dual_contouring;
+pub mod marching_cubes;
+pub mod mesh;
+
+use std::{io, path::PathBuf, time::Instant};
+
+use glam::Vec3;
+
+use crate::scene_descriptor::SceneDescriptorBuilder;
+use mesh::Mesh;
+
+pub use crate::bvh::Aabb;
+
+/// How the surface is turned into triangles
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    /// Uniform triangles everywhere, rounding off sharp edges
+    MarchingCubes,
+    /// Vertices placed on the surface's features, merging cells across flat areas
+    DualContouring,
+}
+
+pub struct ExportOptions {
+    pub method: Method,
+    /// Cells along the longest side of the bounds, the finest cells for dual contouring
+    pub resolution: u32,
+    /// Distance dual contouring vertices may stray from the surface when merging cells, a
+    /// tenth of a cell when `None`
+    pub tolerance: Option<f32>,
+    /// Region meshed, the scene's bounds when `None`
+    pub bounds: Option<Aabb>,
+    /// Written as OBJ, STL or PLY depending on the extension
+    pub output: PathBuf,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            method: Method::MarchingCubes,
+            resolution: 128,
+            tolerance: None,
+            bounds: None,
+            output: "scene.obj".into(),
+        }
+    }
+}
+
+/// Meshes the surface of the scene from a CPU evaluation of its distance field and writes it out.
+pub fn run(options: &ExportOptions, scene: &SceneDescriptorBuilder) -> io::Result<Mesh> {
+    let bounds = options.bounds.unwrap_or_else(|| {
+        // Padded so surfaces on the edge of the scene bounds are closed off
+        let bounds = scene.bounds();
+        let padding = Vec3::splat((bounds.max - bounds.min).max_element() * 0.02);
+        Aabb {
+            min: bounds.min - padding,
+            max: bounds.max + padding,
+        }
+    });
+
+    log::info!(
+        "Meshing {:?} to {:?} with {:?} at a resolution of {}",
+        bounds.min,
+        bounds.max,
+        options.method,
+        options.resolution
+    );
+
+    let start = Instant::now();
+    let distance = |point| scene.distance(point);
+    let mesh = match options.method {
+        Method::MarchingCubes => marching_cubes::extract(distance, bounds, options.resolution),
+        Method::DualContouring => {
+            let cell_size = (bounds.max - bounds.min).max_element() / options.resolution as f32;
+            let tolerance = options.tolerance.unwrap_or(cell_size * 0.1);
+            dual_contouring::extract(distance, bounds, options.resolution, tolerance)
+        }
+    };
+
+    log::info!(
+        "Extracted {} vertices and {} triangles in {:.2}s",
+        mesh.positions.len(),
+        mesh.triangles.len(),
+        start.elapsed().as_secs_f32()
+    );
+
+    mesh.write(&options.output)?;
+    log::info!("Wrote mesh to {}", options.output.display());
+
+    Ok(mesh)
+}
@@ -0,0 +1,592 @@
+use std::collections::{HashMap, HashSet};
+
+use glam::{DVec3, IVec3, UVec3, Vec3};
+
+use super::{
+    marching_cubes::{corner_offset, gradient, surface_count, EDGES},
+    mesh::Mesh,
+};
+use crate::bvh::Aabb;
+
+/// Eigenvalues below this fraction of the largest are treated as zero when solving a QEF, so
+/// flat and edge cells stay near their mass point along the unconstrained directions
+const SINGULAR_FRACTION: f64 = 0.1;
+/// False position steps locating where the surface crosses a cell edge
+const CROSSING_STEPS: usize = 4;
+
+/// Quadratic error function of a set of tangent planes, the summed squared distance to them.
+/// Accumulated in double precision as the error is a difference of large sums.
+#[derive(Clone, Copy, Debug, Default)]
+struct Qef {
+    /// Upper triangle of AᵀA, xx xy xz yy yz zz
+    ata: [f64; 6],
+    atb: DVec3,
+    btb: f64,
+    mass: DVec3,
+    count: u32,
+}
+
+impl Qef {
+    fn add_plane(&mut self, point: Vec3, normal: Vec3) {
+        let (point, normal) = (point.as_dvec3(), normal.as_dvec3());
+        let [x, y, z] = normal.to_array();
+        for (entry, value) in self
+            .ata
+            .iter_mut()
+            .zip([x * x, x * y, x * z, y * y, y * z, z * z])
+        {
+            *entry += value;
+        }
+
+        let b = normal.dot(point);
+        self.atb += normal * b;
+        self.btb += b * b;
+        self.mass += point;
+        self.count += 1;
+    }
+
+    fn merge(self, other: Self) -> Self {
+        let mut ata = self.ata;
+        for (entry, value) in ata.iter_mut().zip(other.ata) {
+            *entry += value;
+        }
+
+        Self {
+            ata,
+            atb: self.atb + other.atb,
+            btb: self.btb + other.btb,
+            mass: self.mass + other.mass,
+            count: self.count + other.count,
+        }
+    }
+
+    fn matrix(&self) -> [[f64; 3]; 3] {
+        let [xx, xy, xz, yy, yz, zz] = self.ata;
+        [[xx, xy, xz], [xy, yy, yz], [xz, yz, zz]]
+    }
+
+    fn multiply(&self, vector: DVec3) -> DVec3 {
+        let [x, y, z] = self.matrix().map(|row| DVec3::from_array(row).dot(vector));
+        DVec3::new(x, y, z)
+    }
+
+    fn mass_point(&self) -> Vec3 {
+        (self.mass / self.count.max(1) as f64).as_vec3()
+    }
+
+    /// Mean squared distance from `point` to the planes
+    fn error(&self, point: Vec3) -> f64 {
+        let point = point.as_dvec3();
+        let error = point.dot(self.multiply(point)) - 2.0 * point.dot(self.atb) + self.btb;
+        error.max(0.0) / self.count.max(1) as f64
+    }
+
+    /// The point minimising the error, solved with a truncated pseudo inverse about the mass
+    /// point so underdetermined directions don't run off
+    fn solve(&self) -> Vec3 {
+        let mass = self.mass / self.count.max(1) as f64;
+        let (values, vectors) = symmetric_eigen(self.matrix());
+        let largest = values
+            .iter()
+            .fold(0.0f64, |largest, value| largest.max(*value));
+        let residual = self.atb - self.multiply(mass);
+
+        let mut point = mass;
+        for (value, vector) in values.into_iter().zip(vectors) {
+            if value > largest * SINGULAR_FRACTION {
+                point += vector * (vector.dot(residual) / value);
+            }
+        }
+        point.as_vec3()
+    }
+}
+
+/// Eigenvalues and eigenvectors of a symmetric matrix by cyclic Jacobi rotations
+fn symmetric_eigen(matrix: [[f64; 3]; 3]) -> ([f64; 3], [DVec3; 3]) {
+    let mut a = matrix;
+    let mut v = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+    for _ in 0..8 {
+        for (p, q) in [(0, 1), (0, 2), (1, 2)] {
+            if a[p][q].abs() < 1e-12 {
+                continue;
+            }
+
+            let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+            let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+            let c = 1.0 / (t * t + 1.0).sqrt();
+            let s = t * c;
+
+            for row in a.iter_mut().chain(v.iter_mut()) {
+                let (kp, kq) = (row[p], row[q]);
+                row[p] = c * kp - s * kq;
+                row[q] = s * kp + c * kq;
+            }
+            let (row_p, row_q) = (a[p], a[q]);
+            a[p] = std::array::from_fn(|k| c * row_p[k] - s * row_q[k]);
+            a[q] = std::array::from_fn(|k| s * row_p[k] + c * row_q[k]);
+        }
+    }
+
+    let vectors = [0, 1, 2].map(|column| DVec3::new(v[0][column], v[1][column], v[2][column]));
+    ([a[0][0], a[1][1], a[2][2]], vectors)
+}
+
+struct Leaf {
+    qef: Qef,
+    position: Vec3,
+    /// Inside corners, as in marching cubes
+    case: usize,
+    vertex: u32,
+}
+
+enum Node {
+    Empty,
+    Leaf(Leaf),
+    Branch(Box<[Node; 8]>),
+}
+
+/// Cells are addressed by their minimum corner in units of the finest cells
+struct Octree<'a, F> {
+    distance: &'a F,
+    origin: Vec3,
+    cell_size: f32,
+    /// Largest mean squared distance of a collapsed cell's vertex from its planes
+    max_error: f64,
+    samples: HashMap<UVec3, f32>,
+}
+
+impl<F: Fn(Vec3) -> f32> Octree<'_, F> {
+    fn position(&self, point: UVec3) -> Vec3 {
+        self.origin + point.as_vec3() * self.cell_size
+    }
+
+    fn sample(&mut self, point: UVec3) -> f32 {
+        let (distance, position) = (self.distance, self.position(point));
+        *self
+            .samples
+            .entry(point)
+            .or_insert_with(|| distance(position))
+    }
+
+    fn build(&mut self, min: UVec3, size: u32) -> Node {
+        // The distance bounds how far away the surface can be, so most cells are skipped
+        // without sampling their corners
+        let extent = size as f32 * self.cell_size;
+        let center = self.position(min) + Vec3::splat(extent * 0.5);
+        if (self.distance)(center).abs() > extent * 3.0f32.sqrt() * 0.5 {
+            return Node::Empty;
+        }
+
+        if size == 1 {
+            return self.finest_leaf(min);
+        }
+
+        let half = size / 2;
+        let children: [Node; 8] =
+            std::array::from_fn(|child| self.build(min + corner_offset(child) * half, half));
+
+        if children.iter().all(|child| matches!(child, Node::Empty)) {
+            return Node::Empty;
+        }
+        self.collapse(min, size, children)
+    }
+
+    fn finest_leaf(&mut self, min: UVec3) -> Node {
+        let values: [f32; 8] =
+            std::array::from_fn(|corner| self.sample(min + corner_offset(corner)));
+        let case = inside_case(&values);
+        if case == 0 || case == 255 {
+            return Node::Empty;
+        }
+
+        let mut qef = Qef::default();
+        for (a, b) in EDGES {
+            if (values[a] < 0.0) != (values[b] < 0.0) {
+                let point = self.crossing(
+                    self.position(min + corner_offset(a)),
+                    self.position(min + corner_offset(b)),
+                    values[a],
+                    values[b],
+                );
+                qef.add_plane(point, gradient(self.distance, point, self.cell_size * 0.01));
+            }
+        }
+
+        Node::Leaf(Leaf {
+            position: self.vertex_position(&qef, min, 1),
+            qef,
+            case,
+            vertex: u32::MAX,
+        })
+    }
+
+    /// Where the surface crosses the segment between two points of opposite sign
+    fn crossing(&self, mut a: Vec3, mut b: Vec3, mut value_a: f32, mut value_b: f32) -> Vec3 {
+        let mut point = a;
+        for _ in 0..CROSSING_STEPS {
+            point = a.lerp(b, value_a / (value_a - value_b));
+            let value = (self.distance)(point);
+            if value == 0.0 {
+                break;
+            }
+            if (value < 0.0) == (value_a < 0.0) {
+                (a, value_a) = (point, value);
+            } else {
+                (b, value_b) = (point, value);
+            }
+        }
+        point
+    }
+
+    /// The QEF minimiser, or the mass point when that lies outside the cell
+    fn vertex_position(&self, qef: &Qef, min: UVec3, size: u32) -> Vec3 {
+        let position = qef.solve();
+        let low = self.position(min);
+        let high = self.position(min + UVec3::splat(size));
+        let margin = Vec3::splat(self.cell_size * 1e-3);
+        match position.cmpge(low - margin).all() && position.cmple(high + margin).all() {
+            true => position,
+            false => qef.mass_point(),
+        }
+    }
+
+    /// Replaces eight leaves by one when their surface is simple enough to be represented by a
+    /// single vertex without changing its topology
+    fn collapse(&mut self, min: UVec3, size: u32, children: [Node; 8]) -> Node {
+        let collapsible = children.iter().all(|child| match child {
+            Node::Empty => true,
+            Node::Leaf(leaf) => surface_count(leaf.case) <= 1,
+            Node::Branch(_) => false,
+        });
+        if !collapsible {
+            return Node::Branch(Box::new(children));
+        }
+
+        // Signs on a 3x3x3 lattice over the cell, the corners of all eight children
+        let half = size / 2;
+        let mut inside = [[[false; 3]; 3]; 3];
+        for (x, plane) in inside.iter_mut().enumerate() {
+            for (y, row) in plane.iter_mut().enumerate() {
+                for (z, sign) in row.iter_mut().enumerate() {
+                    let offset = UVec3::new(x as u32, y as u32, z as u32) * half;
+                    *sign = self.sample(min + offset) < 0.0;
+                }
+            }
+        }
+
+        let corners: [f32; 8] = std::array::from_fn(|corner| {
+            let [x, y, z] = (corner_offset(corner) * 2)
+                .to_array()
+                .map(|axis| axis as usize);
+            if inside[x][y][z] {
+                -1.0
+            } else {
+                1.0
+            }
+        });
+        let case = inside_case(&corners);
+        if surface_count(case) != 1 {
+            return Node::Branch(Box::new(children));
+        }
+
+        // The sign in the middle of each edge, face and the cell itself has to match one of
+        // the corners of that edge, face or cell, or the coarse surface would lose features
+        for x in 0..3 {
+            for y in 0..3 {
+                for z in 0..3 {
+                    let choices = |axis: usize| match axis {
+                        1 => vec![0, 2],
+                        axis => vec![axis],
+                    };
+                    let matched = choices(x).into_iter().any(|cx| {
+                        choices(y).into_iter().any(|cy| {
+                            choices(z)
+                                .into_iter()
+                                .any(|cz| inside[cx][cy][cz] == inside[x][y][z])
+                        })
+                    });
+                    if !matched {
+                        return Node::Branch(Box::new(children));
+                    }
+                }
+            }
+        }
+
+        let qef = children
+            .iter()
+            .filter_map(|child| match child {
+                Node::Leaf(leaf) => Some(leaf.qef),
+                _ => None,
+            })
+            .fold(Qef::default(), Qef::merge);
+
+        let position = self.vertex_position(&qef, min, size);
+        if qef.error(position) > self.max_error {
+            return Node::Branch(Box::new(children));
+        }
+
+        Node::Leaf(Leaf {
+            qef,
+            position,
+            case,
+            vertex: u32::MAX,
+        })
+    }
+}
+
+fn inside_case(values: &[f32; 8]) -> usize {
+    (0..8)
+        .filter(|&corner| values[corner] < 0.0)
+        .fold(0, |case, corner| case | 1 << corner)
+}
+
+/// Numbers every leaf's vertex and records the region each covers
+fn assign_vertices(
+    node: &mut Node,
+    min: UVec3,
+    size: u32,
+    mesh: &mut Mesh,
+    normal: &impl Fn(Vec3) -> Vec3,
+    regions: &mut Vec<(UVec3, u32)>,
+) {
+    match node {
+        Node::Empty => {}
+        Node::Leaf(leaf) => {
+            leaf.vertex = mesh.positions.len() as u32;
+            mesh.positions.push(leaf.position);
+            mesh.normals.push(normal(leaf.position));
+            regions.push((min, size));
+        }
+        Node::Branch(children) => {
+            for (child, node) in children.iter_mut().enumerate() {
+                let half = size / 2;
+                assign_vertices(
+                    node,
+                    min + corner_offset(child) * half,
+                    half,
+                    mesh,
+                    normal,
+                    regions,
+                );
+            }
+        }
+    }
+}
+
+/// Vertex of the leaf containing the finest cell `cell`
+fn leaf_vertex(root: &Node, size: u32, cell: UVec3) -> Option<u32> {
+    let (mut node, mut min, mut size) = (root, UVec3::ZERO, size);
+    loop {
+        match node {
+            Node::Empty => return None,
+            Node::Leaf(leaf) => return Some(leaf.vertex),
+            Node::Branch(children) => {
+                size /= 2;
+                let child = cell.cmpge(min + UVec3::splat(size)).bitmask() as usize;
+                min += corner_offset(child) * size;
+                node = &children[child];
+            }
+        }
+    }
+}
+
+/// Extracts the zero isosurface of `distance` within `bounds` by dual contouring, with
+/// `resolution` of the finest cells along the longest side. Cells are merged up an octree while
+/// their vertex stays within `tolerance` of the surface's tangent planes, so flat areas use few
+/// triangles while edges and corners stay sharp.
+pub fn extract(
+    distance: impl Fn(Vec3) -> f32,
+    bounds: Aabb,
+    resolution: u32,
+    tolerance: f32,
+) -> Mesh {
+    let extent = bounds.max - bounds.min;
+    let cell_size = extent.max_element() / resolution.max(1) as f32;
+    let size = resolution.max(1).next_power_of_two();
+
+    let mut octree = Octree {
+        distance: &distance,
+        origin: bounds.min,
+        cell_size,
+        max_error: (tolerance as f64).powi(2),
+        samples: HashMap::new(),
+    };
+    let mut root = octree.build(UVec3::ZERO, size);
+
+    let mut mesh = Mesh::default();
+    let mut regions = Vec::new();
+    let normal = |point| gradient(&distance, point, cell_size * 0.1);
+    assign_vertices(
+        &mut root,
+        UVec3::ZERO,
+        size,
+        &mut mesh,
+        &normal,
+        &mut regions,
+    );
+
+    // Every finest edge the surface crosses lies within a leaf that has a vertex
+    let mut crossings = HashSet::new();
+    for (min, region_size) in regions {
+        for offset in 0..region_size.pow(3) {
+            let cell = min
+                + UVec3::new(
+                    offset % region_size,
+                    offset / region_size % region_size,
+                    offset / (region_size * region_size),
+                );
+            for (a, b) in EDGES {
+                let (start, end) = (cell + corner_offset(a), cell + corner_offset(b));
+                if (octree.sample(start) < 0.0) != (octree.sample(end) < 0.0) {
+                    crossings.insert((start, (a ^ b).trailing_zeros() as usize));
+                }
+            }
+        }
+    }
+
+    // Each crossing edge joins the vertices of the four cells around it. Neighbouring cells in
+    // the same collapsed leaf repeat a vertex, leaving a triangle or nothing.
+    for (start, axis) in crossings {
+        let (b, c) = ((axis + 1) % 3, (axis + 2) % 3);
+        let step = |axis: usize| IVec3::from_array(std::array::from_fn(|i| (i == axis) as i32));
+        let start_cell = start.as_ivec3();
+
+        let cells = [
+            start_cell - step(b) - step(c),
+            start_cell - step(c),
+            start_cell,
+            start_cell - step(b),
+        ];
+        if cells
+            .iter()
+            .any(|cell| cell.min_element() < 0 || cell.max_element() >= size as i32)
+        {
+            continue;
+        }
+
+        let Some(mut quad) = cells
+            .iter()
+            .map(|cell| leaf_vertex(&root, size, cell.as_uvec3()))
+            .collect::<Option<Vec<u32>>>()
+        else {
+            continue;
+        };
+
+        // Counter clockwise around the edge's axis, facing away from the inside end
+        if octree.sample(start) >= 0.0 {
+            quad.reverse();
+        }
+
+        // Split along the diagonal that doesn't join a repeated vertex to itself
+        let triangles = match quad[0] == quad[2] {
+            true => [[quad[1], quad[2], quad[3]], [quad[1], quad[3], quad[0]]],
+            false => [[quad[0], quad[1], quad[2]], [quad[0], quad[2], quad[3]]],
+        };
+        for triangle in triangles {
+            if triangle[0] != triangle[1]
+                && triangle[1] != triangle[2]
+                && triangle[2] != triangle[0]
+            {
+                mesh.triangles.push(triangle);
+            }
+        }
+    }
+
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sphere(point: Vec3) -> f32 {
+        point.length() - 1.0
+    }
+
+    fn cube(point: Vec3) -> f32 {
+        let q = point.abs() - Vec3::splat(0.45);
+        q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+    }
+
+    fn bounds() -> Aabb {
+        Aabb {
+            min: Vec3::splat(-1.5),
+            max: Vec3::splat(1.5),
+        }
+    }
+
+    #[test]
+    fn qef_solves_to_corner_of_three_planes() {
+        let mut qef = Qef::default();
+        qef.add_plane(Vec3::new(0.3, 0.0, 0.0), Vec3::X);
+        qef.add_plane(Vec3::new(0.0, 0.2, 0.0), Vec3::Y);
+        qef.add_plane(Vec3::new(0.0, 0.0, 0.1), Vec3::Z);
+
+        let corner = qef.solve();
+        assert!(corner.distance(Vec3::new(0.3, 0.2, 0.1)) < 1e-5);
+        assert!(qef.error(corner) < 1e-10);
+    }
+
+    #[test]
+    fn qef_keeps_free_directions_at_mass_point() {
+        let mut qef = Qef::default();
+        qef.add_plane(Vec3::new(0.0, 0.5, 0.0), Vec3::Y);
+        qef.add_plane(Vec3::new(1.0, 0.5, 1.0), Vec3::Y);
+
+        assert!(qef.solve().distance(Vec3::new(0.5, 0.5, 0.5)) < 1e-5);
+    }
+
+    #[test]
+    fn eigen_decomposition_reconstructs_matrix() {
+        let matrix = [[4.0, 1.0, -2.0], [1.0, 3.0, 0.5], [-2.0, 0.5, 1.0]];
+        let (values, vectors) = symmetric_eigen(matrix);
+
+        for (row, expected) in matrix.iter().enumerate() {
+            for (column, expected) in expected.iter().enumerate() {
+                let value: f64 = (0..3)
+                    .map(|k| values[k] * vectors[k][row] * vectors[k][column])
+                    .sum();
+                assert!((value - expected).abs() < 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn sphere_is_watertight() {
+        let mesh = extract(sphere, bounds(), 16, 0.01);
+
+        assert!(!mesh.triangles.is_empty());
+        assert!(mesh.is_watertight());
+        for position in &mesh.positions {
+            assert!(
+                sphere(*position).abs() < 0.02,
+                "{position} is off the surface"
+            );
+        }
+    }
+
+    #[test]
+    fn cube_keeps_sharp_corners() {
+        let mesh = extract(cube, bounds(), 16, 0.01);
+
+        assert!(mesh.is_watertight());
+        for corner in 0..8 {
+            let corner = (corner_offset(corner).as_vec3() * 2.0 - 1.0) * 0.45;
+            assert!(
+                mesh.positions
+                    .iter()
+                    .any(|position| position.distance(corner) < 1e-3),
+                "No vertex at {corner}"
+            );
+        }
+    }
+
+    #[test]
+    fn flat_faces_collapse() {
+        // Each face merges into a single quad, as flat faces have no error however large
+        let mesh = extract(cube, bounds(), 16, 0.01);
+
+        assert_eq!(mesh.triangles.len(), 12);
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+
+use glam::{UVec3, Vec3};
+use lazy_static::lazy_static;
+use rayon::prelude::*;
+
+use super::mesh::Mesh;
+use crate::bvh::Aabb;
+
+/// Cube corners are numbered by their offset bits, x in bit 0, y in bit 1 and z in bit 2
+pub const EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (2, 3),
+    (4, 5),
+    (6, 7),
+    (0, 2),
+    (1, 3),
+    (4, 6),
+    (5, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+/// Corners of each face, counter clockwise seen from outside the cube
+const FACES: [[usize; 4]; 6] = [
+    [0, 4, 6, 2],
+    [1, 3, 7, 5],
+    [0, 1, 5, 4],
+    [2, 6, 7, 3],
+    [0, 2, 3, 1],
+    [4, 5, 7, 6],
+];
+
+lazy_static! {
+    /// Triangles of each of the 256 inside/outside cases, as edge indices
+    static ref CASES: Vec<Vec<[usize; 3]>> = (0..256).map(triangulate_case).collect();
+}
+
+fn edge_index(a: usize, b: usize) -> usize {
+    EDGES
+        .iter()
+        .position(|&edge| edge == (a.min(b), a.max(b)))
+        .expect("Face corners are joined by cube edges")
+}
+
+/// The closed loops of crossing edges the surface makes around the cube in a case. The surface
+/// crosses each face along segments between its crossing edges, which join up around the cube.
+/// Faces with two diagonally opposite inside corners always separate them, so neighbouring
+/// cubes agree and meshes are watertight.
+fn case_loops(case: usize) -> Vec<Vec<usize>> {
+    let inside = |corner: usize| case & (1 << corner) != 0;
+
+    // Each crossing edge starts exactly one segment, on one of its two faces
+    let mut next_edge = [None; 12];
+    for face in FACES {
+        for start in 0..4 {
+            let (outside_corner, inside_corner) = (face[start], face[(start + 1) % 4]);
+            if inside(outside_corner) || !inside(inside_corner) {
+                continue;
+            }
+
+            // Walk the run of inside corners counter clockwise to the edge the surface leaves by
+            let mut end = (start + 1) % 4;
+            while inside(face[(end + 1) % 4]) {
+                end = (end + 1) % 4;
+            }
+
+            // Oriented with the inside corners on the left seen from outside the cube
+            let entry = edge_index(outside_corner, inside_corner);
+            let exit = edge_index(face[end], face[(end + 1) % 4]);
+            next_edge[exit] = Some(entry);
+        }
+    }
+
+    let mut loops = Vec::new();
+    let mut visited = [false; 12];
+    for first in 0..12 {
+        if visited[first] || next_edge[first].is_none() {
+            continue;
+        }
+
+        let mut boundary = vec![first];
+        visited[first] = true;
+        let mut edge = first;
+        while let Some(next) = next_edge[edge].filter(|&next| next != first) {
+            boundary.push(next);
+            visited[next] = true;
+            edge = next;
+        }
+        loops.push(boundary);
+    }
+
+    loops
+}
+
+/// Fans each loop of a case into triangles, built here rather than taken from the usual hand
+/// written table
+fn triangulate_case(case: usize) -> Vec<[usize; 3]> {
+    let mut triangles = Vec::new();
+    for boundary in case_loops(case) {
+        // The loops run clockwise seen from outside the surface, so the fan is reversed
+        for pair in boundary[1..].windows(2) {
+            triangles.push([boundary[0], pair[1], pair[0]]);
+        }
+    }
+    triangles
+}
+
+/// Separate pieces of surface in a cube with the given inside corners
+pub fn surface_count(case: usize) -> usize {
+    case_loops(case).len()
+}
+
+/// Cube corner with the offset bits of `corner`, x in bit 0, y in bit 1 and z in bit 2
+pub fn corner_offset(corner: usize) -> UVec3 {
+    UVec3::new(
+        corner as u32 & 1,
+        (corner as u32 >> 1) & 1,
+        (corner as u32 >> 2) & 1,
+    )
+}
+
+/// Signed distances sampled at the corners of a regular grid of cubic cells
+struct SampleGrid {
+    origin: Vec3,
+    cell_size: f32,
+    cells: UVec3,
+    values: Vec<f32>,
+}
+
+impl SampleGrid {
+    fn sample(distance: &(impl Fn(Vec3) -> f32 + Sync), bounds: Aabb, resolution: u32) -> Self {
+        let extent = bounds.max - bounds.min;
+        let cell_size = extent.max_element() / resolution.max(1) as f32;
+        let cells = (extent / cell_size).ceil().as_uvec3().max(UVec3::ONE);
+
+        let mut grid = Self {
+            origin: bounds.min,
+            cell_size,
+            cells,
+            values: Vec::new(),
+        };
+
+        let points = (cells + UVec3::ONE).element_product() as usize;
+        grid.values = (0..points)
+            .into_par_iter()
+            .map(|index| distance(grid.position(grid.point(index))))
+            .collect();
+        grid
+    }
+
+    fn index(&self, point: UVec3) -> usize {
+        let size = self.cells + UVec3::ONE;
+        (point.x + size.x * (point.y + size.y * point.z)) as usize
+    }
+
+    fn point(&self, index: usize) -> UVec3 {
+        let size = self.cells + UVec3::ONE;
+        let (width, height) = (size.x as usize, size.y as usize);
+        UVec3::new(
+            (index % width) as u32,
+            (index / width % height) as u32,
+            (index / (width * height)) as u32,
+        )
+    }
+
+    fn position(&self, point: UVec3) -> Vec3 {
+        self.origin + point.as_vec3() * self.cell_size
+    }
+}
+
+/// Normalised gradient of the distance field by central differences
+pub fn gradient(distance: &impl Fn(Vec3) -> f32, point: Vec3, step: f32) -> Vec3 {
+    let axis = |offset: Vec3| distance(point + offset) - distance(point - offset);
+    Vec3::new(
+        axis(Vec3::X * step),
+        axis(Vec3::Y * step),
+        axis(Vec3::Z * step),
+    )
+    .normalize_or_zero()
+}
+
+/// Extracts the zero isosurface of `distance` within `bounds`, using `resolution` cells along
+/// the longest side of the bounds. Vertices on shared cell edges are shared between triangles.
+pub fn extract(distance: impl Fn(Vec3) -> f32 + Sync, bounds: Aabb, resolution: u32) -> Mesh {
+    let grid = SampleGrid::sample(&distance, bounds, resolution);
+    let mut mesh = Mesh::default();
+
+    // Vertices keyed by the grid point at the low end of their edge and the edge's axis
+    let mut vertices: HashMap<(usize, u32), u32> = HashMap::new();
+
+    for z in 0..grid.cells.z {
+        for y in 0..grid.cells.y {
+            for x in 0..grid.cells.x {
+                let cell = UVec3::new(x, y, z);
+                let corners: [UVec3; 8] =
+                    std::array::from_fn(|corner| cell + corner_offset(corner));
+                let values = corners.map(|corner| grid.values[grid.index(corner)]);
+
+                let case = (0..8)
+                    .filter(|&corner| values[corner] < 0.0)
+                    .fold(0, |case, corner| case | 1 << corner);
+
+                for triangle in &CASES[case] {
+                    let indices = triangle.map(|edge| {
+                        let (a, b) = EDGES[edge];
+                        let key = (grid.index(corners[a]), (a ^ b).trailing_zeros());
+
+                        *vertices.entry(key).or_insert_with(|| {
+                            let t = values[a] / (values[a] - values[b]);
+                            let position =
+                                grid.position(corners[a]).lerp(grid.position(corners[b]), t);
+
+                            mesh.positions.push(position);
+                            mesh.normals
+                                .push(gradient(&distance, position, grid.cell_size * 0.1));
+                            mesh.positions.len() as u32 - 1
+                        })
+                    });
+                    mesh.triangles.push(indices);
+                }
+            }
+        }
+    }
+
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sphere(point: Vec3) -> f32 {
+        point.length() - 1.0
+    }
+
+    fn bounds() -> Aabb {
+        Aabb {
+            min: Vec3::splat(-1.5),
+            max: Vec3::splat(1.5),
+        }
+    }
+
+    #[test]
+    fn cases_match_their_surfaces() {
+        // No surface entirely inside or outside, one piece for a single corner
+        assert!(CASES[0].is_empty());
+        assert!(CASES[255].is_empty());
+        assert_eq!(CASES[1].len(), 1);
+        assert_eq!(surface_count(1), 1);
+        // Opposite corners of a face are separate pieces
+        assert_eq!(surface_count(0b1001), 2);
+    }
+
+    #[test]
+    fn sphere_is_watertight() {
+        let mesh = extract(sphere, bounds(), 16);
+
+        assert!(!mesh.triangles.is_empty());
+        assert!(mesh.is_watertight());
+    }
+
+    #[test]
+    fn sphere_vertices_lie_on_surface() {
+        let mesh = extract(sphere, bounds(), 16);
+
+        assert_eq!(mesh.positions.len(), mesh.normals.len());
+        for (position, normal) in mesh.positions.iter().zip(&mesh.normals) {
+            assert!(
+                sphere(*position).abs() < 0.01,
+                "{position} is off the surface"
+            );
+            assert!(normal.dot(position.normalize()) > 0.99);
+        }
+    }
+
+    #[test]
+    fn sphere_winds_outwards() {
+        let mesh = extract(sphere, bounds(), 16);
+
+        for triangle in &mesh.triangles {
+            let [a, b, c] = triangle.map(|index| mesh.positions[index as usize]);
+            assert!((b - a).cross(c - a).dot(a + b + c) > 0.0);
+        }
+    }
+}
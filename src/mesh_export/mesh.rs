@@ -0,0 +1,340 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use glam::Vec3;
+
+/// An indexed triangle mesh with per vertex normals, wound counter clockwise seen from outside
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse_vector<'a>(mut words: impl Iterator<Item = &'a str>) -> io::Result<Vec3> {
+    let mut component = || {
+        words
+            .next()
+            .and_then(|word| word.parse::<f32>().ok())
+            .ok_or_else(|| invalid_data("Expected three numbers".into()))
+    };
+    Ok(Vec3::new(component()?, component()?, component()?))
+}
+
+impl Mesh {
+    /// Reads OBJ or STL depending on the extension. Normals are left empty, only positions and
+    /// triangles are read.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let bytes = fs::read(path)?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("stl") => Self::read_stl(&bytes),
+            Some("obj") => Self::read_obj(&String::from_utf8_lossy(&bytes)),
+            _ => Err(invalid_data(format!(
+                "Unsupported mesh format {}",
+                path.display()
+            ))),
+        }
+    }
+
+    /// Polygons are fanned into triangles, texture coordinates, normals and groups are ignored
+    pub fn read_obj(text: &str) -> io::Result<Self> {
+        let mut mesh = Self::default();
+
+        for line in text.lines() {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("v") => mesh.positions.push(parse_vector(words)?),
+                Some("f") => {
+                    let count = mesh.positions.len() as i64;
+                    let indices = words
+                        .map(|word| {
+                            // Negative indices count back from the latest vertex
+                            let index = word.split('/').next().and_then(|index| index.parse().ok());
+                            match index {
+                                Some(index) if (1..=count).contains(&index) => Ok(index as u32 - 1),
+                                Some(index) if (-count..0).contains(&index) => {
+                                    Ok((count + index) as u32)
+                                }
+                                _ => Err(invalid_data(format!("Invalid face vertex {word}"))),
+                            }
+                        })
+                        .collect::<io::Result<Vec<u32>>>()?;
+
+                    for pair in indices.get(1..).unwrap_or_default().windows(2) {
+                        mesh.triangles.push([indices[0], pair[0], pair[1]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(mesh)
+    }
+
+    /// Binary or ASCII STL. Triangles share no vertices, as STL stores each one separately.
+    pub fn read_stl(bytes: &[u8]) -> io::Result<Self> {
+        let mut mesh = Self::default();
+
+        let binary_count = bytes
+            .get(80..84)
+            .map(|count| u32::from_le_bytes(count.try_into().unwrap()) as usize);
+
+        // ASCII files can start with "solid" too, so the size is what tells them apart
+        if let Some(count) = binary_count.filter(|count| bytes.len() == 84 + count * 50) {
+            for triangle in bytes[84..].chunks_exact(50) {
+                let component = |index: usize| {
+                    let offset = 12 + index * 4;
+                    f32::from_le_bytes(triangle[offset..offset + 4].try_into().unwrap())
+                };
+                for corner in 0..3 {
+                    mesh.positions.push(Vec3::new(
+                        component(corner * 3),
+                        component(corner * 3 + 1),
+                        component(corner * 3 + 2),
+                    ));
+                }
+            }
+            mesh.triangles = (0..count as u32)
+                .map(|triangle| [0, 1, 2].map(|corner| triangle * 3 + corner))
+                .collect();
+            return Ok(mesh);
+        }
+
+        for line in String::from_utf8_lossy(bytes).lines() {
+            let mut words = line.split_whitespace();
+            if words.next() == Some("vertex") {
+                mesh.positions.push(parse_vector(words)?);
+            }
+        }
+        if mesh.positions.len() % 3 != 0 {
+            return Err(invalid_data("Facets must have three vertices".into()));
+        }
+        mesh.triangles = (0..mesh.positions.len() as u32 / 3)
+            .map(|triangle| [0, 1, 2].map(|corner| triangle * 3 + corner))
+            .collect();
+
+        Ok(mesh)
+    }
+
+    /// Writes OBJ, binary STL or binary PLY depending on the extension, OBJ when unknown
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("stl") => self.write_stl(&mut writer)?,
+            Some("ply") => self.write_ply(&mut writer)?,
+            _ => self.write_obj(&mut writer)?,
+        }
+        writer.flush()
+    }
+
+    pub fn write_obj(&self, writer: &mut impl Write) -> io::Result<()> {
+        for position in &self.positions {
+            writeln!(writer, "v {} {} {}", position.x, position.y, position.z)?;
+        }
+        for normal in &self.normals {
+            writeln!(writer, "vn {} {} {}", normal.x, normal.y, normal.z)?;
+        }
+
+        // OBJ indices start at one
+        for [a, b, c] in self
+            .triangles
+            .iter()
+            .map(|triangle| triangle.map(|index| index + 1))
+        {
+            writeln!(writer, "f {a}//{a} {b}//{b} {c}//{c}")?;
+        }
+        Ok(())
+    }
+
+    /// STL has no shared vertices or vertex normals, each triangle stores its face normal
+    pub fn write_stl(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&[0; 80])?;
+        writer.write_all(&(self.triangles.len() as u32).to_le_bytes())?;
+
+        for triangle in &self.triangles {
+            let [a, b, c] = triangle.map(|index| self.positions[index as usize]);
+            let normal = (b - a).cross(c - a).normalize_or_zero();
+
+            for vector in [normal, a, b, c] {
+                for component in vector.to_array() {
+                    writer.write_all(&component.to_le_bytes())?;
+                }
+            }
+            // Attribute byte count
+            writer.write_all(&[0; 2])?;
+        }
+        Ok(())
+    }
+
+    pub fn write_ply(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "ply")?;
+        writeln!(writer, "format binary_little_endian 1.0")?;
+        writeln!(writer, "element vertex {}", self.positions.len())?;
+        for property in ["x", "y", "z", "nx", "ny", "nz"] {
+            writeln!(writer, "property float {property}")?;
+        }
+        writeln!(writer, "element face {}", self.triangles.len())?;
+        writeln!(writer, "property list uchar uint vertex_indices")?;
+        writeln!(writer, "end_header")?;
+
+        for (position, normal) in self.positions.iter().zip(&self.normals) {
+            for component in position.to_array().into_iter().chain(normal.to_array()) {
+                writer.write_all(&component.to_le_bytes())?;
+            }
+        }
+
+        for triangle in &self.triangles {
+            writer.write_all(&[3])?;
+            for index in triangle {
+                writer.write_all(&index.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl Mesh {
+    /// Whether every edge is shared by exactly two triangles running along it in opposite
+    /// directions, so the surface is closed and consistently wound
+    pub fn is_watertight(&self) -> bool {
+        let mut edges = std::collections::HashMap::new();
+        for triangle in &self.triangles {
+            for corner in 0..3 {
+                let edge = (triangle[corner], triangle[(corner + 1) % 3]);
+                *edges.entry(edge).or_insert(0) += 1;
+            }
+        }
+        edges
+            .iter()
+            .all(|(&(a, b), &count)| count == 1 && edges.get(&(b, a)) == Some(&1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A single triangle facing +z
+    fn triangle() -> Mesh {
+        Mesh {
+            positions: vec![Vec3::ZERO, Vec3::X, Vec3::Y],
+            normals: vec![Vec3::Z; 3],
+            triangles: vec![[0, 1, 2]],
+        }
+    }
+
+    #[test]
+    fn obj_indices_start_at_one() {
+        let mut obj = Vec::new();
+        triangle().write_obj(&mut obj).unwrap();
+        let obj = String::from_utf8(obj).unwrap();
+
+        assert_eq!(obj.lines().filter(|line| line.starts_with("v ")).count(), 3);
+        assert_eq!(
+            obj.lines().filter(|line| line.starts_with("vn ")).count(),
+            3
+        );
+        assert!(obj.lines().any(|line| line == "f 1//1 2//2 3//3"));
+    }
+
+    #[test]
+    fn stl_stores_face_normals() {
+        let mut stl = Vec::new();
+        triangle().write_stl(&mut stl).unwrap();
+
+        assert_eq!(stl.len(), 84 + 50);
+        assert_eq!(u32::from_le_bytes(stl[80..84].try_into().unwrap()), 1);
+        let normal: Vec<f32> = stl[84..96]
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        assert_eq!(normal, [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn ply_header_counts_elements() {
+        let mut ply = Vec::new();
+        triangle().write_ply(&mut ply).unwrap();
+
+        let header_end = b"end_header\n";
+        let header_len = ply
+            .windows(header_end.len())
+            .position(|window| window == header_end)
+            .unwrap()
+            + header_end.len();
+        let header = std::str::from_utf8(&ply[..header_len]).unwrap();
+
+        assert!(header.contains("element vertex 3\n"));
+        assert!(header.contains("element face 1\n"));
+        // Six floats per vertex, then a count and three indices per face
+        assert_eq!(ply.len() - header_len, 3 * 6 * 4 + (1 + 3 * 4));
+    }
+
+    #[test]
+    fn obj_faces_fan_into_triangles() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nf 1/1/1 2/2/1 3/3/1 4/4/1\n";
+        let mesh = Mesh::read_obj(obj).unwrap();
+
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.triangles, [[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    fn obj_negative_indices_count_back() {
+        let mesh = Mesh::read_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\n").unwrap();
+
+        assert_eq!(mesh.triangles, [[0, 1, 2]]);
+    }
+
+    #[test]
+    fn obj_rejects_missing_vertices() {
+        assert!(Mesh::read_obj("v 0 0 0\nf 1 2 3\n").is_err());
+        assert!(Mesh::read_obj("v 0 0\n").is_err());
+    }
+
+    #[test]
+    fn obj_round_trips() {
+        let mut obj = Vec::new();
+        triangle().write_obj(&mut obj).unwrap();
+        let mesh = Mesh::read_obj(&String::from_utf8(obj).unwrap()).unwrap();
+
+        assert_eq!(mesh.positions, triangle().positions);
+        assert_eq!(mesh.triangles, triangle().triangles);
+    }
+
+    #[test]
+    fn binary_stl_round_trips() {
+        let mut stl = Vec::new();
+        triangle().write_stl(&mut stl).unwrap();
+        let mesh = Mesh::read_stl(&stl).unwrap();
+
+        assert_eq!(mesh.positions, triangle().positions);
+        assert_eq!(mesh.triangles, [[0, 1, 2]]);
+    }
+
+    #[test]
+    fn ascii_stl_reads_facets() {
+        let stl = "solid triangle
+            facet normal 0 0 1
+                outer loop
+                    vertex 0 0 0
+                    vertex 1 0 0
+                    vertex 0 1 0
+                endloop
+            endfacet
+            endsolid triangle";
+        let mesh = Mesh::read_stl(stl.as_bytes()).unwrap();
+
+        assert_eq!(mesh.positions, triangle().positions);
+        assert_eq!(mesh.triangles, [[0, 1, 2]]);
+        assert!(Mesh::read_stl(b"solid\nvertex 0 0 0\nendsolid").is_err());
+    }
+}
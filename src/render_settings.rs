@@ -0,0 +1,134 @@
+use bytemuck::{Pod, Zeroable};
+
+use crate::scene_descriptor::SceneDescriptorBuilder;
+
+pub const DEFAULT_MARCH_STEPS: u32 = 255;
+/// Fractal surfaces are approached in many short steps as rays graze their detail
+pub const FRACTAL_MARCH_STEPS: u32 = 512;
+
+/// How `surface_point` steps along a ray
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MarchStrategy {
+    /// Steps by exactly the distance to the nearest surface
+    Sphere = 0,
+    /// Steps by the distance scaled by `relaxation`, retreating when a step overshoots
+    OverRelaxed = 1,
+    /// Scales each step by a planar estimate of the surface, up to `relaxation`
+    Enhanced = 2,
+}
+
+impl MarchStrategy {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Sphere => Self::OverRelaxed,
+            Self::OverRelaxed => Self::Enhanced,
+            Self::Enhanced => Self::Sphere,
+        }
+    }
+}
+
+/// Diagnostic view replacing the lit image
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugView {
+    None = 0,
+    /// Primary march step count as a heatmap
+    Steps = 1,
+    Normals = 2,
+    Depth = 3,
+    AmbientOcclusion = 4,
+    /// Soft shadow term averaged over the lights
+    Shadow = 5,
+    /// A color per object
+    ObjectId = 6,
+    /// A color per material, currently the primitive kind
+    MaterialId = 7,
+}
+
+/// Renderer options read by the main fragment shader.
+#[repr(C, align(16))]
+#[derive(Clone, Copy, Debug)]
+pub struct RenderSettings {
+    pub max_march_steps: u32,
+    /// Traverse the bounding volume hierarchy in `map()` rather than every object
+    pub use_bvh: u32,
+    /// Start primary rays from the distance found by the cone marching pre-pass
+    pub cone_prepass: u32,
+    /// Accumulate march step counts into the statistics buffer
+    pub collect_stats: u32,
+    march_strategy: u32,
+    /// Largest multiple of the distance a relaxed step may take
+    pub relaxation: f32,
+    /// Hit threshold in pixels at the hit distance, zero for a fixed threshold
+    pub pixel_footprint: f32,
+    debug_view: u32,
+}
+
+unsafe impl Pod for RenderSettings {}
+unsafe impl Zeroable for RenderSettings {}
+
+impl RenderSettings {
+    pub fn march_strategy(&self) -> MarchStrategy {
+        match self.march_strategy {
+            1 => MarchStrategy::OverRelaxed,
+            2 => MarchStrategy::Enhanced,
+            _ => MarchStrategy::Sphere,
+        }
+    }
+
+    pub fn set_march_strategy(&mut self, strategy: MarchStrategy) {
+        self.march_strategy = strategy as u32;
+    }
+
+    pub fn debug_view(&self) -> DebugView {
+        match self.debug_view {
+            1 => DebugView::Steps,
+            2 => DebugView::Normals,
+            3 => DebugView::Depth,
+            4 => DebugView::AmbientOcclusion,
+            5 => DebugView::Shadow,
+            6 => DebugView::ObjectId,
+            7 => DebugView::MaterialId,
+            _ => DebugView::None,
+        }
+    }
+
+    pub fn set_debug_view(&mut self, view: DebugView) {
+        self.debug_view = view as u32;
+    }
+
+    /// Defaults suited to the scene. Scenes with fractals get a larger step budget and a hit
+    /// threshold of a pixel, so rays stop at detail too fine to see rather than marching into
+    /// it until the budget runs out.
+    pub fn for_scene(scene: &SceneDescriptorBuilder) -> Self {
+        let has_fractals = scene
+            .shapes
+            .iter()
+            .any(|shape| shape.shape_kind().is_fractal());
+
+        match has_fractals {
+            true => Self {
+                max_march_steps: FRACTAL_MARCH_STEPS,
+                pixel_footprint: 1.0,
+                ..Self::default()
+            },
+            false => Self::default(),
+        }
+    }
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            max_march_steps: DEFAULT_MARCH_STEPS,
+            use_bvh: 1,
+            cone_prepass: 1,
+            collect_stats: 0,
+            march_strategy: MarchStrategy::Sphere as u32,
+            relaxation: 1.6,
+            pixel_footprint: 0.0,
+            debug_view: DebugView::None as u32,
+        }
+    }
+}
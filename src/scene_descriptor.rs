@@ -1,12 +1,41 @@
+pub mod distance_grid;
+pub mod fractal;
+pub mod graph;
+pub mod heightmap;
+pub mod media;
+pub mod noise;
 pub mod objects;
+pub mod operator;
+
+use std::{io, path::Path, sync::Arc};
 
 use bytemuck::{Pod, Zeroable};
-use objects::{Cuboid, Sphere};
+use distance_grid::DistanceGrid;
+use glam::{Vec2, Vec3};
+use heightmap::Heightmap;
+use media::{Fog, Medium};
+use objects::{Cuboid, Shape, Sphere};
+use operator::{Operand, Operands, Operator};
+
+use crate::bvh::Aabb;
+
+pub const MAX_MEDIA: usize = 4;
+/// Distance of empty scenes, matches `MAX_SIGNED_DISTANCE` in the shader
+pub const MAX_SIGNED_DISTANCE: f32 = 10000.0;
 
 #[derive(Clone, Default)]
 pub struct SceneDescriptorBuilder {
     pub spheres: Vec<Sphere>,
     pub cuboids: Vec<Cuboid>,
+    pub shapes: Vec<Shape>,
+    /// Distance grids sampled by shapes, shared between copies of the scene as they are large
+    pub grids: Vec<Arc<DistanceGrid>>,
+    /// Heightmaps sampled by heightfield shapes
+    pub heightmaps: Vec<Arc<Heightmap>>,
+    /// Objects operators apply to
+    pub operands: Operands,
+    pub media: Vec<Medium>,
+    pub fog: Fog,
 }
 
 impl SceneDescriptorBuilder {
@@ -14,8 +43,87 @@ impl SceneDescriptorBuilder {
         SceneLengthDescriptor {
             spheres: self.spheres.len() as u32,
             cuboids: self.cuboids.len() as u32,
-            padding: [0; 2],
+            media: self.media.len().min(MAX_MEDIA) as u32,
+            shapes: self.shapes.len() as u32,
+        }
+    }
+
+    /// Adds a medium volume, dropping it with a warning once the shader's `MAX_MEDIA` are in use
+    pub fn add_medium(&mut self, medium: Medium) {
+        if self.media.len() >= MAX_MEDIA {
+            log::warn!("Scenes hold at most {MAX_MEDIA} media, skipping another");
+            return;
+        }
+        self.media.push(medium);
+    }
+
+    /// Adds a grid for shapes to sample, returning a shape sampling it for the caller to place
+    /// and push to `shapes`
+    pub fn add_grid(&mut self, grid: DistanceGrid) -> Shape {
+        let shape = Shape::grid(self.grids.len() as u32, &grid);
+        self.grids.push(Arc::new(grid));
+        shape
+    }
+
+    /// Converts an OBJ or STL mesh to a distance grid with `resolution` cells along its longest
+    /// side, returning a shape sampling it in the mesh's own coordinates
+    pub fn import_mesh(&mut self, path: &Path, resolution: u32) -> io::Result<Shape> {
+        Ok(self.add_grid(DistanceGrid::import(path, resolution)?))
+    }
+
+    /// Adds a heightmap for terrain to follow, returning a heightfield shape spanning `size`
+    /// along x and z between the lowest and highest of `heights`, for the caller to place and
+    /// push to `shapes`
+    pub fn add_heightmap(&mut self, heightmap: Heightmap, size: Vec2, heights: Vec2) -> Shape {
+        let shape = Shape::heightfield(self.heightmaps.len() as u32, &heightmap, size, heights);
+        self.heightmaps.push(Arc::new(heightmap));
+        shape
+    }
+
+    /// Loads a grayscale image as terrain, as `add_heightmap` does
+    pub fn load_heightmap(&mut self, path: &Path, size: Vec2, heights: Vec2) -> io::Result<Shape> {
+        Ok(self.add_heightmap(Heightmap::load(path)?, size, heights))
+    }
+
+    /// Applies `operator` to `operand`, returning a shape for the caller to place and push to
+    /// `shapes`, or to pass to another operator. Chains longer than `MAX_OPERATOR_DEPTH` are cut
+    /// short on the GPU.
+    pub fn operate(&mut self, operator: Operator, operand: impl Into<Operand>) -> Shape {
+        let operand = operand.into();
+        let bounds = operand.bounds(self);
+        let source = self.operands.push(operand);
+        operator.shape(source, bounds)
+    }
+
+    /// Signed distance to the nearest object, evaluated on the CPU the same way `map` does
+    pub fn distance(&self, point: Vec3) -> f32 {
+        let spheres = self.spheres.iter().map(|sphere| sphere.distance(point));
+        let cuboids = self.cuboids.iter().map(|cuboid| cuboid.distance(point));
+        let shapes = self.shapes.iter().map(|shape| shape.distance(point, self));
+        spheres
+            .chain(cuboids)
+            .chain(shapes)
+            .fold(MAX_SIGNED_DISTANCE, f32::min)
+    }
+
+    /// World space bounds of every object
+    pub fn bounds(&self) -> Aabb {
+        let spheres = self.spheres.iter().map(|sphere| sphere.bounds());
+        let cuboids = self.cuboids.iter().map(|cuboid| cuboid.bounds());
+        let shapes = self.shapes.iter().map(|shape| shape.bounds(self));
+        spheres
+            .chain(cuboids)
+            .chain(shapes)
+            .fold(Aabb::EMPTY, Aabb::union)
+    }
+
+    /// Media volumes padded out to the fixed size of the uniform array.
+    pub fn media_buffer(&self) -> [Medium; MAX_MEDIA] {
+        let mut buffer = [Medium::zeroed(); MAX_MEDIA];
+        for (slot, medium) in buffer.iter_mut().zip(&self.media) {
+            *slot = *medium;
         }
+        buffer
     }
 }
 
@@ -24,5 +132,6 @@ impl SceneDescriptorBuilder {
 pub struct SceneLengthDescriptor {
     spheres: u32,
     cuboids: u32,
-    padding: [u32; 2],
+    media: u32,
+    shapes: u32,
 }
@@ -0,0 +1,256 @@
+pub mod triangle_tree;
+
+use std::{io, path::Path};
+
+use glam::{UVec3, Vec3};
+use rayon::prelude::*;
+use triangle_tree::TriangleTree;
+
+use crate::{bvh::Aabb, mesh_export::mesh::Mesh};
+
+/// Cells of padding around what a grid samples, so the surface never meets the faces of its grid
+const PADDING_CELLS: f32 = 2.0;
+
+/// Signed distances sampled at the corners of a regular grid of cubic cells, in the local
+/// space of the shape sampling it
+#[derive(Clone, Debug)]
+pub struct DistanceGrid {
+    /// The first and last samples along each axis lie on the faces of the bounds
+    pub bounds: Aabb,
+    /// Samples along each axis, at least two
+    pub resolution: UVec3,
+    /// x varies fastest, then y, then z
+    pub values: Vec<f32>,
+}
+
+impl DistanceGrid {
+    /// A grid over `bounds` with `resolution` cells along the longest side and no values yet.
+    /// The bounds are grown to a whole number of cells.
+    pub fn empty(bounds: Aabb, resolution: u32) -> Self {
+        let extent = bounds.max - bounds.min;
+        let cell_size = extent.max_element() / resolution.max(1) as f32;
+        let cells = (extent / cell_size).ceil().as_uvec3().max(UVec3::ONE);
+
+        Self {
+            bounds: Aabb {
+                min: bounds.min,
+                max: bounds.min + cells.as_vec3() * cell_size,
+            },
+            resolution: cells + UVec3::ONE,
+            values: Vec::new(),
+        }
+    }
+
+    /// `bounds` grown by a margin of cells for a grid of `resolution` cells along the longest
+    /// side, so a surface within them stays clear of the grid's faces
+    pub fn pad(bounds: Aabb, resolution: u32) -> Aabb {
+        let padding =
+            (bounds.max - bounds.min).max_element() / resolution.max(1) as f32 * PADDING_CELLS;
+        Aabb {
+            min: bounds.min - padding,
+            max: bounds.max + padding,
+        }
+    }
+
+    /// Samples `distance` over `bounds`, laid out as `empty` lays grids out
+    pub fn sample(distance: impl Fn(Vec3) -> f32 + Sync, bounds: Aabb, resolution: u32) -> Self {
+        let mut grid = Self::empty(bounds, resolution);
+        grid.values = (0..grid.resolution.element_product() as usize)
+            .into_par_iter()
+            .map(|index| distance(grid.position(index)))
+            .collect();
+        grid
+    }
+
+    /// Exact distances to the nearest triangle, negative where the mesh's winding number is
+    /// over a half, so meshes with small holes or overlaps still have a sensible inside. Its
+    /// magnitude is used so meshes wound inside out still work.
+    pub fn from_mesh(mesh: &Mesh, resolution: u32) -> Self {
+        let tree = TriangleTree::build(mesh);
+        let bounds = mesh
+            .positions
+            .iter()
+            .fold(Aabb::EMPTY, |bounds, position| bounds.grow(*position));
+
+        Self::sample(
+            |point| match tree.winding_number(point).abs() > 0.5 {
+                true => -tree.distance(point),
+                false => tree.distance(point),
+            },
+            Self::pad(bounds, resolution),
+            resolution,
+        )
+    }
+
+    /// Reads an OBJ or STL mesh and samples its distance field
+    pub fn import(path: &Path, resolution: u32) -> io::Result<Self> {
+        let mesh = Mesh::read(path)?;
+        if mesh.triangles.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} has no triangles", path.display()),
+            ));
+        }
+
+        let grid = Self::from_mesh(&mesh, resolution);
+        log::info!(
+            "Converted {} ({} triangles) to a {}x{}x{} distance grid",
+            path.display(),
+            mesh.triangles.len(),
+            grid.resolution.x,
+            grid.resolution.y,
+            grid.resolution.z
+        );
+        Ok(grid)
+    }
+
+    /// Position of the sample at `index` in `values`
+    pub fn position(&self, index: usize) -> Vec3 {
+        let (width, height) = (self.resolution.x as usize, self.resolution.y as usize);
+        let texel = UVec3::new(
+            (index % width) as u32,
+            (index / width % height) as u32,
+            (index / (width * height)) as u32,
+        );
+        self.bounds.min + texel.as_vec3() * self.cell_size()
+    }
+
+    pub fn cell_size(&self) -> Vec3 {
+        (self.bounds.max - self.bounds.min) / (self.resolution - UVec3::ONE).as_vec3()
+    }
+
+    fn value(&self, texel: UVec3) -> f32 {
+        let size = self.resolution;
+        self.values[(texel.x + size.x * (texel.y + size.y * texel.z)) as usize]
+    }
+
+    /// Matches `evaluate_sdf_grid` in the shader
+    pub fn distance(&self, point: Vec3) -> f32 {
+        let clamped = point.clamp(self.bounds.min, self.bounds.max);
+        let cell = (clamped - self.bounds.min) / self.cell_size();
+        let base = cell
+            .floor()
+            .as_uvec3()
+            .min(self.resolution - UVec3::splat(2));
+        let t = cell - base.as_vec3();
+
+        let corner = |x: u32, y: u32, z: u32| self.value(base + UVec3::new(x, y, z));
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let y0 = lerp(
+            lerp(corner(0, 0, 0), corner(1, 0, 0), t.x),
+            lerp(corner(0, 1, 0), corner(1, 1, 0), t.x),
+            t.y,
+        );
+        let y1 = lerp(
+            lerp(corner(0, 0, 1), corner(1, 0, 1), t.x),
+            lerp(corner(0, 1, 1), corner(1, 1, 1), t.x),
+            t.y,
+        );
+        let inside = lerp(y0, y1, t.z);
+
+        // The surface lies within the box, and from outside the box any point in it is
+        // further than the nearest point of the box by at least its distance along the face
+        let outside = point.distance(clamped);
+        match outside > 0.0 {
+            true => (outside * outside + inside.max(0.0).powi(2)).sqrt(),
+            false => inside,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cube from -1 to 1, its faces wound counter clockwise seen from outside
+    fn cube() -> Mesh {
+        let positions = (0..8)
+            .map(|corner| {
+                Vec3::new(
+                    (corner & 1) as f32,
+                    (corner >> 1 & 1) as f32,
+                    (corner >> 2 & 1) as f32,
+                ) * 2.0
+                    - 1.0
+            })
+            .collect();
+        let faces = [
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+        ];
+
+        Mesh {
+            positions,
+            normals: Vec::new(),
+            triangles: faces
+                .iter()
+                .flat_map(|[a, b, c, d]| [[*a, *b, *c], [*a, *c, *d]])
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn winding_number_signs_cube() {
+        let grid = DistanceGrid::from_mesh(&cube(), 16);
+
+        assert!(grid.distance(Vec3::ZERO) < 0.0);
+        assert!(grid.distance(Vec3::new(0.7, -0.7, 0.7)) < 0.0);
+        assert!(grid.distance(Vec3::new(1.3, 0.0, 0.0)) > 0.0);
+        assert!(grid.distance(Vec3::new(0.0, 0.0, 3.0)) > 0.0);
+    }
+
+    #[test]
+    fn samples_are_exact_distances() {
+        let grid = DistanceGrid::from_mesh(&cube(), 16);
+        let exact = |point: Vec3| {
+            let q = point.abs() - Vec3::ONE;
+            q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+        };
+
+        for (index, value) in grid.values.iter().enumerate() {
+            let position = grid.position(index);
+            assert!(
+                (value - exact(position)).abs() < 1e-4,
+                "{value} at {position}"
+            );
+        }
+    }
+
+    #[test]
+    fn inside_out_meshes_keep_their_inside() {
+        let mut mesh = cube();
+        for triangle in &mut mesh.triangles {
+            triangle.swap(1, 2);
+        }
+        let grid = DistanceGrid::from_mesh(&mesh, 16);
+
+        assert!(grid.distance(Vec3::ZERO) < 0.0);
+        assert!(grid.distance(Vec3::new(1.3, 0.0, 0.0)) > 0.0);
+    }
+
+    #[test]
+    fn distance_outside_grid_is_a_bound() {
+        let grid = DistanceGrid::from_mesh(&cube(), 16);
+
+        // Nine away from the face, but only the distance to the grid is known exactly
+        let far = grid.distance(Vec3::new(10.0, 0.0, 0.0));
+        assert!(far <= 9.0 && far > 8.5, "{far}");
+    }
+
+    #[test]
+    fn import_rejects_unusable_files() {
+        let directory = std::env::temp_dir();
+        let empty = directory.join("distance_grid_test_empty.obj");
+        std::fs::write(&empty, "v 0 0 0\n").unwrap();
+        let unsupported = directory.join("distance_grid_test.ply");
+        std::fs::write(&unsupported, "ply\n").unwrap();
+
+        assert!(DistanceGrid::import(&empty, 16).is_err());
+        assert!(DistanceGrid::import(&unsupported, 16).is_err());
+        assert!(DistanceGrid::import(&directory.join("missing.obj"), 16).is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use std::f32::consts::PI;
+
+use glam::Vec3;
+
+use crate::{bvh::Aabb, mesh_export::mesh::Mesh};
+
+const LEAF_SIZE: usize = 4;
+/// Clusters further than this many times their radius are treated as a single dipole when
+/// summing the winding number
+const WINDING_ACCURACY: f32 = 2.0;
+
+/// Nodes hold either a run of `count` triangles from `first` or, when `count` is zero, their
+/// two children at `first` and `first + 1`
+struct Node {
+    bounds: Aabb,
+    first: usize,
+    count: usize,
+    /// Sum of the area weighted normals of the node's triangles
+    normal: Vec3,
+    /// Area weighted centroid of the node's triangles
+    center: Vec3,
+    /// Distance from `center` to the furthest corner of the bounds
+    radius: f32,
+}
+
+/// Bounding volume hierarchy over the triangles of a mesh, answering exact distance and
+/// generalized winding number queries
+pub struct TriangleTree {
+    nodes: Vec<Node>,
+    triangles: Vec<[Vec3; 3]>,
+}
+
+/// Closest point to `point` on the triangle `abc`, from Ericson's Real-Time Collision Detection
+fn closest_point([a, b, c]: [Vec3; 3], point: Vec3) -> Vec3 {
+    let (ab, ac, ap) = (b - a, c - a, point - a);
+    let (d1, d2) = (ab.dot(ap), ac.dot(ap));
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+
+    let bp = point - b;
+    let (d3, d4) = (ab.dot(bp), ac.dot(bp));
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = point - c;
+    let (d5, d6) = (ab.dot(cp), ac.dot(cp));
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let denominator = 1.0 / (va + vb + vc);
+    a + ab * (vb * denominator) + ac * (vc * denominator)
+}
+
+/// Solid angle the triangle subtends at `point` over 4π, positive when the point is behind it.
+/// From Van Oosterom and Strackee.
+fn winding([a, b, c]: [Vec3; 3], point: Vec3) -> f32 {
+    let (a, b, c) = (a - point, b - point, c - point);
+    let (la, lb, lc) = (a.length(), b.length(), c.length());
+    let determinant = a.dot(b.cross(c));
+    let divisor = la * lb * lc + a.dot(b) * lc + b.dot(c) * la + c.dot(a) * lb;
+    determinant.atan2(divisor) / (2.0 * PI)
+}
+
+impl TriangleTree {
+    pub fn build(mesh: &Mesh) -> Self {
+        let mut tree = Self {
+            nodes: Vec::new(),
+            triangles: mesh
+                .triangles
+                .iter()
+                .map(|triangle| triangle.map(|index| mesh.positions[index as usize]))
+                .collect(),
+        };
+
+        if !tree.triangles.is_empty() {
+            tree.nodes.push(tree.node(0, tree.triangles.len()));
+            tree.subdivide(0);
+        }
+
+        tree
+    }
+
+    fn node(&self, first: usize, count: usize) -> Node {
+        let triangles = &self.triangles[first..first + count];
+        let bounds = triangles
+            .iter()
+            .flatten()
+            .fold(Aabb::EMPTY, |bounds, vertex| bounds.grow(*vertex));
+
+        let (mut normal, mut center, mut area) = (Vec3::ZERO, Vec3::ZERO, 0.0);
+        for &[a, b, c] in triangles {
+            let area_normal = (b - a).cross(c - a) * 0.5;
+            let triangle_area = area_normal.length();
+            normal += area_normal;
+            center += (a + b + c) / 3.0 * triangle_area;
+            area += triangle_area;
+        }
+        let center = match area > 0.0 {
+            true => center / area,
+            false => bounds.center(),
+        };
+        let radius = (center - bounds.min)
+            .abs()
+            .max((bounds.max - center).abs())
+            .length();
+
+        Node {
+            bounds,
+            first,
+            count,
+            normal,
+            center,
+            radius,
+        }
+    }
+
+    fn subdivide(&mut self, node_index: usize) {
+        let Node { first, count, .. } = self.nodes[node_index];
+        if count <= LEAF_SIZE {
+            return;
+        }
+
+        // Median split along the longest axis of the centroids
+        let triangles = &mut self.triangles[first..first + count];
+        let centroid = |[a, b, c]: &[Vec3; 3]| a + b + c;
+        let centroid_bounds = triangles.iter().fold(Aabb::EMPTY, |bounds, triangle| {
+            bounds.grow(centroid(triangle))
+        });
+        let extent = centroid_bounds.max - centroid_bounds.min;
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        };
+
+        let middle = count / 2;
+        triangles.select_nth_unstable_by(middle, |a, b| {
+            centroid(a)[axis].total_cmp(&centroid(b)[axis])
+        });
+
+        let left_index = self.nodes.len();
+        let left = self.node(first, middle);
+        let right = self.node(first + middle, count - middle);
+        self.nodes.extend([left, right]);
+        self.nodes[node_index].first = left_index;
+        self.nodes[node_index].count = 0;
+
+        self.subdivide(left_index);
+        self.subdivide(left_index + 1);
+    }
+
+    /// Distance to the nearest triangle, visiting nearer nodes first and skipping any further
+    /// than the nearest triangle so far
+    pub fn distance(&self, point: Vec3) -> f32 {
+        let mut nearest = f32::INFINITY;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if node.bounds.distance(point) >= nearest {
+                continue;
+            }
+
+            if node.count > 0 {
+                for triangle in &self.triangles[node.first..node.first + node.count] {
+                    nearest = nearest.min(closest_point(*triangle, point).distance(point));
+                }
+                continue;
+            }
+
+            let (left, right) = (node.first, node.first + 1);
+            let left_distance = self.nodes[left].bounds.distance(point);
+            let right_distance = self.nodes[right].bounds.distance(point);
+            match left_distance < right_distance {
+                true => stack.extend([right, left]),
+                false => stack.extend([left, right]),
+            }
+        }
+
+        nearest
+    }
+
+    /// Generalized winding number of the mesh around `point`, near one inside and near zero
+    /// outside even for meshes with small holes or overlaps. Distant clusters of triangles are
+    /// approximated by their area weighted normal, as in Barill et al.'s fast winding numbers.
+    pub fn winding_number(&self, point: Vec3) -> f32 {
+        let mut total = 0.0;
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            let offset = node.center - point;
+            let distance = offset.length();
+
+            if distance > node.radius * WINDING_ACCURACY {
+                total += node.normal.dot(offset) / (4.0 * PI * distance.powi(3));
+            } else if node.count > 0 {
+                total += self.triangles[node.first..node.first + node.count]
+                    .iter()
+                    .map(|triangle| winding(*triangle, point))
+                    .sum::<f32>();
+            } else {
+                stack.extend([node.first, node.first + 1]);
+            }
+        }
+
+        total
+    }
+}
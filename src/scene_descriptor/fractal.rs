@@ -0,0 +1,131 @@
+use glam::{Vec3, Vec3Swizzles};
+
+/// Orbits escaping this far from the origin diverge, which also bounds the Mandelbulb
+pub const MANDELBULB_BAILOUT: f32 = 2.0;
+
+/// Matches `evaluate_mandelbulb` in the shader
+pub fn mandelbulb(point: Vec3, iterations: u32, power: f32) -> f32 {
+    let mut z = point;
+    let mut dr = 1.0;
+    for _ in 0..iterations {
+        let r = z.length();
+        if r > MANDELBULB_BAILOUT {
+            break;
+        }
+
+        let theta = (z.y / r.max(1e-6)).clamp(-1.0, 1.0).acos() * power;
+        let phi = z.z.atan2(z.x) * power;
+        dr = r.powf(power - 1.0) * power * dr + 1.0;
+        z = r.powf(power)
+            * Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            )
+            + point;
+    }
+
+    let r = z.length().max(1e-6);
+    0.5 * r.ln() * r / dr
+}
+
+fn cube(point: Vec3) -> f32 {
+    let q = point.abs() - Vec3::ONE;
+    q.max(Vec3::ZERO).length() + q.max_element().min(0.0)
+}
+
+/// Matches `evaluate_menger_sponge` in the shader
+pub fn menger_sponge(point: Vec3, iterations: u32, scale: f32) -> f32 {
+    let mut z = point;
+    let mut factor = 1.0;
+    for _ in 0..iterations {
+        z = z.abs();
+        if z.x < z.y {
+            z = z.yxz();
+        }
+        if z.x < z.z {
+            z = z.zyx();
+        }
+        if z.y < z.z {
+            z = z.xzy();
+        }
+
+        z = Vec3::new(
+            z.x * scale - (scale - 1.0),
+            z.y * scale - (scale - 1.0),
+            z.z * scale,
+        );
+        if z.z > 0.5 * (scale - 1.0) {
+            z.z -= scale - 1.0;
+        }
+        factor *= scale;
+    }
+
+    cube(z) / factor
+}
+
+/// Matches `evaluate_mandelbox` in the shader
+pub fn mandelbox(
+    point: Vec3,
+    iterations: u32,
+    scale: f32,
+    min_radius: f32,
+    fixed_radius: f32,
+) -> f32 {
+    let (min_squared, fixed_squared) = (min_radius * min_radius, fixed_radius * fixed_radius);
+    let mut z = point;
+    let mut dr = 1.0;
+    for _ in 0..iterations {
+        z = z.clamp(Vec3::NEG_ONE, Vec3::ONE) * 2.0 - z;
+
+        let r_squared = z.length_squared();
+        let fold = if r_squared < min_squared {
+            fixed_squared / min_squared
+        } else if r_squared < fixed_squared {
+            fixed_squared / r_squared
+        } else {
+            1.0
+        };
+        z *= fold;
+        dr *= fold;
+
+        z = z * scale + point;
+        dr = dr * scale.abs() + 1.0;
+    }
+
+    // Subtracting the radius orbits settle within makes the estimate negative inside
+    (z.length() - (scale - 1.0).abs()) / dr.abs() - scale.abs().powf(1.0 - iterations as f32)
+}
+
+/// Half the side of the cube a Mandelbox of `scale` stays within, widened for scales near one
+/// where it stops being bounded
+pub fn mandelbox_bound(scale: f32) -> f32 {
+    2.0 * (scale.abs() + 1.0) / (scale.abs() - 1.0).max(0.25)
+}
+
+/// Matches `evaluate_sierpinski` in the shader
+pub fn sierpinski(point: Vec3, iterations: u32, scale: f32) -> f32 {
+    let mut z = point;
+    let mut factor = 1.0;
+    for _ in 0..iterations {
+        if z.x + z.y < 0.0 {
+            z = Vec3::new(-z.y, -z.x, z.z);
+        }
+        if z.x + z.z < 0.0 {
+            z = Vec3::new(-z.z, z.y, -z.x);
+        }
+        if z.y + z.z < 0.0 {
+            z = Vec3::new(z.x, -z.z, -z.y);
+        }
+
+        z = z * scale - Vec3::splat(scale - 1.0);
+        factor *= scale;
+    }
+
+    // The tetrahedron with corners at (1, 1, 1), (-1, -1, 1), (1, -1, -1) and (-1, 1, -1)
+    let tetrahedron = (-z.x - z.y - z.z)
+        .max(z.x + z.y - z.z)
+        .max(-z.x + z.y + z.z)
+        .max(z.x - z.y + z.z);
+    (tetrahedron - 1.0) / 3f32.sqrt() / factor
+}
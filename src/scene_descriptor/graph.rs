@@ -0,0 +1,329 @@
+use glam::{Mat4, Vec3};
+
+use super::{
+    objects::{Cuboid, Shape, Sphere},
+    SceneDescriptorBuilder,
+};
+
+/// Index of a node in its `SceneGraph`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NodeId(usize);
+
+/// What a node adds to the scene besides its children
+#[derive(Clone, Copy)]
+pub enum Content {
+    /// Nothing, only placing its children
+    Group,
+    Sphere(Sphere),
+    Cuboid(Cuboid),
+    /// Shapes don't compensate for scale as spheres and cuboids do, so should only be placed
+    /// under nodes that aren't scaled
+    Shape(Shape),
+    /// Another copy of a subtree, placed by this node
+    Instance(NodeId),
+}
+
+impl From<Sphere> for Content {
+    fn from(sphere: Sphere) -> Self {
+        Self::Sphere(sphere)
+    }
+}
+
+impl From<Cuboid> for Content {
+    fn from(cuboid: Cuboid) -> Self {
+        Self::Cuboid(cuboid)
+    }
+}
+
+impl From<Shape> for Content {
+    fn from(shape: Shape) -> Self {
+        Self::Shape(shape)
+    }
+}
+
+pub struct Node {
+    pub name: String,
+    /// Maps the parent's space to the node's, as object transforms map world space to local
+    /// space, so each object's transform places it within its node
+    pub transform: Mat4,
+    /// Smallest factor the node has been scaled by, see `Sphere::scale`
+    pub scale: f32,
+    pub content: Content,
+    pub children: Vec<NodeId>,
+}
+
+/// Objects arranged in a hierarchy of nodes, each placed relative to its parent, resolved into
+/// a flat `SceneDescriptorBuilder` with every object's world transform before upload. Subtrees
+/// made with `subtree` are only resolved through the instances referring to them.
+pub struct SceneGraph {
+    nodes: Vec<Node>,
+    root: NodeId,
+}
+
+impl Node {
+    fn new(name: String, content: Content) -> Self {
+        Self {
+            name,
+            transform: Mat4::IDENTITY,
+            scale: 1.0,
+            content,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn translate(&mut self, v: Vec3) -> &mut Self {
+        self.transform = Mat4::from_translation(v) * self.transform;
+        self
+    }
+
+    pub fn rotate(&mut self, v: Vec3) -> &mut Self {
+        self.transform = Mat4::from_rotation_x(v.x)
+            * Mat4::from_rotation_y(v.y)
+            * Mat4::from_rotation_z(v.z)
+            * self.transform;
+        self
+    }
+
+    pub fn scale(&mut self, v: Vec3) -> &mut Self {
+        self.transform = Mat4::from_scale(v.recip()) * self.transform;
+        self.scale *= v.abs().min_element();
+        self
+    }
+
+    pub fn scale_uniform(&mut self, factor: f32) -> &mut Self {
+        self.scale(Vec3::splat(factor))
+    }
+}
+
+impl Default for SceneGraph {
+    fn default() -> Self {
+        Self {
+            nodes: vec![Node::new("root".into(), Content::Group)],
+            root: NodeId(0),
+        }
+    }
+}
+
+impl SceneGraph {
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id.0]
+    }
+
+    /// First node named `name`, in the order they were added
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.nodes
+            .iter()
+            .position(|node| node.name == name)
+            .map(NodeId)
+    }
+
+    /// Adds a node under `parent`, returning it to be placed and given children
+    pub fn add(
+        &mut self,
+        parent: NodeId,
+        name: impl Into<String>,
+        content: impl Into<Content>,
+    ) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node::new(name.into(), content.into()));
+        self.nodes[parent.0].children.push(id);
+        id
+    }
+
+    pub fn group(&mut self, parent: NodeId, name: impl Into<String>) -> NodeId {
+        self.add(parent, name, Content::Group)
+    }
+
+    /// Adds a copy of `subtree` under `parent`, following any later changes to it
+    pub fn instance(&mut self, parent: NodeId, name: impl Into<String>, subtree: NodeId) -> NodeId {
+        self.add(parent, name, Content::Instance(subtree))
+    }
+
+    /// Adds a group outside the hierarchy, only appearing where it's instanced
+    pub fn subtree(&mut self, name: impl Into<String>) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        self.nodes.push(Node::new(name.into(), Content::Group));
+        id
+    }
+
+    /// Adds every object in the hierarchy to `scene` with its world transform
+    pub fn resolve(&self, scene: &mut SceneDescriptorBuilder) {
+        self.resolve_node(self.root, Mat4::IDENTITY, 1.0, &mut Vec::new(), scene);
+    }
+
+    /// `to_parent` maps world space to the parent's space, and `path` holds the nodes above
+    /// this one, through instances too
+    fn resolve_node(
+        &self,
+        id: NodeId,
+        to_parent: Mat4,
+        parent_scale: f32,
+        path: &mut Vec<NodeId>,
+        scene: &mut SceneDescriptorBuilder,
+    ) {
+        let node = self.node(id);
+        if path.contains(&id) {
+            log::warn!("Skipping node {} inside itself", node.name);
+            return;
+        }
+
+        let to_node = node.transform * to_parent;
+        let scale = parent_scale * node.scale;
+        path.push(id);
+
+        match node.content {
+            Content::Group => {}
+            Content::Sphere(mut sphere) => {
+                sphere.transform *= to_node;
+                sphere.scale *= scale;
+                scene.spheres.push(sphere);
+            }
+            Content::Cuboid(mut cuboid) => {
+                cuboid.transform *= to_node;
+                cuboid.scale *= scale;
+                scene.cuboids.push(cuboid);
+            }
+            Content::Shape(mut shape) => {
+                shape.transform *= to_node;
+                scene.shapes.push(shape);
+            }
+            Content::Instance(subtree) => self.resolve_node(subtree, to_node, scale, path, scene),
+        }
+
+        for &child in &node.children {
+            self.resolve_node(child, to_node, scale, path, scene);
+        }
+        path.pop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3;
+
+    use super::*;
+
+    /// The world space centre of a sphere, where its local space origin lands
+    fn centre(sphere: &Sphere) -> Vec3 {
+        sphere.transform.inverse().transform_point3(Vec3::ZERO)
+    }
+
+    fn resolve(graph: &SceneGraph) -> SceneDescriptorBuilder {
+        let mut scene = SceneDescriptorBuilder::default();
+        graph.resolve(&mut scene);
+        scene
+    }
+
+    #[test]
+    fn transforms_compose_down_the_hierarchy() {
+        let mut graph = SceneGraph::default();
+        let group = graph.group(graph.root(), "group");
+        graph.node_mut(group).translate(vec3(-1.0, 0.0, 0.0));
+        let child = graph.add(
+            group,
+            "child",
+            Sphere::new(0.5).translate(vec3(0.0, -2.0, 0.0)),
+        );
+        graph.node_mut(child).translate(vec3(0.0, 0.0, -3.0));
+
+        let scene = resolve(&graph);
+        assert_eq!(scene.spheres.len(), 1);
+        assert!(centre(&scene.spheres[0]).distance(vec3(1.0, 2.0, 3.0)) < 1e-5);
+    }
+
+    #[test]
+    fn subtrees_appear_once_per_instance() {
+        let mut graph = SceneGraph::default();
+        let subtree = graph.subtree("pair");
+        graph.add(subtree, "sphere", Sphere::new(0.5));
+        graph.add(subtree, "cuboid", Cuboid::new(Vec3::splat(0.5)));
+
+        assert!(resolve(&graph).cuboids.is_empty());
+
+        for offset in [-2.0, 2.0] {
+            let copy = graph.instance(graph.root(), "copy", subtree);
+            graph.node_mut(copy).translate(vec3(offset, 0.0, 0.0));
+        }
+
+        let scene = resolve(&graph);
+        assert_eq!(scene.spheres.len(), 2);
+        assert_eq!(scene.cuboids.len(), 2);
+        assert!(centre(&scene.spheres[0]).distance(vec3(2.0, 0.0, 0.0)) < 1e-5);
+        assert!(centre(&scene.spheres[1]).distance(vec3(-2.0, 0.0, 0.0)) < 1e-5);
+    }
+
+    #[test]
+    fn instances_follow_later_changes() {
+        let mut graph = SceneGraph::default();
+        let subtree = graph.subtree("subtree");
+        graph.instance(graph.root(), "copy", subtree);
+        graph.add(subtree, "sphere", Sphere::new(1.0));
+
+        assert_eq!(resolve(&graph).spheres.len(), 1);
+    }
+
+    #[test]
+    fn scale_compounds_through_instances() {
+        let mut graph = SceneGraph::default();
+        let subtree = graph.subtree("ball");
+        let ball = graph.add(subtree, "sphere", Sphere::new(1.0));
+        graph.node_mut(ball).translate(vec3(-1.0, 0.0, 0.0));
+
+        // Moved along x by four, then half size about where it was moved to
+        let copy = graph.instance(graph.root(), "small", subtree);
+        graph
+            .node_mut(copy)
+            .translate(vec3(-4.0, 0.0, 0.0))
+            .scale_uniform(0.5);
+        // Halved within a group doubling x and halving y, so full size along x only
+        let group = graph.group(graph.root(), "stretched");
+        graph.node_mut(group).scale(vec3(2.0, 0.5, 1.0));
+        let nested = graph.instance(group, "smaller", subtree);
+        graph.node_mut(nested).scale_uniform(0.5);
+
+        let scene = resolve(&graph);
+        let [small, smaller] = [scene.spheres[0], scene.spheres[1]];
+
+        assert!(centre(&small).distance(vec3(4.5, 0.0, 0.0)) < 1e-5);
+        assert!((small.distance(vec3(4.5, 2.0, 0.0)) - 1.5).abs() < 1e-5);
+        assert_eq!(small.scale, 0.5);
+
+        assert!(centre(&smaller).distance(vec3(1.0, 0.0, 0.0)) < 1e-5);
+        assert_eq!(smaller.scale, 0.25);
+        // An ellipsoid reaching a unit along x, a quarter along y and a half along z
+        assert!(smaller.distance(vec3(1.0, 0.0, 2.0)) <= 1.5 + 1e-5);
+        assert!(smaller.distance(vec3(1.9, 0.0, 0.0)) < 0.0);
+        assert!(smaller.distance(vec3(1.0, 0.3, 0.0)) > 0.0);
+        assert!(smaller.distance(vec3(1.0, 0.0, 0.4)) < 0.0);
+    }
+
+    #[test]
+    fn instancing_a_subtree_inside_itself_stops() {
+        let mut graph = SceneGraph::default();
+        let subtree = graph.subtree("loop");
+        graph.add(subtree, "sphere", Sphere::new(1.0));
+        graph.instance(subtree, "again", subtree);
+        graph.instance(graph.root(), "copy", subtree);
+
+        assert_eq!(resolve(&graph).spheres.len(), 1);
+    }
+
+    #[test]
+    fn find_returns_first_match() {
+        let mut graph = SceneGraph::default();
+        let first = graph.group(graph.root(), "name");
+        graph.group(first, "name");
+
+        assert_eq!(graph.find("name"), Some(first));
+        assert_eq!(graph.find("root"), Some(graph.root()));
+        assert_eq!(graph.find("missing"), None);
+    }
+}
@@ -0,0 +1,80 @@
+use std::{io, path::Path};
+
+use glam::{IVec2, UVec2, Vec2};
+
+/// Heights from zero to one read from a grayscale image, with the first row at the image's
+/// top. Sampled bilinearly between texel centres, wrapping at the edges so it can tile.
+#[derive(Clone, Debug)]
+pub struct Heightmap {
+    pub size: UVec2,
+    /// x varies fastest, then y
+    pub values: Vec<f32>,
+    /// Steepest change of height per unit of texture coordinate along each axis, the
+    /// Lipschitz constant of the interpolated heights
+    pub slope: Vec2,
+}
+
+impl Heightmap {
+    pub fn new(size: UVec2, values: Vec<f32>) -> Self {
+        let mut heightmap = Self {
+            size,
+            values,
+            slope: Vec2::ZERO,
+        };
+
+        // Interpolation never changes faster than between neighbouring texels, including the
+        // pairs that meet across the wrapped edges
+        let mut steepest = Vec2::ZERO;
+        for y in 0..size.y as i32 {
+            for x in 0..size.x as i32 {
+                let value = heightmap.value(IVec2::new(x, y));
+                steepest = steepest.max(Vec2::new(
+                    (heightmap.value(IVec2::new(x + 1, y)) - value).abs(),
+                    (heightmap.value(IVec2::new(x, y + 1)) - value).abs(),
+                ));
+            }
+        }
+        heightmap.slope = steepest * size.as_vec2();
+
+        heightmap
+    }
+
+    /// Reads an image of any format as luminance
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let image = image::open(path).map_err(io::Error::other)?.to_luma32f();
+        let heightmap = Self::new(image.dimensions().into(), image.into_raw());
+        log::info!(
+            "Loaded {}x{} heightmap from {}, slope {:.1}x{:.1}",
+            heightmap.size.x,
+            heightmap.size.y,
+            path.display(),
+            heightmap.slope.x,
+            heightmap.slope.y
+        );
+        Ok(heightmap)
+    }
+
+    fn value(&self, texel: IVec2) -> f32 {
+        let texel = texel.rem_euclid(self.size.as_ivec2()).as_uvec2();
+        self.values[(texel.x + texel.y * self.size.x) as usize]
+    }
+
+    /// Matches `heightmap_height` in the shader
+    pub fn height(&self, uv: Vec2) -> f32 {
+        let position = uv * self.size.as_vec2() - 0.5;
+        let base = position.floor();
+        let t = position - base;
+        let base = base.as_ivec2();
+
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        lerp(
+            lerp(self.value(base), self.value(base + IVec2::X), t.x),
+            lerp(
+                self.value(base + IVec2::Y),
+                self.value(base + IVec2::ONE),
+                t.x,
+            ),
+            t.y,
+        )
+    }
+}
@@ -0,0 +1,84 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3};
+
+/// Global exponential height fog, applied along every view ray.
+#[repr(C, align(16))]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct Fog {
+    pub color: Vec3,
+    pub density: f32,
+    pub height_falloff: f32,
+    pub base_height: f32,
+    pub absorption: f32,
+    _padding: u32,
+}
+
+/// A box shaped volume of participating media.
+#[repr(C, align(16))]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct Medium {
+    pub transform: Mat4,
+    pub dimensions: Vec3,
+    pub density: f32,
+    pub scattering: Vec3,
+    pub absorption: f32,
+}
+
+impl Fog {
+    pub fn new(color: Vec3, density: f32, height_falloff: f32, base_height: f32) -> Self {
+        Self {
+            color,
+            density,
+            height_falloff,
+            base_height,
+            absorption: 0.0,
+            _padding: 0,
+        }
+    }
+
+    pub fn with_absorption(&self, absorption: f32) -> Self {
+        Self {
+            absorption,
+            ..*self
+        }
+    }
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self::new(Vec3::ONE, 0.0, 0.0, 0.0)
+    }
+}
+
+impl Medium {
+    pub fn new(dimensions: Vec3, density: f32) -> Self {
+        Self {
+            transform: Mat4::IDENTITY,
+            dimensions,
+            density,
+            scattering: Vec3::ONE,
+            absorption: 0.0,
+        }
+    }
+
+    pub fn translate(&self, v: Vec3) -> Self {
+        Self {
+            transform: Mat4::from_translation(v) * self.transform,
+            ..*self
+        }
+    }
+
+    pub fn with_scattering(&self, scattering: Vec3) -> Self {
+        Self {
+            scattering,
+            ..*self
+        }
+    }
+
+    pub fn with_absorption(&self, absorption: f32) -> Self {
+        Self {
+            absorption,
+            ..*self
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use glam::{UVec3, Vec3};
+
+/// Steepest slope of each noise found by sampling, with some margin, used to bound how far a
+/// displaced surface can be from a sample's distance
+const VALUE_LIPSCHITZ: f32 = 4.0;
+const GRADIENT_LIPSCHITZ: f32 = 2.5;
+const WORLEY_LIPSCHITZ: f32 = 2.0;
+
+/// Shift between octaves, so their lattices don't line up at the origin
+const OCTAVE_OFFSET: Vec3 = Vec3::new(17.31, 5.93, 11.47);
+
+/// Kinds of noise, matching the `NOISE_*` constants in the shader. Each ranges from minus one
+/// to one.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Noise {
+    /// Random values at lattice points, blended between them
+    Value = 0,
+    /// Random slopes at lattice points, smoother and less blocky than value noise
+    Gradient = 1,
+    /// Bumps peaking at random points, one per lattice cell, for cellular or pebbled surfaces
+    Worley = 2,
+}
+
+/// Noise displacing a surface, summed over octaves as fractional Brownian motion
+#[derive(Clone, Copy, Debug)]
+pub struct Displacement {
+    pub noise: Noise,
+    /// Height of the first octave's bumps to either side of the surface
+    pub amplitude: f32,
+    /// Lattice cells per unit of the first octave
+    pub frequency: f32,
+    pub octaves: u32,
+    /// Frequency of each octave over the one before
+    pub lacunarity: f32,
+    /// Amplitude of each octave over the one before
+    pub gain: f32,
+}
+
+impl Noise {
+    pub fn from_u32(kind: u32) -> Self {
+        match kind {
+            1 => Self::Gradient,
+            2 => Self::Worley,
+            _ => Self::Value,
+        }
+    }
+
+    fn lipschitz(self) -> f32 {
+        match self {
+            Self::Value => VALUE_LIPSCHITZ,
+            Self::Gradient => GRADIENT_LIPSCHITZ,
+            Self::Worley => WORLEY_LIPSCHITZ,
+        }
+    }
+
+    /// Matches `noise` in the shader
+    pub fn sample(self, point: Vec3) -> f32 {
+        match self {
+            Self::Value => value(point),
+            Self::Gradient => gradient(point),
+            Self::Worley => worley(point),
+        }
+    }
+}
+
+impl Displacement {
+    /// A single octave of `noise`, more added with `with_octaves`
+    pub fn new(noise: Noise, amplitude: f32, frequency: f32) -> Self {
+        Self {
+            noise,
+            amplitude,
+            frequency,
+            octaves: 1,
+            lacunarity: 2.0,
+            gain: 0.5,
+        }
+    }
+
+    pub fn with_octaves(&self, octaves: u32) -> Self {
+        Self { octaves, ..*self }
+    }
+
+    pub fn with_lacunarity(&self, lacunarity: f32) -> Self {
+        Self {
+            lacunarity,
+            ..*self
+        }
+    }
+
+    pub fn with_gain(&self, gain: f32) -> Self {
+        Self { gain, ..*self }
+    }
+
+    /// Furthest the surface moves to either side
+    pub fn reach(&self) -> f32 {
+        (0..self.octaves)
+            .map(|octave| (self.amplitude * self.gain.powi(octave as i32)).abs())
+            .sum()
+    }
+
+    /// Lipschitz constant of the displaced distance, which sphere tracing divides it by to
+    /// keep from stepping through bumps
+    pub fn lipschitz(&self) -> f32 {
+        let slope: f32 = (0..self.octaves)
+            .map(|octave| {
+                let octave = octave as i32;
+                (self.amplitude * self.gain.powi(octave)).abs()
+                    * (self.frequency * self.lacunarity.powi(octave)).abs()
+            })
+            .sum();
+        1.0 + slope * self.noise.lipschitz()
+    }
+
+    /// Height of the surface above `point`, matches `fbm` in the shader
+    pub fn sample(&self, point: Vec3) -> f32 {
+        let (mut amplitude, mut frequency) = (self.amplitude, self.frequency);
+        let mut sum = 0.0;
+        for octave in 0..self.octaves {
+            sum += amplitude
+                * self
+                    .noise
+                    .sample(point * frequency + OCTAVE_OFFSET * octave as f32);
+            amplitude *= self.gain;
+            frequency *= self.lacunarity;
+        }
+        sum
+    }
+}
+
+/// PCG based hash of a lattice cell, matches `hash3` in the shader
+fn hash3(cell: Vec3) -> UVec3 {
+    let mix = |v: UVec3| {
+        let x = v.x.wrapping_add(v.y.wrapping_mul(v.z));
+        let y = v.y.wrapping_add(v.z.wrapping_mul(x));
+        let z = v.z.wrapping_add(x.wrapping_mul(y));
+        UVec3::new(x, y, z)
+    };
+    let v = cell
+        .as_ivec3()
+        .as_uvec3()
+        .wrapping_mul(UVec3::splat(1664525))
+        .wrapping_add(UVec3::splat(1013904223));
+    let v = mix(v);
+    mix(v ^ (v >> 16u32))
+}
+
+/// Hash of a lattice cell as three values from zero to one
+fn random3(cell: Vec3) -> Vec3 {
+    (hash3(cell) >> 8u32).as_vec3() / 16777215.0
+}
+
+fn fade(t: Vec3) -> Vec3 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+/// Blends the values at a cell's eight corners, `corner` giving the value at an offset
+fn blend(point: Vec3, corner: impl Fn(Vec3) -> f32) -> f32 {
+    let cell = point.floor();
+    let u = fade(point - cell);
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let edge = |y: f32, z: f32| {
+        lerp(
+            corner(Vec3::new(0.0, y, z)),
+            corner(Vec3::new(1.0, y, z)),
+            u.x,
+        )
+    };
+    let face = |z: f32| lerp(edge(0.0, z), edge(1.0, z), u.y);
+    lerp(face(0.0), face(1.0), u.z)
+}
+
+/// Matches `lattice_noise` in the shader for value noise
+fn value(point: Vec3) -> f32 {
+    let cell = point.floor();
+    blend(point, |offset| random3(cell + offset).x * 2.0 - 1.0)
+}
+
+/// Matches `lattice_noise` in the shader for gradient noise
+fn gradient(point: Vec3) -> f32 {
+    let cell = point.floor();
+    let local = point - cell;
+    blend(point, |offset| {
+        let slope = random3(cell + offset) * 2.0 - 1.0;
+        slope.dot(local - offset)
+    })
+}
+
+/// Matches `worley_noise` in the shader
+fn worley(point: Vec3) -> f32 {
+    let cell = point.floor();
+    let mut nearest = f32::MAX;
+    for z in -1..=1 {
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let neighbour = cell + Vec3::new(x as f32, y as f32, z as f32);
+                let feature = neighbour + random3(neighbour);
+                nearest = nearest.min(point.distance_squared(feature));
+            }
+        }
+    }
+    1.0 - 2.0 * nearest.sqrt().min(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOISES: [Noise; 3] = [Noise::Value, Noise::Gradient, Noise::Worley];
+
+    /// Points scattered through a few lattice cells, from a fixed seed
+    fn points(count: usize) -> Vec<Vec3> {
+        (0..count)
+            .map(|index| random3(Vec3::new(index as f32, 3.0, -7.0)) * 8.0 - 4.0)
+            .collect()
+    }
+
+    #[test]
+    fn noise_is_deterministic() {
+        for noise in NOISES {
+            for point in points(100) {
+                assert_eq!(noise.sample(point), noise.sample(point));
+            }
+        }
+        assert_eq!(
+            hash3(Vec3::new(-3.0, 5.0, 1.0)),
+            hash3(Vec3::new(-3.0, 5.0, 1.0))
+        );
+        assert_ne!(
+            hash3(Vec3::new(-3.0, 5.0, 1.0)),
+            hash3(Vec3::new(-3.0, 5.0, 2.0))
+        );
+    }
+
+    #[test]
+    fn noise_stays_in_range() {
+        for noise in NOISES {
+            for point in points(2000) {
+                let value = noise.sample(point);
+                assert!(
+                    (-1.0..=1.0).contains(&value),
+                    "{noise:?} is {value} at {point}"
+                );
+            }
+        }
+        for cell in points(200) {
+            let random = random3(cell.floor());
+            assert!(random.cmpge(Vec3::ZERO).all() && random.cmple(Vec3::ONE).all());
+        }
+    }
+
+    #[test]
+    fn lattice_noise_matches_corners() {
+        let cell = Vec3::new(2.0, -1.0, 5.0);
+
+        assert!((value(cell) - (random3(cell).x * 2.0 - 1.0)).abs() < 1e-6);
+        assert!(gradient(cell).abs() < 1e-6);
+    }
+
+    #[test]
+    fn displacement_stays_within_reach() {
+        let displacement = Displacement::new(Noise::Gradient, 0.3, 1.5).with_octaves(4);
+
+        assert!((displacement.reach() - 0.3 * (1.0 + 0.5 + 0.25 + 0.125)).abs() < 1e-6);
+        for point in points(500) {
+            assert!(displacement.sample(point).abs() <= displacement.reach());
+        }
+    }
+
+    #[test]
+    fn displacement_slope_is_bounded() {
+        for noise in NOISES {
+            let displacement = Displacement::new(noise, 0.2, 2.0)
+                .with_octaves(3)
+                .with_lacunarity(2.5)
+                .with_gain(0.4);
+            let slope = displacement.lipschitz() - 1.0;
+
+            for point in points(500) {
+                for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+                    let step = 1e-2;
+                    let change = (displacement.sample(point + axis * step)
+                        - displacement.sample(point))
+                    .abs();
+                    assert!(
+                        change <= slope * step,
+                        "{noise:?} changes by {change} at {point}"
+                    );
+                }
+            }
+        }
+    }
+}
@@ -1,12 +1,20 @@
 use bytemuck::{Pod, Zeroable};
-use glam::{Mat4, Vec3};
+use glam::{Mat4, UVec3, Vec2, Vec3, Vec3Swizzles, Vec4, Vec4Swizzles};
+
+use super::{
+    distance_grid::DistanceGrid, fractal, heightmap::Heightmap, operator, SceneDescriptorBuilder,
+};
+use crate::bvh::Aabb;
 
 #[repr(C, align(16))]
 #[derive(Clone, Copy, Pod, Zeroable)]
 pub struct Sphere {
     pub transform: Mat4,
     pub radius: f32,
-    _padding: [u32; 3],
+    /// Smallest factor the sphere has been scaled by. Scaling stretches distances in its local
+    /// space, so they're multiplied by this to stay a lower bound in world space.
+    pub scale: f32,
+    _padding: [u32; 2],
 }
 
 #[repr(C, align(16))]
@@ -14,7 +22,69 @@ pub struct Sphere {
 pub struct Cuboid {
     pub transform: Mat4,
     pub dimensions: Vec3,
-    _padding: u32,
+    /// Smallest factor the cuboid has been scaled by, as for `Sphere`
+    pub scale: f32,
+}
+
+/// Kinds of `Shape`, matching the `SHAPE_*` constants in the shader
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShapeKind {
+    /// Trilinearly sampled `DistanceGrid`, with the grid's bounds in the first two parameters
+    Grid = 0,
+    /// Terrain following a `Heightmap` across a footprint centred on the origin, solid from its
+    /// surface down to the bottom of its height range. The first parameter holds the
+    /// footprint's size along x and z then the lowest and highest heights, the second the
+    /// number of times the heightmap repeats across each side then its slope.
+    Heightfield = 1,
+    /// The first parameter holds the iteration count, power and size of each fractal. Size
+    /// scales the fractal uniformly, keeping its distance estimate exact.
+    Mandelbulb = 2,
+    /// Scale in place of power, three for the classic sponge
+    MengerSponge = 3,
+    /// Scale in place of power, and the minimum and fixed radii of its sphere fold at the
+    /// start of the second parameter
+    Mandelbox = 4,
+    /// Scale in place of power, two for the classic tetrahedron
+    Sierpinski = 5,
+    /// Operators apply to the operand at `source` among the scene's operands, flagged by kind
+    /// as BVH primitives are. See `Operator` for their parameters.
+    Repeat = 6,
+    RepeatLimited = 7,
+    Polar = 8,
+    Mirror = 9,
+    Twist = 10,
+    Bend = 11,
+    Elongate = 12,
+    Onion = 13,
+    Displace = 14,
+}
+
+impl ShapeKind {
+    pub fn is_operator(self) -> bool {
+        self as u32 >= Self::Repeat as u32
+    }
+
+    pub fn is_fractal(self) -> bool {
+        matches!(
+            self,
+            Self::Mandelbulb | Self::MengerSponge | Self::Mandelbox | Self::Sierpinski
+        )
+    }
+}
+
+/// Objects of the kinds that don't need a buffer of their own, told apart by `kind`. Shapes
+/// sampled from a texture refer to their data by `source`, its index in the scene, and the
+/// region of the atlas holding it is filled in on upload.
+#[repr(C, align(16))]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+pub struct Shape {
+    pub transform: Mat4,
+    pub parameters: [Vec4; 2],
+    pub region_origin: UVec3,
+    pub kind: u32,
+    pub region_size: UVec3,
+    pub source: u32,
 }
 
 impl Sphere {
@@ -22,7 +92,8 @@ impl Sphere {
         Self {
             transform: Mat4::IDENTITY,
             radius,
-            _padding: [0; 3],
+            scale: 1.0,
+            _padding: [0; 2],
         }
     }
 
@@ -32,6 +103,29 @@ impl Sphere {
             ..*self
         }
     }
+
+    /// Scales the sphere about its origin by a factor along each of its axes, stretching it
+    /// into an ellipsoid
+    pub fn scale(&self, v: Vec3) -> Self {
+        Self {
+            transform: Mat4::from_scale(v.recip()) * self.transform,
+            scale: self.scale * v.abs().min_element(),
+            ..*self
+        }
+    }
+
+    pub fn scale_uniform(&self, factor: f32) -> Self {
+        self.scale(Vec3::splat(factor))
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        Aabb::from_local(self.transform, Vec3::splat(self.radius))
+    }
+
+    /// Matches `evaluate_sdf_sphere` in the shader
+    pub fn distance(&self, point: Vec3) -> f32 {
+        (self.transform.transform_point3(point).length() - self.radius) * self.scale
+    }
 }
 
 impl Cuboid {
@@ -39,7 +133,145 @@ impl Cuboid {
         Self {
             transform: Mat4::IDENTITY,
             dimensions,
-            _padding: 0,
+            scale: 1.0,
+        }
+    }
+
+    pub fn translate(&self, v: Vec3) -> Self {
+        Self {
+            transform: Mat4::from_translation(v) * self.transform,
+            ..*self
+        }
+    }
+
+    pub fn rotate(&self, v: Vec3) -> Self {
+        Self {
+            transform: Mat4::from_rotation_x(v.x)
+                * Mat4::from_rotation_y(v.y)
+                * Mat4::from_rotation_z(v.z)
+                * self.transform,
+            ..*self
+        }
+    }
+
+    /// Scales the cuboid about its origin by a factor along each of its axes
+    pub fn scale(&self, v: Vec3) -> Self {
+        Self {
+            transform: Mat4::from_scale(v.recip()) * self.transform,
+            scale: self.scale * v.abs().min_element(),
+            ..*self
+        }
+    }
+
+    pub fn scale_uniform(&self, factor: f32) -> Self {
+        self.scale(Vec3::splat(factor))
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        Aabb::from_local(self.transform, self.dimensions)
+    }
+
+    /// Matches `evaluate_sdf_cuboid` in the shader
+    pub fn distance(&self, point: Vec3) -> f32 {
+        let q = self.transform.transform_point3(point).abs() - self.dimensions;
+        (q.max(Vec3::ZERO).length() + q.max_element().min(0.0)) * self.scale
+    }
+}
+
+impl Shape {
+    /// Samples the grid at `source` in the scene's grids, placed where its local space puts it
+    pub fn grid(source: u32, grid: &DistanceGrid) -> Self {
+        Self {
+            transform: Mat4::IDENTITY,
+            parameters: [grid.bounds.min.extend(0.0), grid.bounds.max.extend(0.0)],
+            region_origin: UVec3::ZERO,
+            kind: ShapeKind::Grid as u32,
+            region_size: grid.resolution,
+            source,
+        }
+    }
+
+    /// Terrain following the heightmap at `source` in the scene's heightmaps, spanning `size`
+    /// along x and z with black at `heights.x` and white at `heights.y`
+    pub fn heightfield(source: u32, heightmap: &Heightmap, size: Vec2, heights: Vec2) -> Self {
+        Self {
+            transform: Mat4::IDENTITY,
+            parameters: [
+                Vec4::new(size.x, size.y, heights.x, heights.y),
+                Vec2::ONE
+                    .extend(heightmap.slope.x)
+                    .extend(heightmap.slope.y),
+            ],
+            region_origin: UVec3::ZERO,
+            kind: ShapeKind::Heightfield as u32,
+            region_size: heightmap.size.extend(1),
+            source,
+        }
+    }
+
+    /// Repeats a heightfield's heightmap `tiling` times across its footprint along x and z
+    pub fn with_tiling(&self, tiling: Vec2) -> Self {
+        let mut parameters = self.parameters;
+        parameters[1] = tiling.extend(parameters[1].z).extend(parameters[1].w);
+        Self {
+            parameters,
+            ..*self
+        }
+    }
+
+    fn fractal(kind: ShapeKind, iterations: u32, power: f32) -> Self {
+        Self {
+            transform: Mat4::IDENTITY,
+            parameters: [Vec4::new(iterations as f32, power, 1.0, 0.0), Vec4::ZERO],
+            region_origin: UVec3::ZERO,
+            kind: kind as u32,
+            region_size: UVec3::ZERO,
+            source: 0,
+        }
+    }
+
+    /// Mandelbulb of the given power, eight giving the familiar bulb, within a radius of two
+    pub fn mandelbulb(power: f32) -> Self {
+        Self::fractal(ShapeKind::Mandelbulb, 8, power)
+    }
+
+    /// Menger sponge filling the cube from -1 to 1, each iteration dividing its sides `scale`
+    /// times
+    pub fn menger_sponge(scale: f32) -> Self {
+        Self::fractal(ShapeKind::MengerSponge, 5, scale)
+    }
+
+    /// Mandelbox of the given scale, around two or minus one and a half for the usual shapes.
+    /// Its size grows as the scale nears one, see `mandelbox_bound`.
+    pub fn mandelbox(scale: f32) -> Self {
+        let mut shape = Self::fractal(ShapeKind::Mandelbox, 12, scale);
+        shape.parameters[1] = Vec4::new(0.5, 1.0, 0.0, 0.0);
+        shape
+    }
+
+    /// Sierpinski tetrahedron with corners at (1, 1, 1), (-1, -1, 1), (1, -1, -1) and
+    /// (-1, 1, -1), each iteration shrinking its copies by `scale`
+    pub fn sierpinski(scale: f32) -> Self {
+        Self::fractal(ShapeKind::Sierpinski, 10, scale)
+    }
+
+    /// Iterations of a fractal, more showing finer detail at a higher cost per step
+    pub fn with_iterations(&self, iterations: u32) -> Self {
+        let mut parameters = self.parameters;
+        parameters[0].x = iterations as f32;
+        Self {
+            parameters,
+            ..*self
+        }
+    }
+
+    /// Scales a fractal uniformly about its origin
+    pub fn with_size(&self, size: f32) -> Self {
+        let mut parameters = self.parameters;
+        parameters[0].z = size;
+        Self {
+            parameters,
+            ..*self
         }
     }
 
@@ -59,4 +291,215 @@ impl Cuboid {
             ..*self
         }
     }
+
+    pub fn shape_kind(&self) -> ShapeKind {
+        match self.kind {
+            1 => ShapeKind::Heightfield,
+            2 => ShapeKind::Mandelbulb,
+            3 => ShapeKind::MengerSponge,
+            4 => ShapeKind::Mandelbox,
+            5 => ShapeKind::Sierpinski,
+            6 => ShapeKind::Repeat,
+            7 => ShapeKind::RepeatLimited,
+            8 => ShapeKind::Polar,
+            9 => ShapeKind::Mirror,
+            10 => ShapeKind::Twist,
+            11 => ShapeKind::Bend,
+            12 => ShapeKind::Elongate,
+            13 => ShapeKind::Onion,
+            14 => ShapeKind::Displace,
+            _ => ShapeKind::Grid,
+        }
+    }
+
+    /// Box a heightfield's terrain stands in
+    fn footprint(&self) -> Aabb {
+        let [first, _] = self.parameters;
+        Aabb {
+            min: Vec3::new(-first.x * 0.5, first.z, -first.y * 0.5),
+            max: Vec3::new(first.x * 0.5, first.w, first.y * 0.5),
+        }
+    }
+
+    fn local_bounds(&self, scene: &SceneDescriptorBuilder) -> Aabb {
+        let [first, second] = self.parameters;
+        let extent = match self.shape_kind() {
+            kind if kind.is_operator() => {
+                let operand = scene.operands.get(self.source);
+                let operand_bounds = operand.map_or(Aabb::EMPTY, |operand| operand.bounds(scene));
+                return operator::bounds(self, operand_bounds);
+            }
+            ShapeKind::Grid => {
+                return Aabb {
+                    min: first.truncate(),
+                    max: second.truncate(),
+                }
+            }
+            ShapeKind::Heightfield => return self.footprint(),
+            ShapeKind::Mandelbulb => fractal::MANDELBULB_BAILOUT,
+            ShapeKind::Mandelbox => fractal::mandelbox_bound(first.y),
+            _ => 1.0,
+        };
+        Aabb {
+            min: Vec3::splat(-extent * first.z),
+            max: Vec3::splat(extent * first.z),
+        }
+    }
+
+    pub fn bounds(&self, scene: &SceneDescriptorBuilder) -> Aabb {
+        let local = self.local_bounds(scene);
+        let center = Mat4::from_translation(-local.center());
+        Aabb::from_local(center * self.transform, (local.max - local.min) * 0.5)
+    }
+
+    /// Matches `evaluate_sdf_heightfield` in the shader
+    fn heightfield_distance(&self, point: Vec3, heightmap: &Heightmap) -> f32 {
+        let bounds = self.footprint();
+        let [first, second] = self.parameters;
+        let (size, heights, tiling) = (first.xy(), first.zw(), second.xy());
+
+        let uv = (point.xz() / size + 0.5) * tiling;
+        let height = heights.x + (heights.y - heights.x) * heightmap.height(uv);
+
+        // Dividing by the steepest the surface gets keeps the vertical distance from
+        // overstepping it sideways
+        let gradient = (heights.y - heights.x) * second.zw() * tiling / size;
+        let surface = (point.y - height) / (1.0 + gradient.length_squared()).sqrt();
+
+        let q = (point - bounds.center()).abs() - (bounds.max - bounds.min) * 0.5;
+        let footprint = q.max(Vec3::ZERO).length() + q.max_element().min(0.0);
+
+        footprint.max(surface)
+    }
+
+    /// Matches `evaluate_fractal` in the shader
+    fn fractal_distance(&self, kind: ShapeKind, point: Vec3) -> f32 {
+        let [first, second] = self.parameters;
+        let (iterations, power, size) = (first.x as u32, first.y, first.z);
+        let point = point / size;
+
+        let distance = match kind {
+            ShapeKind::Mandelbulb => fractal::mandelbulb(point, iterations, power),
+            ShapeKind::MengerSponge => fractal::menger_sponge(point, iterations, power),
+            ShapeKind::Mandelbox => {
+                fractal::mandelbox(point, iterations, power, second.x, second.y)
+            }
+            _ => fractal::sierpinski(point, iterations, power),
+        };
+        distance * size
+    }
+
+    /// Matches `evaluate_sdf_shape` in the shader, reading the data it samples from `scene`
+    pub fn distance(&self, point: Vec3, scene: &SceneDescriptorBuilder) -> f32 {
+        let local = self.transform.transform_point3(point);
+        let source = self.source as usize;
+        let kind = self.shape_kind();
+        let distance = match kind {
+            ShapeKind::Grid => scene.grids.get(source).map(|grid| grid.distance(local)),
+            ShapeKind::Heightfield => scene
+                .heightmaps
+                .get(source)
+                .map(|heightmap| self.heightfield_distance(local, heightmap)),
+            kind if kind.is_operator() => return operator::distance(self, point, scene),
+            _ => Some(self.fractal_distance(kind, local)),
+        };
+        distance.unwrap_or(super::MAX_SIGNED_DISTANCE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{PI, TAU};
+
+    use super::*;
+
+    /// Points spread over the surface of a unit sphere
+    fn directions(count: usize) -> impl Iterator<Item = Vec3> {
+        (0..count).map(move |index| {
+            let z = 1.0 - 2.0 * (index as f32 + 0.5) / count as f32;
+            let angle = index as f32 * PI * (3.0 - 5.0f32.sqrt());
+            let radius = (1.0 - z * z).sqrt();
+            Vec3::new(radius * angle.cos(), radius * angle.sin(), z)
+        })
+    }
+
+    /// Points spread over the faces of a cube from -1 to 1
+    fn cube_surface(steps: u32) -> impl Iterator<Item = Vec3> {
+        (0..6).flat_map(move |face| {
+            (0..steps * steps).map(move |index| {
+                let u = (index % steps) as f32 / (steps - 1) as f32 * 2.0 - 1.0;
+                let v = (index / steps) as f32 / (steps - 1) as f32 * 2.0 - 1.0;
+                let side = if face < 3 { 1.0 } else { -1.0 };
+                match face % 3 {
+                    0 => Vec3::new(side, u, v),
+                    1 => Vec3::new(u, side, v),
+                    _ => Vec3::new(u, v, side),
+                }
+            })
+        })
+    }
+
+    fn samples() -> impl Iterator<Item = Vec3> {
+        directions(200).flat_map(|direction| [0.3, 0.9, 1.6, 3.0].map(|length| direction * length))
+    }
+
+    /// Whether `distance` ever claims more room than there is to the nearest of `surface`,
+    /// which bounds the true distance both inside and out
+    fn assert_conservative(distance: impl Fn(Vec3) -> f32, surface: &[Vec3]) {
+        for point in samples() {
+            let nearest = surface
+                .iter()
+                .map(|surface| point.distance(*surface))
+                .fold(f32::MAX, f32::min);
+            let distance = distance(point);
+            assert!(
+                distance.abs() <= nearest + 1e-3,
+                "{distance} at {point} with the surface within {nearest}"
+            );
+        }
+    }
+
+    #[test]
+    fn uniform_scale_is_exact() {
+        let sphere = Sphere::new(0.5).scale_uniform(2.0);
+        assert!((sphere.distance(Vec3::new(0.0, 3.0, 0.0)) - 2.0).abs() < 1e-6);
+        assert!((sphere.distance(Vec3::ZERO) + 1.0).abs() < 1e-6);
+
+        let cuboid = Cuboid::new(Vec3::splat(0.5)).scale_uniform(-3.0);
+        assert!((cuboid.distance(Vec3::new(2.5, 0.0, 0.0)) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn scale_grows_bounds() {
+        let bounds = Sphere::new(1.0).scale(Vec3::new(2.0, 0.5, 1.0)).bounds();
+
+        assert!(bounds.min.distance(Vec3::new(-2.0, -0.5, -1.0)) < 1e-6);
+        assert!(bounds.max.distance(Vec3::new(2.0, 0.5, 1.0)) < 1e-6);
+    }
+
+    #[test]
+    fn scaled_sphere_never_overestimates() {
+        let sphere = Sphere::new(1.0)
+            .scale(Vec3::new(2.0, 0.5, 1.0))
+            .translate(Vec3::new(0.2, 0.0, -0.1));
+        let to_world = sphere.transform.inverse();
+        let surface: Vec<Vec3> = directions(20000)
+            .map(|direction| to_world.transform_point3(direction))
+            .collect();
+
+        assert_conservative(|point| sphere.distance(point), &surface);
+    }
+
+    #[test]
+    fn scaled_cuboid_never_overestimates() {
+        let cuboid = Cuboid::new(Vec3::ONE)
+            .scale(Vec3::new(0.4, 1.5, 0.8))
+            .rotate(Vec3::new(0.3, TAU / 8.0, 0.0));
+        let to_world = cuboid.transform.inverse();
+        let surface: Vec<Vec3> = cube_surface(60)
+            .map(|point| to_world.transform_point3(point))
+            .collect();
+
+        assert_conservative(|point| cuboid.distance(point), &surface);
+    }
 }
@@ -0,0 +1,560 @@
+use std::f32::consts::TAU;
+
+use glam::{BVec3, Mat4, UVec3, Vec3, Vec3Swizzles, Vec4};
+
+use super::{
+    noise::{Displacement, Noise},
+    objects::{Cuboid, Shape, ShapeKind, Sphere},
+    SceneDescriptorBuilder, MAX_SIGNED_DISTANCE,
+};
+use crate::bvh::{Aabb, CUBOID_FLAG, SHAPE_FLAG};
+
+/// Longest chain of operators `map()` follows, matches `MAX_OPERATOR_DEPTH` in the shader
+pub const MAX_OPERATOR_DEPTH: usize = 8;
+
+/// Changes to the space an operand is evaluated in, or to its distance. Each is applied in the
+/// operator shape's local space, so the shape's transform places the whole result and the
+/// operand's own transform places it within each copy.
+#[derive(Clone, Copy, Debug)]
+pub enum Operator {
+    /// Copies the operand every `period` along each axis without end, zero leaving an axis as
+    /// it is. Copies only stay exact while the operand fits within its cell.
+    Repeat(Vec3),
+    /// Copies the operand every `period` along each axis, `count` times to either side of the
+    /// original
+    RepeatLimited { period: Vec3, count: UVec3 },
+    /// Copies the operand around the y axis, the first copy where it stands
+    Polar(u32),
+    /// Reflects the positive side of each selected axis onto the negative one
+    Mirror(BVec3),
+    /// Turns the operand about the y axis by this many radians per unit along it
+    Twist(f32),
+    /// Bends the operand about the z axis by this many radians per unit along x
+    Bend(f32),
+    /// Pulls the operand's halves apart by this much to either side along each axis,
+    /// stretching its middle to fill the gap
+    Elongate(Vec3),
+    /// Hollows the operand into a shell this thick about its surface
+    Onion(f32),
+    /// Raises and lowers the operand's surface by noise, sampled in the operator's local space
+    Displace(Displacement),
+}
+
+/// Objects only evaluated through the operators referring to them, uploaded after the scene's
+/// own objects of each kind
+#[derive(Clone, Default)]
+pub struct Operands {
+    pub spheres: Vec<Sphere>,
+    pub cuboids: Vec<Cuboid>,
+    pub shapes: Vec<Shape>,
+}
+
+/// Object an operator applies to
+#[derive(Clone, Copy)]
+pub enum Operand {
+    Sphere(Sphere),
+    Cuboid(Cuboid),
+    Shape(Shape),
+}
+
+impl From<Sphere> for Operand {
+    fn from(sphere: Sphere) -> Self {
+        Self::Sphere(sphere)
+    }
+}
+
+impl From<Cuboid> for Operand {
+    fn from(cuboid: Cuboid) -> Self {
+        Self::Cuboid(cuboid)
+    }
+}
+
+impl From<Shape> for Operand {
+    fn from(shape: Shape) -> Self {
+        Self::Shape(shape)
+    }
+}
+
+impl Operands {
+    /// Adds an operand, returning its index flagged with its kind as BVH primitives are
+    pub fn push(&mut self, operand: Operand) -> u32 {
+        match operand {
+            Operand::Sphere(sphere) => {
+                self.spheres.push(sphere);
+                self.spheres.len() as u32 - 1
+            }
+            Operand::Cuboid(cuboid) => {
+                self.cuboids.push(cuboid);
+                (self.cuboids.len() as u32 - 1) | CUBOID_FLAG
+            }
+            Operand::Shape(shape) => {
+                self.shapes.push(shape);
+                (self.shapes.len() as u32 - 1) | SHAPE_FLAG
+            }
+        }
+    }
+
+    pub fn get(&self, source: u32) -> Option<Operand> {
+        let index = (source & !(CUBOID_FLAG | SHAPE_FLAG)) as usize;
+        if source & CUBOID_FLAG != 0 {
+            self.cuboids.get(index).copied().map(Operand::Cuboid)
+        } else if source & SHAPE_FLAG != 0 {
+            self.shapes.get(index).copied().map(Operand::Shape)
+        } else {
+            self.spheres.get(index).copied().map(Operand::Sphere)
+        }
+    }
+}
+
+impl Operand {
+    /// Distance to the operand from a point its operator has warped
+    pub fn distance(&self, point: Vec3, scene: &SceneDescriptorBuilder) -> f32 {
+        match self {
+            Self::Sphere(sphere) => sphere.distance(point),
+            Self::Cuboid(cuboid) => cuboid.distance(point),
+            Self::Shape(shape) => shape.distance(point, scene),
+        }
+    }
+
+    pub fn bounds(&self, scene: &SceneDescriptorBuilder) -> Aabb {
+        match self {
+            Self::Sphere(sphere) => sphere.bounds(),
+            Self::Cuboid(cuboid) => cuboid.bounds(),
+            Self::Shape(shape) => shape.bounds(scene),
+        }
+    }
+}
+
+/// Largest distance from an axis to the corners of `bounds`, given the two other axes
+fn radius(bounds: Aabb, axes: [usize; 2]) -> f32 {
+    let extent = bounds.min.abs().max(bounds.max.abs());
+    (extent[axes[0]].powi(2) + extent[axes[1]].powi(2)).sqrt()
+}
+
+/// Lipschitz constant of turning space about an axis by `rate` radians per unit along another,
+/// at `radius` from the axis
+fn turn_lipschitz(rate: f32, radius: f32) -> f32 {
+    let shear = rate.abs() * radius * 0.5;
+    shear + (1.0 + shear * shear).sqrt()
+}
+
+impl Operator {
+    /// Shape applying the operator to the operand at `source`, whose bounds in the operator's
+    /// local space are `operand_bounds`
+    pub fn shape(self, source: u32, operand_bounds: Aabb) -> Shape {
+        let (kind, parameters) = match self {
+            Self::Repeat(period) => (ShapeKind::Repeat, [period.extend(0.0), Vec4::ZERO]),
+            Self::RepeatLimited { period, count } => (
+                ShapeKind::RepeatLimited,
+                [period.extend(0.0), count.as_vec3().extend(0.0)],
+            ),
+            Self::Polar(copies) => (
+                ShapeKind::Polar,
+                [Vec4::new(copies.max(1) as f32, 0.0, 0.0, 0.0), Vec4::ZERO],
+            ),
+            Self::Mirror(axes) => (
+                ShapeKind::Mirror,
+                [
+                    Vec3::select(axes, Vec3::ONE, Vec3::ZERO).extend(0.0),
+                    Vec4::ZERO,
+                ],
+            ),
+            Self::Twist(rate) => (
+                ShapeKind::Twist,
+                [
+                    Vec4::new(rate, radius(operand_bounds, [0, 2]), 0.0, 0.0),
+                    Vec4::ZERO,
+                ],
+            ),
+            Self::Bend(rate) => (
+                ShapeKind::Bend,
+                [
+                    Vec4::new(rate, radius(operand_bounds, [0, 1]), 0.0, 0.0),
+                    Vec4::ZERO,
+                ],
+            ),
+            Self::Elongate(half) => (ShapeKind::Elongate, [half.extend(0.0), Vec4::ZERO]),
+            Self::Onion(thickness) => (
+                ShapeKind::Onion,
+                [Vec4::new(thickness, 0.0, 0.0, 0.0), Vec4::ZERO],
+            ),
+            Self::Displace(displacement) => (
+                ShapeKind::Displace,
+                [
+                    Vec4::new(
+                        displacement.amplitude,
+                        displacement.frequency,
+                        displacement.octaves as f32,
+                        displacement.lipschitz(),
+                    ),
+                    Vec4::new(
+                        displacement.lacunarity,
+                        displacement.gain,
+                        displacement.noise as u32 as f32,
+                        0.0,
+                    ),
+                ],
+            ),
+        };
+
+        Shape {
+            transform: Mat4::IDENTITY,
+            parameters,
+            region_origin: UVec3::ZERO,
+            kind: kind as u32,
+            region_size: UVec3::ZERO,
+            source,
+        }
+    }
+}
+
+/// Displacement a displacing operator was made from
+fn displacement(shape: &Shape) -> Displacement {
+    let [first, second] = shape.parameters;
+    Displacement {
+        noise: Noise::from_u32(second.z as u32),
+        amplitude: first.x,
+        frequency: first.y,
+        octaves: first.z as u32,
+        lacunarity: second.x,
+        gain: second.y,
+    }
+}
+
+/// Matches `warp` in the shader
+pub fn warp(shape: &Shape, point: Vec3) -> Vec3 {
+    let parameters = shape.parameters[0];
+    match shape.shape_kind() {
+        ShapeKind::Repeat => {
+            let period = parameters.truncate();
+            let cell = Vec3::select(
+                period.cmpgt(Vec3::ZERO),
+                (point / period).round(),
+                Vec3::ZERO,
+            );
+            point - period * cell
+        }
+        ShapeKind::RepeatLimited => {
+            let period = parameters.truncate();
+            let count = shape.parameters[1].truncate();
+            let cell = Vec3::select(
+                period.cmpgt(Vec3::ZERO),
+                (point / period).round().clamp(-count, count),
+                Vec3::ZERO,
+            );
+            point - period * cell
+        }
+        ShapeKind::Polar => {
+            let sector = TAU / parameters.x;
+            let angle = point.z.atan2(point.x);
+            let turned = angle - sector * (angle / sector).round();
+            let radius = point.xz().length();
+            Vec3::new(radius * turned.cos(), point.y, radius * turned.sin())
+        }
+        ShapeKind::Mirror => {
+            Vec3::select(parameters.truncate().cmpgt(Vec3::ZERO), point.abs(), point)
+        }
+        ShapeKind::Twist => {
+            let (sin, cos) = (parameters.x * point.y).sin_cos();
+            Vec3::new(
+                cos * point.x - sin * point.z,
+                point.y,
+                sin * point.x + cos * point.z,
+            )
+        }
+        ShapeKind::Bend => {
+            let (sin, cos) = (parameters.x * point.x).sin_cos();
+            Vec3::new(
+                cos * point.x - sin * point.y,
+                sin * point.x + cos * point.y,
+                point.z,
+            )
+        }
+        ShapeKind::Elongate => {
+            let half = parameters.truncate();
+            point - point.clamp(-half, half)
+        }
+        _ => point,
+    }
+}
+
+/// Matches `finish` in the shader, `point` being in the operator's local space
+pub fn finish(shape: &Shape, point: Vec3, distance: f32) -> f32 {
+    let parameters = shape.parameters[0];
+    match shape.shape_kind() {
+        // Space further from the axis is turned faster, and the way to the surface from `point`
+        // stays within the further of it and the operand
+        ShapeKind::Twist => {
+            distance / turn_lipschitz(parameters.x, parameters.y.max(point.xz().length()))
+        }
+        ShapeKind::Bend => {
+            distance / turn_lipschitz(parameters.x, parameters.y.max(point.xy().length()))
+        }
+        ShapeKind::Onion => distance.abs() - parameters.x,
+        ShapeKind::Displace => (distance - displacement(shape).sample(point)) / parameters.w,
+        _ => distance,
+    }
+}
+
+/// Matches `evaluate_sdf_shape` in the shader, `shape` being an operator
+pub fn distance(shape: &Shape, point: Vec3, scene: &SceneDescriptorBuilder) -> f32 {
+    let mut chain = Vec::with_capacity(MAX_OPERATOR_DEPTH);
+    let mut operator = *shape;
+    let mut warped = point;
+
+    let distance = loop {
+        let local = operator.transform.transform_point3(warped);
+        warped = warp(&operator, local);
+        chain.push((operator, local));
+
+        let operand = scene.operands.get(operator.source);
+        match operand {
+            Some(Operand::Shape(next))
+                if next.shape_kind().is_operator() && chain.len() < MAX_OPERATOR_DEPTH =>
+            {
+                operator = next
+            }
+            // Operators past the end of the chain are left out, as the shader does
+            Some(Operand::Shape(next)) if next.shape_kind().is_operator() => {
+                break MAX_SIGNED_DISTANCE
+            }
+            Some(operand) => break operand.distance(warped, scene),
+            None => break MAX_SIGNED_DISTANCE,
+        }
+    };
+
+    chain
+        .iter()
+        .rev()
+        .fold(distance, |distance, (operator, local)| {
+            finish(operator, *local, distance)
+        })
+}
+
+/// Bounds of the operator's result in its local space, from its operand's
+pub fn bounds(shape: &Shape, operand: Aabb) -> Aabb {
+    let parameters = shape.parameters[0];
+    match shape.shape_kind() {
+        ShapeKind::Repeat => {
+            let repeated = parameters.truncate().cmpgt(Vec3::ZERO);
+            Aabb {
+                min: Vec3::select(repeated, Vec3::splat(-MAX_SIGNED_DISTANCE), operand.min),
+                max: Vec3::select(repeated, Vec3::splat(MAX_SIGNED_DISTANCE), operand.max),
+            }
+        }
+        ShapeKind::RepeatLimited => {
+            let period = parameters.truncate().max(Vec3::ZERO);
+            let reach = period * shape.parameters[1].truncate();
+            Aabb {
+                min: operand.min - reach,
+                max: operand.max + reach,
+            }
+        }
+        ShapeKind::Polar | ShapeKind::Twist => {
+            let radius = radius(operand, [0, 2]);
+            Aabb {
+                min: Vec3::new(-radius, operand.min.y, -radius),
+                max: Vec3::new(radius, operand.max.y, radius),
+            }
+        }
+        ShapeKind::Bend => {
+            let radius = radius(operand, [0, 1]);
+            Aabb {
+                min: Vec3::new(-radius, -radius, operand.min.z),
+                max: Vec3::new(radius, radius, operand.max.z),
+            }
+        }
+        ShapeKind::Mirror => {
+            let mirrored = parameters.truncate().cmpgt(Vec3::ZERO);
+            let extent = operand.min.abs().max(operand.max.abs());
+            Aabb {
+                min: Vec3::select(mirrored, -extent, operand.min),
+                max: Vec3::select(mirrored, extent, operand.max),
+            }
+        }
+        ShapeKind::Elongate => Aabb {
+            min: operand.min - parameters.truncate(),
+            max: operand.max + parameters.truncate(),
+        },
+        ShapeKind::Onion => Aabb {
+            min: operand.min - Vec3::splat(parameters.x.max(0.0)),
+            max: operand.max + Vec3::splat(parameters.x.max(0.0)),
+        },
+        ShapeKind::Displace => {
+            let reach = Vec3::splat(displacement(shape).reach());
+            Aabb {
+                min: operand.min - reach,
+                max: operand.max + reach,
+            }
+        }
+        _ => operand,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points on a lattice through a cube of side `size` about the origin
+    fn lattice(size: f32, steps: u32) -> impl Iterator<Item = Vec3> {
+        (0..steps.pow(3)).map(move |index| {
+            let cell = UVec3::new(
+                index % steps,
+                index / steps % steps,
+                index / (steps * steps),
+            );
+            (cell.as_vec3() / (steps - 1) as f32 - 0.5) * size
+        })
+    }
+
+    fn operators() -> Vec<Operator> {
+        vec![
+            Operator::Repeat(Vec3::new(1.5, 0.0, 0.0)),
+            Operator::RepeatLimited {
+                period: Vec3::new(0.0, 1.5, 0.0),
+                count: UVec3::new(0, 1, 0),
+            },
+            Operator::Polar(5),
+            Operator::Mirror(BVec3::new(true, false, true)),
+            Operator::Twist(1.5),
+            Operator::Bend(0.8),
+            Operator::Elongate(Vec3::new(0.5, 0.0, 0.25)),
+            Operator::Onion(0.1),
+            Operator::Displace(Displacement::new(Noise::Gradient, 0.1, 2.0).with_octaves(3)),
+            Operator::Displace(Displacement::new(Noise::Worley, 0.05, 4.0)),
+        ]
+    }
+
+    /// Each operator applied to a box, off centre so mirroring and polar copies show, except
+    /// for repetition where it has to fit within its cell
+    fn operated(operator: Operator) -> (SceneDescriptorBuilder, Shape) {
+        let mut scene = SceneDescriptorBuilder::default();
+        let offset = match operator {
+            Operator::Repeat(_) | Operator::RepeatLimited { .. } => Vec3::ZERO,
+            _ => Vec3::new(-0.8, 0.0, 0.0),
+        };
+        let cuboid = Cuboid::new(Vec3::new(0.3, 0.3, 0.2)).translate(offset);
+        let shape = scene.operate(operator, cuboid);
+        (scene, shape)
+    }
+
+    #[test]
+    fn repeat_wraps_into_nearest_cell() {
+        let (_, shape) = operated(Operator::Repeat(Vec3::new(1.0, 0.0, 2.0)));
+
+        let warped = warp(&shape, Vec3::new(2.3, 7.0, -3.1));
+        assert!(warped.distance(Vec3::new(0.3, 7.0, 0.9)) < 1e-5);
+    }
+
+    #[test]
+    fn repeat_limited_stops_at_last_copy() {
+        let (_, shape) = operated(Operator::RepeatLimited {
+            period: Vec3::new(1.0, 0.0, 0.0),
+            count: UVec3::new(2, 0, 0),
+        });
+
+        assert!(warp(&shape, Vec3::new(1.2, 0.0, 0.0)).distance(Vec3::new(0.2, 0.0, 0.0)) < 1e-5);
+        assert!(warp(&shape, Vec3::new(10.0, 0.0, 0.0)).distance(Vec3::new(8.0, 0.0, 0.0)) < 1e-5);
+    }
+
+    #[test]
+    fn polar_turns_copies_onto_first() {
+        let (_, shape) = operated(Operator::Polar(4));
+        let point = Vec3::new(1.0, 0.5, 0.2);
+
+        for copy in 0..4 {
+            let turned = Mat4::from_rotation_y(copy as f32 * TAU / 4.0).transform_point3(point);
+            assert!(warp(&shape, turned).distance(point) < 1e-5);
+        }
+    }
+
+    #[test]
+    fn mirror_only_reflects_selected_axes() {
+        let (_, shape) = operated(Operator::Mirror(BVec3::new(true, false, true)));
+
+        assert_eq!(
+            warp(&shape, Vec3::new(-1.0, -2.0, -3.0)),
+            Vec3::new(1.0, -2.0, 3.0)
+        );
+    }
+
+    #[test]
+    fn twist_and_bend_keep_distance_from_axis() {
+        let point = Vec3::new(0.6, 0.4, -0.3);
+
+        let (_, twist) = operated(Operator::Twist(1.5));
+        let twisted = warp(&twist, point);
+        assert!((twisted.xz().length() - point.xz().length()).abs() < 1e-5);
+        assert_eq!(twisted.y, point.y);
+
+        let (_, bend) = operated(Operator::Bend(0.8));
+        let bent = warp(&bend, point);
+        assert!((bent.xy().length() - point.xy().length()).abs() < 1e-5);
+        assert_eq!(bent.z, point.z);
+    }
+
+    #[test]
+    fn elongate_collapses_middle() {
+        let (_, shape) = operated(Operator::Elongate(Vec3::new(0.5, 0.0, 0.25)));
+
+        assert_eq!(warp(&shape, Vec3::new(0.4, 0.0, -0.2)), Vec3::ZERO);
+        assert!(
+            warp(&shape, Vec3::new(0.7, 0.1, -0.5)).distance(Vec3::new(0.2, 0.1, -0.25)) < 1e-5
+        );
+    }
+
+    #[test]
+    fn bounds_hold_the_surface() {
+        for operator in operators() {
+            let (scene, shape) = operated(operator);
+            let bounds = shape.bounds(&scene);
+
+            for point in lattice(6.0, 40) {
+                let distance = shape.distance(point, &scene);
+                if distance <= 0.0 {
+                    assert!(
+                        bounds.distance(point) <= 1e-4,
+                        "{operator:?} is inside at {point} beyond {bounds:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn distances_never_overestimate() {
+        // Every point inside is at least as far as the surface, so bounds the true distance
+        for operator in operators() {
+            let (scene, shape) = operated(operator);
+            let inside: Vec<Vec3> = lattice(6.0, 48)
+                .filter(|point| shape.distance(*point, &scene) <= 0.0)
+                .collect();
+            assert!(!inside.is_empty(), "{operator:?} has no inside");
+
+            for point in lattice(7.0, 12) {
+                let distance = shape.distance(point, &scene);
+                let bound = inside
+                    .iter()
+                    .map(|inside| point.distance(*inside))
+                    .fold(f32::MAX, f32::min);
+                assert!(
+                    distance <= bound + 1e-4,
+                    "{operator:?} gives {distance} at {point}, inside within {bound}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn long_chains_are_cut_short() {
+        let mut scene = SceneDescriptorBuilder::default();
+        let mut shape = scene.operate(Operator::Onion(0.1), Sphere::new(1.0));
+        for _ in 1..MAX_OPERATOR_DEPTH {
+            shape = scene.operate(Operator::Mirror(BVec3::TRUE), shape);
+        }
+        assert!(shape.distance(Vec3::ZERO, &scene) < MAX_SIGNED_DISTANCE);
+
+        let shape = scene.operate(Operator::Mirror(BVec3::TRUE), shape);
+        assert_eq!(shape.distance(Vec3::ZERO, &scene), MAX_SIGNED_DISTANCE);
+    }
+}
@@ -0,0 +1,63 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use image::RgbaImage;
+
+use crate::camera::Camera;
+
+/// `<prefix>-<unix time in milliseconds>.png` in the working directory
+pub fn timestamped_path(prefix: &str) -> PathBuf {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_millis());
+    PathBuf::from(format!("{prefix}-{millis}.png"))
+}
+
+/// PNG text chunks describing how an image was rendered
+pub fn metadata(scene_name: &str, camera: &Camera) -> Vec<(&'static str, String)> {
+    let position = camera.position;
+    let orientation = camera.orientation;
+    vec![
+        ("Scene", scene_name.to_owned()),
+        (
+            "Camera Position",
+            format!("{} {} {}", position.x, position.y, position.z),
+        ),
+        (
+            "Camera Orientation",
+            format!(
+                "{} {} {} {}",
+                orientation.x, orientation.y, orientation.z, orientation.w
+            ),
+        ),
+        ("Camera Fov", camera.fov.to_string()),
+        ("Software", "ray-marcher".to_owned()),
+    ]
+}
+
+/// Writes an RGBA PNG with the given text chunks, which `image` has no way to add
+pub fn save_png(path: &Path, image: &RgbaImage, text: &[(&str, String)]) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        image.width(),
+        image.height(),
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+
+    for (keyword, value) in text {
+        encoder
+            .add_text_chunk(keyword.to_string(), value.clone())
+            .map_err(io::Error::other)?;
+    }
+
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(image.as_raw()))
+        .map_err(io::Error::other)
+}
@@ -0,0 +1,28 @@
+// Compute entry point baking map() into a grid of samples, appended to frag.wgsl so it shares
+// the scene code
+
+const BAKE_WORKGROUP_SIZE = 4u;
+
+struct Bake {
+    origin: vec3<f32>,
+    cell_size: vec3<f32>,
+    resolution: vec3<u32>,
+}
+
+@group(2) @binding(0)
+var<uniform> bake: Bake;
+
+// x varies fastest, then y, then z, as in DistanceGrid
+@group(2) @binding(1)
+var<storage, read_write> bake_values: array<f32>;
+
+@compute @workgroup_size(BAKE_WORKGROUP_SIZE, BAKE_WORKGROUP_SIZE, BAKE_WORKGROUP_SIZE)
+fn bake_main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+    if any(global_id >= bake.resolution) {
+        return;
+    }
+
+    let point = bake.origin + vec3<f32>(global_id) * bake.cell_size;
+    let index = global_id.x + bake.resolution.x * (global_id.y + bake.resolution.y * global_id.z);
+    bake_values[index] = map(point);
+}
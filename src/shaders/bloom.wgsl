@@ -0,0 +1,92 @@
+struct Settings {
+    bloom_threshold: f32,
+    bloom_knee: f32,
+    bloom_intensity: f32,
+    focus_distance: f32,
+    aperture: f32,
+    max_blur: f32,
+    chromatic_aberration: f32,
+    vignette_strength: f32,
+    vignette_radius: f32,
+    enabled: u32,
+}
+
+const BLOOM = 1u;
+const DEPTH_OF_FIELD = 2u;
+const CHROMATIC_ABERRATION = 4u;
+const VIGNETTE = 8u;
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+
+@group(0) @binding(1)
+var source_sampler: sampler;
+
+@group(0) @binding(2)
+var<uniform> settings: Settings;
+
+struct Input {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// 13 tap downsample filter, reduces the flickering of a plain box filter
+fn downsample_source(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+
+    let a = textureSample(source, source_sampler, uv + texel * vec2(-2.0, -2.0)).rgb;
+    let b = textureSample(source, source_sampler, uv + texel * vec2(0.0, -2.0)).rgb;
+    let c = textureSample(source, source_sampler, uv + texel * vec2(2.0, -2.0)).rgb;
+    let d = textureSample(source, source_sampler, uv + texel * vec2(-2.0, 0.0)).rgb;
+    let e = textureSample(source, source_sampler, uv).rgb;
+    let f = textureSample(source, source_sampler, uv + texel * vec2(2.0, 0.0)).rgb;
+    let g = textureSample(source, source_sampler, uv + texel * vec2(-2.0, 2.0)).rgb;
+    let h = textureSample(source, source_sampler, uv + texel * vec2(0.0, 2.0)).rgb;
+    let i = textureSample(source, source_sampler, uv + texel * vec2(2.0, 2.0)).rgb;
+    let j = textureSample(source, source_sampler, uv + texel * vec2(-1.0, -1.0)).rgb;
+    let k = textureSample(source, source_sampler, uv + texel * vec2(1.0, -1.0)).rgb;
+    let l = textureSample(source, source_sampler, uv + texel * vec2(-1.0, 1.0)).rgb;
+    let m = textureSample(source, source_sampler, uv + texel * vec2(1.0, 1.0)).rgb;
+
+    return e * 0.125
+        + (a + c + g + i) * 0.03125
+        + (b + d + f + h) * 0.0625
+        + (j + k + l + m) * 0.125;
+}
+
+@fragment
+fn prefilter(in: Input) -> @location(0) vec4<f32> {
+    let color = downsample_source(in.uv);
+    let brightness = max(color.r, max(color.g, color.b));
+
+    // Quadratic soft knee around the threshold
+    let knee = settings.bloom_threshold * settings.bloom_knee;
+    var soft = clamp(brightness - settings.bloom_threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 0.00001);
+    let contribution = max(soft, brightness - settings.bloom_threshold) / max(brightness, 0.00001);
+
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+@fragment
+fn downsample(in: Input) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample_source(in.uv), 1.0);
+}
+
+// 3x3 tent filter, blended additively into the next larger level
+@fragment
+fn upsample(in: Input) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+
+    var color = textureSample(source, source_sampler, in.uv).rgb * 4.0;
+    color += textureSample(source, source_sampler, in.uv + texel * vec2(-1.0, 0.0)).rgb * 2.0;
+    color += textureSample(source, source_sampler, in.uv + texel * vec2(1.0, 0.0)).rgb * 2.0;
+    color += textureSample(source, source_sampler, in.uv + texel * vec2(0.0, -1.0)).rgb * 2.0;
+    color += textureSample(source, source_sampler, in.uv + texel * vec2(0.0, 1.0)).rgb * 2.0;
+    color += textureSample(source, source_sampler, in.uv + texel * vec2(-1.0, -1.0)).rgb;
+    color += textureSample(source, source_sampler, in.uv + texel * vec2(1.0, -1.0)).rgb;
+    color += textureSample(source, source_sampler, in.uv + texel * vec2(-1.0, 1.0)).rgb;
+    color += textureSample(source, source_sampler, in.uv + texel * vec2(1.0, 1.0)).rgb;
+
+    return vec4<f32>(color / 16.0, 1.0);
+}
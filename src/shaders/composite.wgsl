@@ -0,0 +1,62 @@
+struct Settings {
+    bloom_threshold: f32,
+    bloom_knee: f32,
+    bloom_intensity: f32,
+    focus_distance: f32,
+    aperture: f32,
+    max_blur: f32,
+    chromatic_aberration: f32,
+    vignette_strength: f32,
+    vignette_radius: f32,
+    enabled: u32,
+}
+
+const BLOOM = 1u;
+const DEPTH_OF_FIELD = 2u;
+const CHROMATIC_ABERRATION = 4u;
+const VIGNETTE = 8u;
+
+@group(0) @binding(0)
+var scene: texture_2d<f32>;
+
+@group(0) @binding(1)
+var bloom: texture_2d<f32>;
+
+@group(0) @binding(2)
+var source_sampler: sampler;
+
+@group(0) @binding(3)
+var<uniform> settings: Settings;
+
+struct Input {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+fn sample_hdr(uv: vec2<f32>) -> vec3<f32> {
+    var color = textureSample(scene, source_sampler, uv).rgb;
+    if (settings.enabled & BLOOM) != 0u {
+        color += textureSample(bloom, source_sampler, uv).rgb * settings.bloom_intensity;
+    }
+    return color;
+}
+
+@fragment
+fn main(in: Input) -> @location(0) vec4<f32> {
+    let centered = in.uv - vec2<f32>(0.5);
+
+    var color = sample_hdr(in.uv);
+
+    if (settings.enabled & CHROMATIC_ABERRATION) != 0u {
+        let offset = centered * settings.chromatic_aberration;
+        color.r = sample_hdr(in.uv + offset).r;
+        color.b = sample_hdr(in.uv - offset).b;
+    }
+
+    if (settings.enabled & VIGNETTE) != 0u {
+        let distance = length(centered) * sqrt(2.0);
+        color *= 1.0 - settings.vignette_strength * smoothstep(settings.vignette_radius, 1.0, distance);
+    }
+
+    return vec4<f32>(sqrt(max(color, vec3<f32>(0.0))), 1.0);
+}
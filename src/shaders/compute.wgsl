@@ -0,0 +1,144 @@
+// Compute entry point for the main pass, appended to frag.wgsl so it shares the scene code.
+// Each workgroup renders an 8x8 tile, first culling the scene's objects against the tile's
+// frustum into workgroup memory so primary rays only march objects they can hit.
+
+const TILE_SIZE = 8u;
+const MAX_TILE_OBJECTS = 128u;
+
+// World space bounding sphere of every object, spheres first then cuboids then shapes
+@group(0) @binding(12)
+var<storage, read> object_bounds: array<vec4<f32>>;
+
+@group(2) @binding(0)
+var output_color: texture_storage_2d<rgba16float, write>;
+
+@group(2) @binding(1)
+var output_depth: texture_storage_2d<r32float, write>;
+
+var<workgroup> tile_objects: array<u32, MAX_TILE_OBJECTS>;
+var<workgroup> tile_object_count: atomic<u32>;
+var<workgroup> tile_object_total: u32;
+
+// Inward facing normals of the planes bounding the tile's view frustum
+var<workgroup> tile_planes: array<vec3<f32>, 4>;
+
+fn tile_object(index: u32) -> u32 {
+    if index < scene.sphere_count {
+        return index;
+    }
+    let cuboid = index - scene.sphere_count;
+    if cuboid < scene.cuboid_count {
+        return cuboid | CUBOID_FLAG;
+    }
+    return (cuboid - scene.cuboid_count) | SHAPE_FLAG;
+}
+
+fn evaluate_sdf_tile(point: vec3<f32>, object_count: u32) -> f32 {
+    var min_dist = MAX_SIGNED_DISTANCE;
+
+    for (var i = 0u; i < object_count; i++) {
+        min_dist = min(min_dist, evaluate_sdf_primitive(tile_objects[i], point));
+    }
+
+    return min_dist;
+}
+
+// Same as march, marching only the objects within the tile
+fn march_tile(ray_origin: vec3f, ray_direction: vec3f, start: f32, object_count: u32) -> March {
+    var state = march_state(start);
+    var steps:u32 = 0u;
+
+    loop {
+        let point = ray_origin + (ray_direction * state.ray_length);
+        let min_signed_distance = evaluate_sdf_tile(point, object_count);
+        if state.ray_length > CLIP_FAR { break;}
+        if march_step(&state, min_signed_distance) { break; }
+        steps++;
+        if steps > settings.max_march_steps {break;}
+    }
+
+    return March(ray_origin + ray_direction * state.ray_length, steps);
+}
+
+fn in_tile_frustum(bounds: vec4<f32>) -> bool {
+    let offset = bounds.xyz - camera.position;
+    for (var i = 0u; i < 4u; i++) {
+        let normal = tile_planes[i];
+        if dot(normal, offset) < -bounds.w * length(normal) {
+            return false;
+        }
+    }
+    return true;
+}
+
+@compute @workgroup_size(TILE_SIZE, TILE_SIZE)
+fn compute_main(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(workgroup_id) workgroup_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    if local_index == 0u {
+        atomicStore(&tile_object_count, 0u);
+
+        // Widened by a pixel so jittered rays stay inside
+        let tile_min = vec2<f32>(workgroup_id.xy * TILE_SIZE) - vec2<f32>(1.0);
+        let tile_max = vec2<f32>((workgroup_id.xy + vec2<u32>(1u)) * TILE_SIZE) + vec2<f32>(1.0);
+
+        let top_left = camera_ray(tile_min).direction;
+        let top_right = camera_ray(vec2<f32>(tile_max.x, tile_min.y)).direction;
+        let bottom_left = camera_ray(vec2<f32>(tile_min.x, tile_max.y)).direction;
+        let bottom_right = camera_ray(tile_max).direction;
+
+        tile_planes[0] = cross(top_right, top_left);
+        tile_planes[1] = cross(bottom_right, top_right);
+        tile_planes[2] = cross(bottom_left, bottom_right);
+        tile_planes[3] = cross(top_left, bottom_left);
+    }
+
+    workgroupBarrier();
+
+    let total_objects = scene.sphere_count + scene.cuboid_count + scene.shape_count;
+    for (var i = local_index; i < total_objects; i += TILE_SIZE * TILE_SIZE) {
+        if in_tile_frustum(object_bounds[i]) {
+            let slot = atomicAdd(&tile_object_count, 1u);
+            if slot < MAX_TILE_OBJECTS {
+                tile_objects[slot] = tile_object(i);
+            }
+        }
+    }
+
+    workgroupBarrier();
+
+    if local_index == 0u {
+        tile_object_total = atomicLoad(&tile_object_count);
+    }
+
+    let object_count = workgroupUniformLoad(&tile_object_total);
+
+    let size = vec2<u32>(dimensions.xy);
+    if any(global_id.xy >= size) {
+        return;
+    }
+
+    let screen_cords = vec2<f32>(global_id.xy) + vec2<f32>(0.5);
+    let ray = camera_ray(screen_cords + jitter.xy);
+
+    let start = primary_start(screen_cords);
+
+    var hit: March;
+    if object_count > MAX_TILE_OBJECTS {
+        // Too many objects to cache, fall back to the whole scene
+        hit = march(ray.origin, ray.direction, start);
+    } else if object_count == 0u {
+        // Nothing to hit within the tile, skip marching entirely
+        hit = March(ray.origin + ray.direction * CLIP_FAR, 0u);
+    } else {
+        hit = march_tile(ray.origin, ray.direction, start, object_count);
+    }
+    record_march(hit.steps);
+
+    let output = shade_hit(ray, hit, screen_cords);
+
+    textureStore(output_color, global_id.xy, output.color);
+    textureStore(output_depth, global_id.xy, vec4<f32>(output.depth, 0.0, 0.0, 0.0));
+}
@@ -0,0 +1,74 @@
+struct Settings {
+    bloom_threshold: f32,
+    bloom_knee: f32,
+    bloom_intensity: f32,
+    focus_distance: f32,
+    aperture: f32,
+    max_blur: f32,
+    chromatic_aberration: f32,
+    vignette_strength: f32,
+    vignette_radius: f32,
+    enabled: u32,
+}
+
+const BLOOM = 1u;
+const DEPTH_OF_FIELD = 2u;
+const CHROMATIC_ABERRATION = 4u;
+const VIGNETTE = 8u;
+
+const DOF_SAMPLES = 48u;
+const GOLDEN_ANGLE: f32 = 2.39996323;
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+
+@group(0) @binding(1)
+var depth: texture_2d<f32>;
+
+@group(0) @binding(2)
+var source_sampler: sampler;
+
+@group(0) @binding(3)
+var<uniform> settings: Settings;
+
+struct Input {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// Thin lens circle of confusion in pixels for a given march depth
+fn circle_of_confusion(coords: vec2<i32>) -> f32 {
+    let d = max(textureLoad(depth, coords, 0).r, 0.0001);
+    let coc = settings.aperture * abs(d - settings.focus_distance) / d;
+    return clamp(coc, 0.0, 1.0) * settings.max_blur;
+}
+
+@fragment
+fn main(in: Input) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(source));
+    let max_coords = vec2<i32>(size) - vec2<i32>(1);
+    let center = vec2<i32>(in.position.xy);
+    let center_coc = circle_of_confusion(center);
+
+    var color = textureSampleLevel(source, source_sampler, in.uv, 0.0).rgb;
+    var total = 1.0;
+
+    // Gather along a golden angle spiral, weighting each sample by whether its
+    // own blur reaches this pixel so sharp foreground does not bleed.
+    for (var i = 1u; i < DOF_SAMPLES; i++) {
+        let radius = settings.max_blur * sqrt(f32(i) / f32(DOF_SAMPLES));
+        if radius > center_coc + 1.0 {
+            break;
+        }
+
+        let angle = f32(i) * GOLDEN_ANGLE;
+        let offset = vec2<f32>(cos(angle), sin(angle)) * radius;
+        let coords = clamp(center + vec2<i32>(offset), vec2<i32>(0), max_coords);
+
+        let weight = clamp(circle_of_confusion(coords) - radius + 1.0, 0.0, 1.0);
+        color += textureSampleLevel(source, source_sampler, in.uv + offset / size, 0.0).rgb * weight;
+        total += weight;
+    }
+
+    return vec4<f32>(color / total, 1.0);
+}
@@ -1,7 +1,6 @@
 const MAX_LIGHTS = 8u;
 
 const MAX_SIGNED_DISTANCE = 10000.0;
-const MAX_MARCH_STEPS = 255u;
 
 const CLIP_NEAR:f32 = 0.001;
 const CLIP_FAR:f32 = 100.0;
@@ -11,8 +10,54 @@ const AO_DISTANCE: f32 = 0.25;
 
 const THRESHOLD:f32 = 0.00001;
 
+const MARCH_SPHERE = 0u;
+const MARCH_OVER_RELAXED = 1u;
+const MARCH_ENHANCED = 2u;
+
+const DEBUG_NONE = 0u;
+const DEBUG_STEPS = 1u;
+const DEBUG_NORMALS = 2u;
+const DEBUG_DEPTH = 3u;
+const DEBUG_AMBIENT_OCCLUSION = 4u;
+const DEBUG_SHADOW = 5u;
+const DEBUG_OBJECT_ID = 6u;
+const DEBUG_MATERIAL_ID = 7u;
+
 const MAX_RECUR_DEPTH = 8;
 
+const MAX_MEDIA = 4u;
+
+const CONE_TILE_SIZE = 8u;
+
+const CUBOID_FLAG = 0x80000000u;
+const SHAPE_FLAG = 0x40000000u;
+const PRIMITIVE_INDEX_MASK = 0x3fffffffu;
+const SHAPE_GRID = 0u;
+const SHAPE_HEIGHTFIELD = 1u;
+const SHAPE_MANDELBULB = 2u;
+const SHAPE_MENGER_SPONGE = 3u;
+const SHAPE_MANDELBOX = 4u;
+const SHAPE_SIERPINSKI = 5u;
+const SHAPE_REPEAT = 6u;
+const SHAPE_REPEAT_LIMITED = 7u;
+const SHAPE_POLAR = 8u;
+const SHAPE_MIRROR = 9u;
+const SHAPE_TWIST = 10u;
+const SHAPE_BEND = 11u;
+const SHAPE_ELONGATE = 12u;
+const SHAPE_ONION = 13u;
+const SHAPE_DISPLACE = 14u;
+const NOISE_VALUE = 0u;
+const NOISE_GRADIENT = 1u;
+const NOISE_WORLEY = 2u;
+const OCTAVE_OFFSET = vec3<f32>(17.31, 5.93, 11.47);
+const MAX_OPERATOR_DEPTH = 8u;
+const TAU: f32 = 6.283185307;
+const MANDELBULB_BAILOUT: f32 = 2.0;
+const BVH_STACK_SIZE = 32u;
+const VOLUME_STEPS = 48u;
+const VOLUME_DISTANCE: f32 = 100.0;
+
 @group(0) @binding(0) 
 var<uniform> dimensions: vec4<f32>;
 
@@ -26,10 +71,47 @@ var<uniform> lights: Lights;
 var<uniform> camera: Camera;
 
 @group(0) @binding(4)
-var<uniform> cuboids: array<Cuboid, 2>;
+var<storage, read> cuboids: array<Cuboid>;
 
 @group(0) @binding(5)
-var<uniform> spheres: array<Sphere, 1>;
+var<storage, read> spheres: array<Sphere>;
+
+@group(0) @binding(6)
+var<uniform> fog: Fog;
+
+@group(0) @binding(7)
+var<uniform> media: array<Medium, MAX_MEDIA>;
+
+// Sub-pixel offset of the ray in pixels, xy
+@group(0) @binding(8)
+var<uniform> jitter: vec4<f32>;
+
+@group(0) @binding(9)
+var<uniform> settings: RenderSettings;
+
+@group(0) @binding(10)
+var<storage, read> bvh_nodes: array<BvhNode>;
+
+@group(0) @binding(11)
+var<storage, read> bvh_primitives: array<u32>;
+
+@group(0) @binding(13)
+var<storage, read> shapes: array<Shape>;
+
+// Distance grids stacked along z, each shape's region given by its origin and size
+@group(0) @binding(14)
+var volume_atlas: texture_3d<f32>;
+
+// Heightmaps stacked along y, each shape's region given by its origin and size
+@group(0) @binding(15)
+var heightmap_atlas: texture_2d<f32>;
+
+// Conservative distance primary rays can start from, one texel per cone tile
+@group(1) @binding(0)
+var cone_distance: texture_2d<f32>;
+
+@group(1) @binding(1)
+var<storage, read_write> march_statistics: MarchStatistics;
 
 
 struct Camera {
@@ -40,9 +122,36 @@ struct Camera {
     clip_far: f32,
 }
 
+struct RenderSettings {
+    max_march_steps: u32,
+    use_bvh: u32,
+    cone_prepass: u32,
+    collect_stats: u32,
+    march_strategy: u32,
+    relaxation: f32,
+    pixel_footprint: f32,
+    debug_view: u32,
+}
+
+struct MarchStatistics {
+    pixels: atomic<u32>,
+    primary_steps: atomic<u32>,
+}
+
+// Leaves have a non zero count of primitives starting at left_or_first,
+// internal nodes have their children at left_or_first and left_or_first + 1
+struct BvhNode {
+    min: vec3<f32>,
+    left_or_first: u32,
+    max: vec3<f32>,
+    count: u32,
+}
+
 struct Scene {
     sphere_count: u32,
     cuboid_count: u32,
+    medium_count: u32,
+    shape_count: u32,
 }
 
 struct Light {
@@ -56,14 +165,49 @@ struct Lights {
     lights: array<Light, MAX_LIGHTS>
 }
 
+// Distances in an object's local space are multiplied by the smallest factor it's been scaled
+// by, keeping them a lower bound in world space
 struct Sphere {
     transform: mat4x4<f32>,
     radius: f32,
+    scale: f32,
 }
 
 struct Cuboid {
     transform: mat4x4<f32>,
     dimensions: vec3<f32>,
+    scale: f32,
+}
+
+struct Shape {
+    transform: mat4x4<f32>,
+    parameters: array<vec4<f32>, 2>,
+    region_origin: vec3<u32>,
+    kind: u32,
+    region_size: vec3<u32>,
+    source: u32,
+}
+
+struct Fog {
+    color: vec3<f32>,
+    density: f32,
+    height_falloff: f32,
+    base_height: f32,
+    absorption: f32,
+}
+
+struct Medium {
+    transform: mat4x4<f32>,
+    dimensions: vec3<f32>,
+    density: f32,
+    scattering: vec3<f32>,
+    absorption: f32,
+}
+
+// Scattering and extinction coefficients of the media at a point
+struct MediaSample {
+    scattering: vec3<f32>,
+    extinction: vec3<f32>,
 }
 
 struct ViewRay {
@@ -127,7 +271,7 @@ fn trace_shadow(point:vec3<f32>, light: Light) -> f32 {
     let normal = normalize(light.position - point);
     var t = 0.01;
 
-    for(var i = 0u; i < MAX_MARCH_STEPS; i++){
+    for(var i = 0u; i < settings.max_march_steps; i++){
 
         let h = map(point + (normal * t));
 
@@ -146,16 +290,534 @@ fn trace_shadow(point:vec3<f32>, light: Light) -> f32 {
 
 fn evaluate_sdf_sphere(sphere: Sphere, point: vec3<f32>) -> f32 {
     let transformed_point = sphere.transform * vec4f(point, 1.0);
-    return length(transformed_point.xyz) - sphere.radius;
+    return (length(transformed_point.xyz) - sphere.radius) * sphere.scale;
 }
 
 fn evaluate_sdf_cuboid(cuboid: Cuboid, point: vec3<f32>) -> f32 {
     let transformed_point =  cuboid.transform * vec4f(point, 1.0);
     let q = abs(transformed_point.xyz) - cuboid.dimensions;
-    return length(max(q, vec3<f32>(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0);
+    return (length(max(q, vec3<f32>(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0)) * cuboid.scale;
+}
+
+fn grid_value(shape: Shape, texel: vec3<u32>) -> f32 {
+    return textureLoad(volume_atlas, shape.region_origin + texel, 0).r;
+}
+
+// Interpolates the grid's samples trilinearly. Beyond the grid's box the distance to the box is
+// combined with the sample at its nearest point, which never overshoots as the surface lies
+// within the box.
+fn evaluate_sdf_grid(shape: Shape, point: vec3<f32>) -> f32 {
+    if any(shape.region_size < vec3<u32>(2u)) {
+        return MAX_SIGNED_DISTANCE;
+    }
+
+    let bounds_min = shape.parameters[0].xyz;
+    let bounds_max = shape.parameters[1].xyz;
+    let clamped = clamp(point, bounds_min, bounds_max);
+    let cell = (clamped - bounds_min) / (bounds_max - bounds_min) * vec3<f32>(shape.region_size - 1u);
+    let base = min(vec3<u32>(floor(cell)), shape.region_size - 2u);
+    let t = cell - vec3<f32>(base);
+
+    let y0 = mix(
+        mix(grid_value(shape, base), grid_value(shape, base + vec3<u32>(1u, 0u, 0u)), t.x),
+        mix(grid_value(shape, base + vec3<u32>(0u, 1u, 0u)), grid_value(shape, base + vec3<u32>(1u, 1u, 0u)), t.x),
+        t.y
+    );
+    let y1 = mix(
+        mix(grid_value(shape, base + vec3<u32>(0u, 0u, 1u)), grid_value(shape, base + vec3<u32>(1u, 0u, 1u)), t.x),
+        mix(grid_value(shape, base + vec3<u32>(0u, 1u, 1u)), grid_value(shape, base + vec3<u32>(1u, 1u, 1u)), t.x),
+        t.y
+    );
+    let inside = mix(y0, y1, t.z);
+
+    let outside = length(point - clamped);
+    if outside > 0.0 {
+        return sqrt(outside * outside + max(inside, 0.0) * max(inside, 0.0));
+    }
+    return inside;
+}
+
+// Texels wrap around the heightmap so it tiles
+fn heightmap_value(shape: Shape, texel: vec2<i32>) -> f32 {
+    let size = vec2<i32>(shape.region_size.xy);
+    let wrapped = vec2<u32>(((texel % size) + size) % size);
+    return textureLoad(heightmap_atlas, shape.region_origin.xy + wrapped, 0).r;
+}
+
+// Interpolates bilinearly between texel centres, one unit of uv spanning the heightmap
+fn heightmap_height(shape: Shape, uv: vec2<f32>) -> f32 {
+    let position = uv * vec2<f32>(shape.region_size.xy) - 0.5;
+    let base = floor(position);
+    let t = position - base;
+    let texel = vec2<i32>(base);
+
+    return mix(
+        mix(heightmap_value(shape, texel), heightmap_value(shape, texel + vec2<i32>(1, 0)), t.x),
+        mix(heightmap_value(shape, texel + vec2<i32>(0, 1)), heightmap_value(shape, texel + vec2<i32>(1, 1)), t.x),
+        t.y
+    );
+}
+
+// The height above the surface divided by the steepest slope it can have is a lower bound on
+// the distance to it, intersected with the box the terrain stands in
+fn evaluate_sdf_heightfield(shape: Shape, point: vec3<f32>) -> f32 {
+    if any(shape.region_size.xy == vec2<u32>(0u)) {
+        return MAX_SIGNED_DISTANCE;
+    }
+
+    let size = shape.parameters[0].xy;
+    let heights = shape.parameters[0].zw;
+    let tiling = shape.parameters[1].xy;
+
+    let uv = (point.xz / size + 0.5) * tiling;
+    let height = mix(heights.x, heights.y, heightmap_height(shape, uv));
+    let gradient = (heights.y - heights.x) * shape.parameters[1].zw * tiling / size;
+    let surface = (point.y - height) / sqrt(1.0 + dot(gradient, gradient));
+
+    let center = vec3<f32>(0.0, (heights.x + heights.y) * 0.5, 0.0);
+    let half_size = vec3<f32>(size.x, heights.y - heights.x, size.y) * 0.5;
+    let q = abs(point - center) - half_size;
+    let footprint = length(max(q, vec3<f32>(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0);
+
+    return max(footprint, surface);
+}
+
+// Fractals return their orbit trap in xyz, the closest the orbit came to the origin along
+// each axis, and their distance estimate in w
+fn evaluate_mandelbulb(point: vec3<f32>, iterations: u32, power: f32) -> vec4<f32> {
+    var z = point;
+    var dr = 1.0;
+    var trap = vec3<f32>(MAX_SIGNED_DISTANCE);
+    for (var i = 0u; i < iterations; i++) {
+        let r = length(z);
+        if r > MANDELBULB_BAILOUT { break; }
+
+        let theta = acos(clamp(z.y / max(r, 1e-6), -1.0, 1.0)) * power;
+        let phi = atan2(z.z, z.x) * power;
+        dr = pow(r, power - 1.0) * power * dr + 1.0;
+        z = pow(r, power) * vec3<f32>(sin(theta) * cos(phi), cos(theta), sin(theta) * sin(phi)) + point;
+        trap = min(trap, abs(z));
+    }
+
+    let r = max(length(z), 1e-6);
+    return vec4<f32>(trap, 0.5 * log(r) * r / dr);
+}
+
+fn evaluate_menger_sponge(point: vec3<f32>, iterations: u32, scale: f32) -> vec4<f32> {
+    var z = point;
+    var factor = 1.0;
+    var trap = vec3<f32>(MAX_SIGNED_DISTANCE);
+    for (var i = 0u; i < iterations; i++) {
+        // Folding into the wedge x >= y >= z >= 0 maps each of the 20 kept cubes onto one
+        z = abs(z);
+        if z.x < z.y { z = z.yxz; }
+        if z.x < z.z { z = z.zyx; }
+        if z.y < z.z { z = z.xzy; }
+
+        z = vec3<f32>(z.x * scale - (scale - 1.0), z.y * scale - (scale - 1.0), z.z * scale);
+        if z.z > 0.5 * (scale - 1.0) { z.z -= scale - 1.0; }
+        factor *= scale;
+        trap = min(trap, abs(z));
+    }
+
+    let q = abs(z) - vec3<f32>(1.0);
+    let cube = length(max(q, vec3<f32>(0.0))) + min(max(q.x, max(q.y, q.z)), 0.0);
+    return vec4<f32>(trap, cube / factor);
+}
+
+fn evaluate_mandelbox(point: vec3<f32>, iterations: u32, scale: f32, radii: vec2<f32>) -> vec4<f32> {
+    let min_squared = radii.x * radii.x;
+    let fixed_squared = radii.y * radii.y;
+    var z = point;
+    var dr = 1.0;
+    var trap = vec3<f32>(MAX_SIGNED_DISTANCE);
+    for (var i = 0u; i < iterations; i++) {
+        z = clamp(z, vec3<f32>(-1.0), vec3<f32>(1.0)) * 2.0 - z;
+
+        let r_squared = dot(z, z);
+        var fold = 1.0;
+        if r_squared < min_squared {
+            fold = fixed_squared / min_squared;
+        } else if r_squared < fixed_squared {
+            fold = fixed_squared / r_squared;
+        }
+        z *= fold;
+        dr *= fold;
+
+        z = z * scale + point;
+        dr = dr * abs(scale) + 1.0;
+        trap = min(trap, abs(z));
+    }
+
+    // Subtracting the radius orbits settle within makes the estimate negative inside
+    let distance = (length(z) - abs(scale - 1.0)) / abs(dr) - pow(abs(scale), 1.0 - f32(iterations));
+    return vec4<f32>(trap, distance);
+}
+
+fn evaluate_sierpinski(point: vec3<f32>, iterations: u32, scale: f32) -> vec4<f32> {
+    var z = point;
+    var factor = 1.0;
+    var trap = vec3<f32>(MAX_SIGNED_DISTANCE);
+    for (var i = 0u; i < iterations; i++) {
+        // Reflects into the corner nearest (1, 1, 1) before shrinking towards it
+        if z.x + z.y < 0.0 { z = vec3<f32>(-z.y, -z.x, z.z); }
+        if z.x + z.z < 0.0 { z = vec3<f32>(-z.z, z.y, -z.x); }
+        if z.y + z.z < 0.0 { z = vec3<f32>(z.x, -z.z, -z.y); }
+
+        z = z * scale - vec3<f32>(scale - 1.0);
+        factor *= scale;
+        trap = min(trap, abs(z));
+    }
+
+    let tetrahedron = max(max(-z.x - z.y - z.z, z.x + z.y - z.z), max(-z.x + z.y + z.z, z.x - z.y + z.z));
+    return vec4<f32>(trap, (tetrahedron - 1.0) / sqrt(3.0) / factor);
+}
+
+// Evaluates a fractal shape at its size, point being in its local space
+fn evaluate_fractal(shape: Shape, point: vec3<f32>) -> vec4<f32> {
+    let iterations = u32(shape.parameters[0].x);
+    let power = shape.parameters[0].y;
+    let size = shape.parameters[0].z;
+    let scaled = point / size;
+
+    var result: vec4<f32>;
+    switch shape.kind {
+        case SHAPE_MANDELBULB: {
+            result = evaluate_mandelbulb(scaled, iterations, power);
+        }
+        case SHAPE_MENGER_SPONGE: {
+            result = evaluate_menger_sponge(scaled, iterations, power);
+        }
+        case SHAPE_MANDELBOX: {
+            result = evaluate_mandelbox(scaled, iterations, power, shape.parameters[1].xy);
+        }
+        default: {
+            result = evaluate_sierpinski(scaled, iterations, power);
+        }
+    }
+    return vec4<f32>(result.xyz, result.w * size);
+}
+
+fn is_fractal(kind: u32) -> bool {
+    return kind >= SHAPE_MANDELBULB && kind <= SHAPE_SIERPINSKI;
+}
+
+// Shapes of every kind but operators
+fn evaluate_sdf_leaf(shape: Shape, point: vec3<f32>) -> f32 {
+    let transformed_point = (shape.transform * vec4f(point, 1.0)).xyz;
+    switch shape.kind {
+        case SHAPE_GRID: {
+            return evaluate_sdf_grid(shape, transformed_point);
+        }
+        case SHAPE_HEIGHTFIELD: {
+            return evaluate_sdf_heightfield(shape, transformed_point);
+        }
+        case SHAPE_MANDELBULB, SHAPE_MENGER_SPONGE, SHAPE_MANDELBOX, SHAPE_SIERPINSKI: {
+            return evaluate_fractal(shape, transformed_point).w;
+        }
+        default: {
+            return MAX_SIGNED_DISTANCE;
+        }
+    }
+}
+
+fn is_operator(kind: u32) -> bool {
+    return kind >= SHAPE_REPEAT;
+}
+
+// Moves a point in an operator's local space to where its operand is evaluated
+fn warp(shape: Shape, point: vec3<f32>) -> vec3<f32> {
+    let parameters = shape.parameters[0];
+    switch shape.kind {
+        case SHAPE_REPEAT: {
+            let period = parameters.xyz;
+            let cell = select(vec3<f32>(0.0), round(point / period), period > vec3<f32>(0.0));
+            return point - period * cell;
+        }
+        case SHAPE_REPEAT_LIMITED: {
+            let period = parameters.xyz;
+            let count = shape.parameters[1].xyz;
+            let cell = select(vec3<f32>(0.0), clamp(round(point / period), -count, count), period > vec3<f32>(0.0));
+            return point - period * cell;
+        }
+        case SHAPE_POLAR: {
+            let sector = TAU / parameters.x;
+            let angle = atan2(point.z, point.x);
+            let turned = angle - sector * round(angle / sector);
+            let radius = length(point.xz);
+            return vec3<f32>(radius * cos(turned), point.y, radius * sin(turned));
+        }
+        case SHAPE_MIRROR: {
+            return select(point, abs(point), parameters.xyz > vec3<f32>(0.0));
+        }
+        case SHAPE_TWIST: {
+            let angle = parameters.x * point.y;
+            let c = cos(angle);
+            let s = sin(angle);
+            return vec3<f32>(c * point.x - s * point.z, point.y, s * point.x + c * point.z);
+        }
+        case SHAPE_BEND: {
+            let angle = parameters.x * point.x;
+            let c = cos(angle);
+            let s = sin(angle);
+            return vec3<f32>(c * point.x - s * point.y, s * point.x + c * point.y, point.z);
+        }
+        case SHAPE_ELONGATE: {
+            return point - clamp(point, -parameters.xyz, parameters.xyz);
+        }
+        default: {
+            return point;
+        }
+    }
+}
+
+fn hash_mix(v: vec3<u32>) -> vec3<u32> {
+    let x = v.x + v.y * v.z;
+    let y = v.y + v.z * x;
+    let z = v.z + x * y;
+    return vec3<u32>(x, y, z);
+}
+
+// PCG based hash of a lattice cell
+fn hash3(cell: vec3<f32>) -> vec3<u32> {
+    let v = hash_mix(bitcast<vec3<u32>>(vec3<i32>(cell)) * 1664525u + 1013904223u);
+    return hash_mix(v ^ (v >> vec3<u32>(16u)));
+}
+
+// Hash of a lattice cell as three values from zero to one
+fn random3(cell: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(hash3(cell) >> vec3<u32>(8u)) / 16777215.0;
+}
+
+fn fade(t: vec3<f32>) -> vec3<f32> {
+    return t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+}
+
+// Value at the corner of a lattice cell `offset` from its origin, a random value for value
+// noise or a random slope's height at `local` for gradient noise
+fn lattice_corner(kind: u32, cell: vec3<f32>, local: vec3<f32>, offset: vec3<f32>) -> f32 {
+    let random = random3(cell + offset) * 2.0 - 1.0;
+    if kind == NOISE_GRADIENT {
+        return dot(random, local - offset);
+    }
+    return random.x;
+}
+
+// Value or gradient noise, blending the corners of the lattice cell around `point`
+fn lattice_noise(kind: u32, point: vec3<f32>) -> f32 {
+    let cell = floor(point);
+    let local = point - cell;
+    let u = fade(local);
+    let c000 = lattice_corner(kind, cell, local, vec3<f32>(0.0, 0.0, 0.0));
+    let c100 = lattice_corner(kind, cell, local, vec3<f32>(1.0, 0.0, 0.0));
+    let c010 = lattice_corner(kind, cell, local, vec3<f32>(0.0, 1.0, 0.0));
+    let c110 = lattice_corner(kind, cell, local, vec3<f32>(1.0, 1.0, 0.0));
+    let c001 = lattice_corner(kind, cell, local, vec3<f32>(0.0, 0.0, 1.0));
+    let c101 = lattice_corner(kind, cell, local, vec3<f32>(1.0, 0.0, 1.0));
+    let c011 = lattice_corner(kind, cell, local, vec3<f32>(0.0, 1.0, 1.0));
+    let c111 = lattice_corner(kind, cell, local, vec3<f32>(1.0, 1.0, 1.0));
+    let near = mix(mix(c000, c100, u.x), mix(c010, c110, u.x), u.y);
+    let far = mix(mix(c001, c101, u.x), mix(c011, c111, u.x), u.y);
+    return mix(near, far, u.z);
+}
+
+fn worley_noise(point: vec3<f32>) -> f32 {
+    let cell = floor(point);
+    var nearest = MAX_SIGNED_DISTANCE;
+    for (var z = -1; z <= 1; z++) {
+        for (var y = -1; y <= 1; y++) {
+            for (var x = -1; x <= 1; x++) {
+                let neighbour = cell + vec3<f32>(f32(x), f32(y), f32(z));
+                let feature = neighbour + random3(neighbour);
+                let offset = point - feature;
+                nearest = min(nearest, dot(offset, offset));
+            }
+        }
+    }
+    return 1.0 - 2.0 * min(sqrt(nearest), 1.0);
+}
+
+fn noise(kind: u32, point: vec3<f32>) -> f32 {
+    if kind == NOISE_WORLEY {
+        return worley_noise(point);
+    }
+    return lattice_noise(kind, point);
+}
+
+// Octaves of noise summed as fractional Brownian motion, with the parameters of a displacing
+// operator
+fn fbm(parameters: array<vec4<f32>, 2>, point: vec3<f32>) -> f32 {
+    let kind = u32(parameters[1].z);
+    var amplitude = parameters[0].x;
+    var frequency = parameters[0].y;
+    var sum = 0.0;
+    for (var octave = 0u; octave < u32(parameters[0].z); octave++) {
+        sum += amplitude * noise(kind, point * frequency + OCTAVE_OFFSET * f32(octave));
+        amplitude *= parameters[1].y;
+        frequency *= parameters[1].x;
+    }
+    return sum;
+}
+
+// Matches `turn_lipschitz` on the CPU
+fn turn_lipschitz(rate: f32, radius: f32) -> f32 {
+    let shear = abs(rate) * radius * 0.5;
+    return shear + sqrt(1.0 + shear * shear);
+}
+
+// Reshapes the distance to an operator's operand into the distance to its result, `point`
+// being in the operator's local space. Twisting, bending and displacement stretch space, so
+// their distance is divided by how much.
+fn finish(shape: Shape, point: vec3<f32>, distance: f32) -> f32 {
+    let parameters = shape.parameters[0];
+    switch shape.kind {
+        // Space further from the axis is turned faster, and the way to the surface from the point
+        // stays within the further of it and the operand
+        case SHAPE_TWIST: {
+            return distance / turn_lipschitz(parameters.x, max(parameters.y, length(point.xz)));
+        }
+        case SHAPE_BEND: {
+            return distance / turn_lipschitz(parameters.x, max(parameters.y, length(point.xy)));
+        }
+        case SHAPE_ONION: {
+            return abs(distance) - parameters.x;
+        }
+        case SHAPE_DISPLACE: {
+            return (distance - fbm(shape.parameters, point)) / parameters.w;
+        }
+        default: {
+            return distance;
+        }
+    }
+}
+
+// Operands are stored after the scene's own objects of their kind
+fn operand_shape(operand: u32) -> Shape {
+    return shapes[scene.shape_count + (operand & PRIMITIVE_INDEX_MASK)];
+}
+
+// Follows a chain of operators down to the leaf at its end, warping the point on the way down
+// and finishing the distance on the way back up. Shaders can't recurse, and local arrays
+// indexed at runtime are slow to compile on some drivers, so each operator and its local point
+// are found again from the top to be finished. The leaf is evaluated after the walk rather than within it, so
+// compilers unrolling the loop don't copy it. Shapes that aren't operators are a chain of
+// none.
+fn evaluate_sdf_shape(shape: Shape, point: vec3<f32>) -> f32 {
+    var link = shape;
+    var warped = point;
+    var depth = 0u;
+    // Flagged as a shape while the leaf is `link` itself
+    var leaf = SHAPE_FLAG;
+
+    loop {
+        if !is_operator(link.kind) || depth == MAX_OPERATOR_DEPTH {
+            break;
+        }
+
+        warped = warp(link, (link.transform * vec4f(warped, 1.0)).xyz);
+        depth++;
+
+        if (link.source & SHAPE_FLAG) == 0u {
+            leaf = link.source;
+            break;
+        }
+        link = operand_shape(link.source);
+    }
+
+    var distance = MAX_SIGNED_DISTANCE;
+    let index = leaf & PRIMITIVE_INDEX_MASK;
+    if (leaf & CUBOID_FLAG) != 0u {
+        distance = evaluate_sdf_cuboid(cuboids[scene.cuboid_count + index], warped);
+    } else if (leaf & SHAPE_FLAG) == 0u {
+        distance = evaluate_sdf_sphere(spheres[scene.sphere_count + index], warped);
+    } else if !is_operator(link.kind) {
+        // Operators past the end of the chain are left out
+        distance = evaluate_sdf_leaf(link, warped);
+    }
+
+    for (var i = depth; i > 0u; i--) {
+        var finished = shape;
+        var local = (shape.transform * vec4f(point, 1.0)).xyz;
+        for (var j = 1u; j < i; j++) {
+            let next = operand_shape(finished.source);
+            local = (next.transform * vec4f(warp(finished, local), 1.0)).xyz;
+            finished = next;
+        }
+        distance = finish(finished, local, distance);
+    }
+    return distance;
+}
+
+fn bounds_distance(node: BvhNode, point: vec3<f32>) -> f32 {
+    let q = max(node.min - point, point - node.max);
+    return length(max(q, vec3<f32>(0.0)));
+}
+
+fn evaluate_sdf_primitive(primitive: u32, point: vec3<f32>) -> f32 {
+    if (primitive & CUBOID_FLAG) != 0u {
+        return evaluate_sdf_cuboid(cuboids[primitive & PRIMITIVE_INDEX_MASK], point);
+    }
+    if (primitive & SHAPE_FLAG) != 0u {
+        return evaluate_sdf_shape(shapes[primitive & PRIMITIVE_INDEX_MASK], point);
+    }
+    return evaluate_sdf_sphere(spheres[primitive], point);
+}
+
+// Visits nodes nearest first, skipping any whose bounds are further than the closest object so far
+fn evaluate_sdf_bvh(point: vec3<f32>) -> f32 {
+    var min_dist = MAX_SIGNED_DISTANCE;
+
+    if scene.sphere_count + scene.cuboid_count + scene.shape_count == 0u {
+        return min_dist;
+    }
+
+    var stack: array<u32, BVH_STACK_SIZE>;
+    var stack_size = 1u;
+    stack[0] = 0u;
+
+    while stack_size > 0u {
+        stack_size--;
+        let node = bvh_nodes[stack[stack_size]];
+
+        if bounds_distance(node, point) >= min_dist {
+            continue;
+        }
+
+        if node.count > 0u {
+            for (var i = node.left_or_first; i < node.left_or_first + node.count; i++) {
+                min_dist = min(min_dist, evaluate_sdf_primitive(bvh_primitives[i], point));
+            }
+            continue;
+        }
+
+        // `Bvh::build` caps the tree's depth so this never happens, leaves at the cap holding
+        // every primitive below it, but a full stack must not be written past
+        if stack_size + 2u > BVH_STACK_SIZE {
+            continue;
+        }
+
+        let left = node.left_or_first;
+        let right = left + 1u;
+
+        // The nearer child is pushed last so it is visited first
+        if bounds_distance(bvh_nodes[left], point) < bounds_distance(bvh_nodes[right], point) {
+            stack[stack_size] = right;
+            stack[stack_size + 1u] = left;
+        } else {
+            stack[stack_size] = left;
+            stack[stack_size + 1u] = right;
+        }
+        stack_size += 2u;
+    }
+
+    return min_dist;
 }
 
 fn evaluate_sdf(point: vec3<f32>) -> f32 {
+    if settings.use_bvh != 0u {
+        return evaluate_sdf_bvh(point);
+    }
+
     var min_dist = MAX_SIGNED_DISTANCE;
 
     for (var i = 0u; i < scene.sphere_count; i++) {
@@ -166,6 +828,10 @@ fn evaluate_sdf(point: vec3<f32>) -> f32 {
         min_dist = min(min_dist, evaluate_sdf_cuboid(cuboids[i], point));
     }
 
+    for (var i = 0u; i < scene.shape_count; i++) {
+        min_dist = min(min_dist, evaluate_sdf_shape(shapes[i], point));
+    }
+
     return min_dist;
 }
 
@@ -186,42 +852,213 @@ fn surface_normal(point:vec3<f32>) -> vec3<f32> {
 }
 
 
+fn sample_media(point: vec3<f32>) -> MediaSample {
+    var sample = MediaSample(vec3<f32>(0.0), vec3<f32>(0.0));
+
+    if fog.density > 0.0 {
+        // Clamped to keep the exponent from blowing up far below the base height
+        let height = max(point.y - fog.base_height, -10.0);
+        let density = fog.density * exp(-fog.height_falloff * height);
+        sample.scattering += fog.color * density;
+        sample.extinction += fog.color * density + vec3<f32>(fog.absorption * density);
+    }
+
+    for (var i = 0u; i < scene.medium_count; i++) {
+        let medium = media[i];
+        let transformed_point = medium.transform * vec4f(point, 1.0);
+        let q = abs(transformed_point.xyz) - medium.dimensions;
+        if max(q.x, max(q.y, q.z)) < 0.0 {
+            sample.scattering += medium.scattering * medium.density;
+            sample.extinction += medium.scattering * medium.density + vec3<f32>(medium.absorption * medium.density);
+        }
+    }
+
+    return sample;
+}
+
+// Light scattered towards the viewer at a point inside a volume
+fn in_scattered_light(point: vec3<f32>) -> vec3<f32> {
+    var light = vec3<f32>(0.0);
+
+    for (var i = 0u; i < MAX_LIGHTS; i++) {
+        let l = lights.lights[i];
+
+        if l.enabled == 0u { return light; }
+
+        let distance = length(l.position - point);
+        light += l.color * trace_shadow(point, l) / distance;
+    }
+
+    return light;
+}
+
+struct Volume {
+    in_scattered: vec3<f32>,
+    transmittance: vec3<f32>,
+}
+
+// Marches the view ray at fixed steps, integrating in-scattering and transmittance
+fn march_volume(ray_origin: vec3f, ray_direction: vec3f, max_distance: f32, jitter: f32) -> Volume {
+    var volume = Volume(vec3<f32>(0.0), vec3<f32>(1.0));
+
+    if fog.density <= 0.0 && scene.medium_count == 0u {
+        return volume;
+    }
+
+    let step_size = min(max_distance, VOLUME_DISTANCE) / f32(VOLUME_STEPS);
+
+    for (var i = 0u; i < VOLUME_STEPS; i++) {
+        let point = ray_origin + ray_direction * (f32(i) + jitter) * step_size;
+        let sample = sample_media(point);
+
+        if all(sample.extinction <= vec3<f32>(0.0)) {
+            continue;
+        }
+
+        let step_transmittance = exp(-sample.extinction * step_size);
+        // Energy conserving integration of the scattering over the step
+        let scattered = in_scattered_light(point) * sample.scattering
+            * (vec3<f32>(1.0) - step_transmittance) / max(sample.extinction, vec3<f32>(0.00001));
+
+        volume.in_scattered += volume.transmittance * scattered;
+        volume.transmittance *= step_transmittance;
+
+        if all(volume.transmittance < vec3<f32>(0.001)) {
+            break;
+        }
+    }
+
+    return volume;
+}
+
+// Cheap per pixel hash used to hide banding from fixed step marching
+fn pixel_noise(screen_cords: vec2<f32>) -> f32 {
+    return fract(52.9829189 * fract(dot(screen_cords, vec2<f32>(0.06711056, 0.00583715))));
+}
+
 struct Input {
     @builtin(position) screen_cords: vec4<f32>,
 };
 
+struct Output {
+    @location(0) color: vec4<f32>,
+    // Distance marched along the view ray, used by post processing
+    @location(1) depth: f32,
+}
+
+struct March {
+    point: vec3<f32>,
+    steps: u32,
+}
+
+struct MarchState {
+    ray_length: f32,
+    // Length of the step that reached ray_length
+    step: f32,
+    previous_distance: f32,
+    // Upper bound on the step as a multiple of the distance, 1 once a relaxed step overshoots
+    relaxation: f32,
+}
+
+fn march_state(start: f32) -> MarchState {
+    let relaxation = select(max(settings.relaxation, 1.0), 1.0, settings.march_strategy == MARCH_SPHERE);
+    return MarchState(start, 0.0, 0.0, relaxation);
+}
+
+// Distance below which a point counts as a hit, optionally growing with the pixel footprint
+fn hit_threshold(ray_length: f32) -> f32 {
+    return max(THRESHOLD, ray_length * settings.pixel_footprint / dimensions.y);
+}
+
+// Advances the march from the distance at the current point, returns true on a hit
+fn march_step(state: ptr<function, MarchState>, distance: f32) -> bool {
+    let current = *state;
+
+    // The unbounding spheres of the last two points do not overlap, so the relaxed step may
+    // have skipped a surface. Retreat to where plain sphere tracing would be and stop relaxing.
+    if current.relaxation > 1.0 && current.previous_distance + abs(distance) < current.step {
+        *state = MarchState(current.ray_length - current.step + current.previous_distance, 0.0, 0.0, 1.0);
+        return false;
+    }
+
+    if distance < hit_threshold(current.ray_length) {
+        return true;
+    }
+
+    var omega = 1.0;
+    if settings.march_strategy == MARCH_OVER_RELAXED {
+        omega = current.relaxation;
+    } else if settings.march_strategy == MARCH_ENHANCED && current.step > 0.0 {
+        // Assume the surface is the plane tangent to the last two spheres and step so the
+        // next sphere just touches the current one
+        let slope = clamp((current.previous_distance - distance) / current.step, -1.0, 1.0);
+        omega = clamp(2.0 / (1.0 + slope), 1.0, current.relaxation);
+    }
+
+    let step = distance * omega;
+    *state = MarchState(current.ray_length + step, step, distance, current.relaxation);
+    return false;
+}
+
 fn surface_point(ray_origin: vec3f, ray_direction: vec3f) -> vec3f {
-    var ray_length:f32 = CLIP_NEAR;
+    return march(ray_origin, ray_direction, CLIP_NEAR).point;
+}
+
+fn march(ray_origin: vec3f, ray_direction: vec3f, start: f32) -> March {
+    var state = march_state(start);
     var steps:u32 = 0u;
 
     loop {
-        let point = ray_origin + (ray_direction * ray_length);
+        let point = ray_origin + (ray_direction * state.ray_length);
         let min_signed_distance = map(point);
-        if ray_length > CLIP_FAR { break;}
-        if min_signed_distance < THRESHOLD { break;  }
-        ray_length += min_signed_distance;
+        if state.ray_length > CLIP_FAR { break;}
+        if march_step(&state, min_signed_distance) { break; }
         steps++;
-        if steps > MAX_MARCH_STEPS {break;}
+        if steps > settings.max_march_steps {break;}
+    }
+
+    return March(ray_origin + ray_direction * state.ray_length, steps);
+}
+
+// Distance the primary ray through a pixel can safely start marching from
+fn primary_start(pixel: vec2<f32>) -> f32 {
+    if settings.cone_prepass == 0u {
+        return CLIP_NEAR;
     }
+    let coords = vec2<i32>(pixel) / i32(CONE_TILE_SIZE);
+    return max(CLIP_NEAR, textureLoad(cone_distance, coords, 0).r);
+}
 
-    return ray_origin + ray_direction * ray_length;
+fn record_march(steps: u32) {
+    if settings.collect_stats != 0u {
+        atomicAdd(&march_statistics.pixels, 1u);
+        atomicAdd(&march_statistics.primary_steps, steps);
+    }
 }
 
 
-@fragment
-fn main(in: Input) -> @location(0) vec4<f32> {
+struct Ray {
+    origin: vec3<f32>,
+    direction: vec3<f32>,
+}
+
+// Ray from the camera through the given (jittered) pixel coordinates
+fn camera_ray(pixel: vec2<f32>) -> Ray {
     // Get the aspect ratio of the render target
     let aspect_ratio = dimensions.x / dimensions.y;
     // Normalize the pixel coordonates to -0.5 - 0.5;
-    let normalized = in.screen_cords / vec4<f32>(dimensions.x, dimensions.y, 1.0, 1.0) - vec4<f32>(0.5);
+    let normalized = pixel / dimensions.xy - vec2<f32>(0.5);
 
-    let aspected = normalized * vec4<f32>(aspect_ratio, 1.0, 1.0, 1.0);
+    let aspected = normalized * vec2<f32>(aspect_ratio, 1.0);
 
     let ray_dir = normalize(vec3(aspected.x, -aspected.y, 1.0));
-    let ray_direction = applyRotation(ray_dir, camera.orientation);
-    let ray_origin = camera.position;
+    return Ray(camera.position, applyRotation(ray_dir, camera.orientation));
+}
 
-    let surface_point = surface_point(ray_origin, ray_direction);
+// Lights the primary surface point, shared by the fragment and compute paths
+fn shade(ray: Ray, surface_point: vec3<f32>, screen_cords: vec2<f32>) -> Output {
+    let ray_origin = ray.origin;
+    let ray_direction = ray.direction;
 
     let surface_normal = surface_normal(surface_point);
 
@@ -239,11 +1076,11 @@ fn main(in: Input) -> @location(0) vec4<f32> {
     var reflected_color = vec3<f32>(0.0);
 
     if length(surface_point) < 100.0 {
-        color = direct_lighting(surface_point, surface_normal) * max(0.0, 1.0 - occlusion);
+        color = surface_albedo(surface_point) * direct_lighting(surface_point, surface_normal) * max(0.0, 1.0 - occlusion);
     }
 
     if length(reflected_point - surface_point) < 100.0 {
-        reflected_color = direct_lighting(reflected_point, reflected_surface_normal) * max(0.0, 1.0 - reflected_occlusion);
+        reflected_color = surface_albedo(reflected_point) * direct_lighting(reflected_point, reflected_surface_normal) * max(0.0, 1.0 - reflected_occlusion);
     }
 
     // Apply fresnel effect
@@ -251,7 +1088,179 @@ fn main(in: Input) -> @location(0) vec4<f32> {
     
     color = mix(reflected_color, color, ratio);
 
-    color = sqrt(color);
+    let volume = march_volume(
+        ray_origin,
+        ray_direction,
+        length(surface_point - ray_origin),
+        pixel_noise(screen_cords),
+    );
+
+    color = color * volume.transmittance + volume.in_scattered;
+
+    return Output(vec4<f32>(color, 1.0), length(surface_point - ray_origin));
+}
+
+// Index of the object nearest to a point, with CUBOID_FLAG set for cuboids and SHAPE_FLAG for
+// shapes
+fn nearest_object(point: vec3<f32>) -> u32 {
+    var min_dist = MAX_SIGNED_DISTANCE;
+    var nearest = 0u;
+
+    for (var i = 0u; i < scene.sphere_count; i++) {
+        let distance = evaluate_sdf_sphere(spheres[i], point);
+        if distance < min_dist {
+            min_dist = distance;
+            nearest = i;
+        }
+    }
+
+    for (var i = 0u; i < scene.cuboid_count; i++) {
+        let distance = evaluate_sdf_cuboid(cuboids[i], point);
+        if distance < min_dist {
+            min_dist = distance;
+            nearest = i | CUBOID_FLAG;
+        }
+    }
+
+    for (var i = 0u; i < scene.shape_count; i++) {
+        let distance = evaluate_sdf_shape(shapes[i], point);
+        if distance < min_dist {
+            min_dist = distance;
+            nearest = i | SHAPE_FLAG;
+        }
+    }
+
+    return nearest;
+}
+
+// Surface colour at a point. Fractals are coloured by their orbit trap, each channel darker
+// the closer the orbit came to the origin along its axis, everything else is white. Only
+// shapes are searched, the nearest fractal colouring the point when nothing else is nearer.
+fn surface_albedo(point: vec3<f32>) -> vec3<f32> {
+    var nearest = vec4<f32>(vec3<f32>(1.0), MAX_SIGNED_DISTANCE);
+    for (var i = 0u; i < scene.shape_count; i++) {
+        let shape = shapes[i];
+        if !is_fractal(shape.kind) { continue; }
+
+        let fractal = evaluate_fractal(shape, (shape.transform * vec4f(point, 1.0)).xyz);
+        if fractal.w < nearest.w {
+            nearest = fractal;
+        }
+    }
+
+    // Scenes without fractals skip evaluating the whole scene again
+    if nearest.w == MAX_SIGNED_DISTANCE || nearest.w > map(point) {
+        return vec3<f32>(1.0);
+    }
+    return mix(vec3<f32>(0.15), vec3<f32>(1.0), sqrt(clamp(nearest.xyz, vec3<f32>(0.0), vec3<f32>(1.0))));
+}
+
+fn id_color(id: u32) -> vec3<f32> {
+    var hash = id * 747796405u + 2891336453u;
+    hash = ((hash >> ((hash >> 28u) + 4u)) ^ hash) * 277803737u;
+    hash = (hash >> 22u) ^ hash;
+    return vec3<f32>(vec3<u32>(hash, hash >> 8u, hash >> 16u) & vec3<u32>(0xffu)) / 255.0;
+}
+
+// Blue through green to red as the value goes from zero to one
+fn heatmap(value: f32) -> vec3<f32> {
+    let t = clamp(value, 0.0, 1.0);
+    return clamp(vec3<f32>(2.0 * t - 0.5, 1.5 - abs(2.0 * t - 1.0) * 1.5, 1.5 - 2.0 * t), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn shadow_only(point: vec3<f32>, normal: vec3<f32>) -> f32 {
+    var shadow = 0.0;
+    var count = 0u;
+
+    for (var i = 0u; i < MAX_LIGHTS; i++) {
+        let l = lights.lights[i];
+
+        if l.enabled == 0u { break; }
+
+        count++;
+        if dot(l.position - point, normal) > 0.0 {
+            shadow += trace_shadow(point, l);
+        }
+    }
+
+    return shadow / f32(max(count, 1u));
+}
+
+// Replaces the lit color with one of the diagnostic views, sky pixels are left black
+fn shade_debug(ray: Ray, hit: March) -> Output {
+    let depth = length(hit.point - ray.origin);
+    let missed = depth >= CLIP_FAR;
+
+    var color = vec3<f32>(0.0);
+    switch settings.debug_view {
+        case DEBUG_STEPS: {
+            color = heatmap(f32(hit.steps) / f32(max(settings.max_march_steps, 1u)));
+        }
+        case DEBUG_NORMALS: {
+            if !missed { color = surface_normal(hit.point) * 0.5 + 0.5; }
+        }
+        case DEBUG_DEPTH: {
+            color = vec3<f32>(1.0 - sqrt(clamp(depth / CLIP_FAR, 0.0, 1.0)));
+        }
+        case DEBUG_AMBIENT_OCCLUSION: {
+            if !missed {
+                let occlusion = ambient_occlusion(hit.point, surface_normal(hit.point));
+                color = vec3<f32>(max(0.0, 1.0 - occlusion));
+            }
+        }
+        case DEBUG_SHADOW: {
+            if !missed { color = vec3<f32>(shadow_only(hit.point, surface_normal(hit.point))); }
+        }
+        case DEBUG_OBJECT_ID: {
+            if !missed { color = id_color(nearest_object(hit.point)); }
+        }
+        case DEBUG_MATERIAL_ID: {
+            // Objects carry no material yet, so the primitive kind stands in for it
+            if !missed { color = id_color(nearest_object(hit.point) >> 30u); }
+        }
+        default: {}
+    }
+
+    // Squared to cancel the gamma applied when compositing
+    return Output(vec4<f32>(color * color, 1.0), depth);
+}
+
+fn shade_hit(ray: Ray, hit: March, screen_cords: vec2<f32>) -> Output {
+    if settings.debug_view != DEBUG_NONE {
+        return shade_debug(ray, hit);
+    }
+    return shade(ray, hit.point, screen_cords);
+}
+
+// Marches a cone enclosing every ray of a cone tile, stopping once the cone touches a surface.
+// Each step is shortened so the empty sphere around the axis covers the whole cone section.
+@fragment
+fn cone_main(in: Input) -> @location(0) f32 {
+    let tile_center = floor(in.screen_cords.xy) * f32(CONE_TILE_SIZE) + vec2<f32>(f32(CONE_TILE_SIZE) * 0.5);
+    let ray = camera_ray(tile_center);
+
+    // Cone radius per unit distance, widened by a pixel for jitter
+    let spread = (f32(CONE_TILE_SIZE) * 0.5 * sqrt(2.0) + 1.0) / dimensions.y;
+
+    var ray_length = CLIP_NEAR;
+    for (var i = 0u; i < settings.max_march_steps; i++) {
+        let distance = map(ray.origin + ray.direction * ray_length);
+        let radius = ray_length * spread;
+        if distance < radius || ray_length > CLIP_FAR {
+            break;
+        }
+        ray_length += (distance - radius) / (1.0 + spread);
+    }
+
+    return ray_length;
+}
+
+@fragment
+fn main(in: Input) -> Output {
+    let ray = camera_ray(in.screen_cords.xy + jitter.xy);
+
+    let hit = march(ray.origin, ray.direction, primary_start(in.screen_cords.xy));
+    record_march(hit.steps);
 
-    return vec4<f32>(color, 1.0);
+    return shade_hit(ray, hit, in.screen_cords.xy);
 }
@@ -0,0 +1,106 @@
+struct Settings {
+    columns: u32,
+    rows: u32,
+    // Screen pixels per font pixel
+    scale: f32,
+    graph_samples: u32,
+    // Frame time at the top of the graph
+    graph_max: f32,
+    // Frame time marked by a line across the graph
+    graph_target: f32,
+}
+
+const GLYPH_WIDTH = 5u;
+const GLYPH_HEIGHT = 7u;
+const CELL = vec2<u32>(6u, 9u);
+const MARGIN = 4u;
+const GRAPH_HEIGHT = 48u;
+
+const BACKGROUND = vec4<f32>(0.0, 0.0, 0.0, 0.6);
+const TEXT = vec4<f32>(1.0, 1.0, 1.0, 1.0);
+const CPU_BAR = vec4<f32>(0.2, 0.7, 1.0, 0.8);
+const GPU_LINE = vec4<f32>(1.0, 0.6, 0.1, 1.0);
+const TARGET_LINE = vec4<f32>(0.6, 0.6, 0.6, 0.8);
+
+@group(0) @binding(0)
+var<uniform> settings: Settings;
+
+// Glyph index of each character cell, row by row
+@group(0) @binding(1)
+var<storage, read> text: array<u32>;
+
+// One row of 5 pixels per entry, GLYPH_HEIGHT entries per glyph
+@group(0) @binding(2)
+var<storage, read> glyphs: array<u32>;
+
+// CPU and GPU frame times in milliseconds, oldest first, negative when unknown
+@group(0) @binding(3)
+var<storage, read> graph: array<vec2<f32>>;
+
+struct Input {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+fn text_pixel(p: vec2<u32>) -> bool {
+    let cell = p / CELL;
+    if cell.x >= settings.columns || cell.y >= settings.rows {
+        return false;
+    }
+
+    let local = p % CELL;
+    if local.x >= GLYPH_WIDTH || local.y >= GLYPH_HEIGHT {
+        return false;
+    }
+
+    let glyph = text[cell.y * settings.columns + cell.x];
+    let row = glyphs[glyph * GLYPH_HEIGHT + local.y];
+    return ((row >> (GLYPH_WIDTH - 1u - local.x)) & 1u) != 0u;
+}
+
+fn graph_color(p: vec2<u32>) -> vec4<f32> {
+    let sample = graph[p.x];
+    // Height above the bottom of the graph, in font pixels
+    let height = f32(GRAPH_HEIGHT - p.y) - 0.5;
+    let per_ms = f32(GRAPH_HEIGHT) / settings.graph_max;
+
+    if sample.y >= 0.0 && abs(height - sample.y * per_ms) < 0.75 {
+        return GPU_LINE;
+    }
+    if abs(height - settings.graph_target * per_ms) < 0.5 {
+        return TARGET_LINE;
+    }
+    if height < sample.x * per_ms {
+        return CPU_BAR;
+    }
+    return BACKGROUND;
+}
+
+@fragment
+fn main(in: Input) -> @location(0) vec4<f32> {
+    let p = vec2<u32>(in.position.xy / settings.scale);
+
+    let text_size = CELL * vec2<u32>(settings.columns, settings.rows);
+    let panel_size = vec2<u32>(
+        max(text_size.x, settings.graph_samples) + MARGIN * 2u,
+        text_size.y + GRAPH_HEIGHT + MARGIN * 3u,
+    );
+
+    if any(p >= panel_size) {
+        return vec4<f32>(0.0);
+    }
+
+    if all(p >= vec2<u32>(MARGIN)) && text_pixel(p - vec2<u32>(MARGIN)) {
+        return TEXT;
+    }
+
+    let graph_origin = vec2<u32>(MARGIN, text_size.y + MARGIN * 2u);
+    if all(p >= graph_origin) {
+        let q = p - graph_origin;
+        if q.x < settings.graph_samples && q.y < GRAPH_HEIGHT {
+            return graph_color(q);
+        }
+    }
+
+    return BACKGROUND;
+}
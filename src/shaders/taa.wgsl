@@ -0,0 +1,110 @@
+const REPROJECT = 0u;
+const ACCUMULATE = 1u;
+const RESET = 2u;
+
+struct Camera {
+    position: vec3<f32>,
+    fov: f32,
+    orientation: vec4<f32>,
+    clip_near: f32,
+    clip_far: f32,
+}
+
+struct Resolve {
+    current: Camera,
+    previous: Camera,
+    jitter: vec2<f32>,
+    blend: f32,
+    mode: u32,
+}
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+
+@group(0) @binding(1)
+var depth: texture_2d<f32>;
+
+@group(0) @binding(2)
+var history: texture_2d<f32>;
+
+@group(0) @binding(3)
+var history_sampler: sampler;
+
+@group(0) @binding(4)
+var<uniform> resolve: Resolve;
+
+struct Input {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+fn rotate(v: vec3<f32>, r: vec4<f32>) -> vec3<f32> {
+    let s = r.w;
+    let u = r.xyz;
+    let a = u * (dot(u, v) * 2.0);
+    let b = v * ((s * s) - dot(u, u));
+    let c = cross(u, v) * (2.0 * s);
+    return a + b + c;
+}
+
+fn conjugate(q: vec4<f32>) -> vec4<f32> {
+    return q * vec4<f32>(-1.0, -1.0, -1.0, 1.0);
+}
+
+// Inverse of the ray generation in the main pass, from a world position to pixel coordinates
+fn project(world: vec3<f32>, camera: Camera, size: vec2<f32>) -> vec2<f32> {
+    let local = rotate(world - camera.position, camera.orientation);
+    let aspect_ratio = size.x / size.y;
+    let aspected = local.xy / max(local.z, 0.00001);
+    let normalized = vec2<f32>(aspected.x / aspect_ratio, -aspected.y);
+    return (normalized + vec2<f32>(0.5)) * size;
+}
+
+fn unproject(pixel: vec2<f32>, distance: f32, camera: Camera, size: vec2<f32>) -> vec3<f32> {
+    let aspect_ratio = size.x / size.y;
+    let normalized = pixel / size - vec2<f32>(0.5);
+    let ray_dir = normalize(vec3(normalized.x * aspect_ratio, -normalized.y, 1.0));
+    return camera.position + rotate(ray_dir, conjugate(camera.orientation)) * distance;
+}
+
+@fragment
+fn main(in: Input) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(source));
+    let coords = vec2<i32>(in.position.xy);
+    let max_coords = vec2<i32>(size) - vec2<i32>(1);
+    let current = textureLoad(source, coords, 0).rgb;
+
+    if resolve.mode == RESET {
+        return vec4<f32>(current, 1.0);
+    }
+
+    if resolve.mode == ACCUMULATE {
+        let accumulated = textureLoad(history, coords, 0).rgb;
+        return vec4<f32>(mix(accumulated, current, resolve.blend), 1.0);
+    }
+
+    // Bounds of the neighbourhood, used to reject stale history
+    var neighbourhood_min = current;
+    var neighbourhood_max = current;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let sample = textureLoad(source, clamp(coords + vec2<i32>(x, y), vec2<i32>(0), max_coords), 0).rgb;
+            neighbourhood_min = min(neighbourhood_min, sample);
+            neighbourhood_max = max(neighbourhood_max, sample);
+        }
+    }
+
+    let distance = textureLoad(depth, coords, 0).r;
+    let world = unproject(in.position.xy + resolve.jitter, distance, resolve.current, size);
+    let previous_pixel = project(world, resolve.previous, size);
+    let previous_uv = previous_pixel / size;
+
+    if any(previous_uv < vec2<f32>(0.0)) || any(previous_uv > vec2<f32>(1.0)) {
+        return vec4<f32>(current, 1.0);
+    }
+
+    let previous = textureSampleLevel(history, history_sampler, previous_uv, 0.0).rgb;
+    let clamped = clamp(previous, neighbourhood_min, neighbourhood_max);
+
+    return vec4<f32>(mix(clamped, current, resolve.blend), 1.0);
+}
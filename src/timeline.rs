@@ -0,0 +1,832 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use glam::{Mat4, Quat, Vec2, Vec3};
+
+use crate::{
+    camera::Camera, light_buffers::LightBuffers, scene_descriptor::SceneDescriptorBuilder,
+};
+
+/// How a track moves from a keyframe to the next one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    /// Holds the keyframe's value until the next keyframe
+    Step,
+    Linear,
+    /// Eases along a timing curve from (0, 0) to (1, 1) through these two control points, as
+    /// CSS `cubic-bezier` does
+    CubicBezier(Vec2, Vec2),
+    /// Spherical interpolation for rotations, other values interpolate linearly
+    Slerp,
+}
+
+impl Interpolation {
+    pub const EASE_IN_OUT: Self = Self::CubicBezier(Vec2::new(0.42, 0.0), Vec2::new(0.58, 1.0));
+
+    /// Reads the words `write` produces
+    fn parse<'a>(mut words: impl Iterator<Item = &'a str>) -> io::Result<Self> {
+        match words.next() {
+            Some("step") => Ok(Self::Step),
+            Some("linear") => Ok(Self::Linear),
+            Some("slerp") => Ok(Self::Slerp),
+            Some("ease-in-out") => Ok(Self::EASE_IN_OUT),
+            Some("bezier") => {
+                let points = parse_numbers(words, 4)?;
+                Ok(Self::CubicBezier(
+                    Vec2::new(points[0], points[1]),
+                    Vec2::new(points[2], points[3]),
+                ))
+            }
+            Some(word) => Err(invalid_data(format!("Unknown interpolation {word}"))),
+            None => Err(invalid_data("Expected an interpolation".into())),
+        }
+    }
+
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        match self {
+            Self::Step => write!(writer, "step"),
+            Self::Linear => write!(writer, "linear"),
+            Self::Slerp => write!(writer, "slerp"),
+            Self::CubicBezier(p1, p2) => {
+                write!(writer, "bezier {} {} {} {}", p1.x, p1.y, p2.x, p2.y)
+            }
+        }
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse_numbers<'a>(words: impl Iterator<Item = &'a str>, count: usize) -> io::Result<Vec<f32>> {
+    let numbers = words
+        .take(count)
+        .map(|word| word.parse::<f32>().ok())
+        .collect::<Option<Vec<_>>>()
+        .filter(|numbers| numbers.len() == count);
+    numbers.ok_or_else(|| invalid_data(format!("Expected {count} numbers")))
+}
+
+/// Values that can be keyframed
+pub trait Animatable: Copy {
+    fn lerp(self, other: Self, t: f32) -> Self;
+
+    fn slerp(self, other: Self, t: f32) -> Self {
+        self.lerp(other, t)
+    }
+}
+
+mod sealed {
+    pub trait Sealed {}
+
+    impl Sealed for f32 {}
+    impl Sealed for glam::Vec3 {}
+    impl Sealed for glam::Quat {}
+}
+
+/// Values written to timeline files as their components, sealed to the types the format knows
+pub trait Components: Animatable + sealed::Sealed {
+    const COUNT: usize;
+
+    fn components(self) -> Vec<f32>;
+    fn from_components(components: &[f32]) -> Self;
+}
+
+impl Animatable for f32 {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Components for f32 {
+    const COUNT: usize = 1;
+
+    fn components(self) -> Vec<f32> {
+        vec![self]
+    }
+
+    fn from_components(components: &[f32]) -> Self {
+        components[0]
+    }
+}
+
+impl Animatable for Vec3 {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        Vec3::lerp(self, other, t)
+    }
+}
+
+impl Components for Vec3 {
+    const COUNT: usize = 3;
+
+    fn components(self) -> Vec<f32> {
+        self.to_array().to_vec()
+    }
+
+    fn from_components(components: &[f32]) -> Self {
+        Vec3::from_slice(components)
+    }
+}
+
+impl Animatable for Quat {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        Quat::lerp(self, other, t)
+    }
+
+    fn slerp(self, other: Self, t: f32) -> Self {
+        Quat::slerp(self, other, t)
+    }
+}
+
+/// Written x, y, z then w
+impl Components for Quat {
+    const COUNT: usize = 4;
+
+    fn components(self) -> Vec<f32> {
+        self.to_array().to_vec()
+    }
+
+    fn from_components(components: &[f32]) -> Self {
+        Quat::from_slice(components).normalize()
+    }
+}
+
+/// The y of a cubic bezier timing curve at `x`, found by bisection since x(s) is monotonic
+fn ease(p1: Vec2, p2: Vec2, x: f32) -> f32 {
+    let bezier = |a: f32, b: f32, s: f32| {
+        let inverse = 1.0 - s;
+        3.0 * inverse * inverse * s * a + 3.0 * inverse * s * s * b + s * s * s
+    };
+
+    let (mut low, mut high) = (0.0, 1.0);
+    for _ in 0..24 {
+        let middle = (low + high) * 0.5;
+        if bezier(p1.x, p2.x, middle) < x {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+
+    bezier(p1.y, p2.y, (low + high) * 0.5)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe<T> {
+    /// Seconds from the start of the timeline
+    pub time: f32,
+    pub value: T,
+    /// Applies from this keyframe to the next
+    pub interpolation: Interpolation,
+}
+
+/// Keyframes of a single value, sorted by time
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    keyframes: Vec<Keyframe<T>>,
+}
+
+impl<T: Animatable> Track<T> {
+    pub fn new() -> Self {
+        Self {
+            keyframes: Vec::new(),
+        }
+    }
+
+    pub fn key(mut self, time: f32, value: T, interpolation: Interpolation) -> Self {
+        let index = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.time <= time);
+        self.keyframes.insert(
+            index,
+            Keyframe {
+                time,
+                value,
+                interpolation,
+            },
+        );
+        self
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    /// The value at `time`, holding the first and last keyframes outside of the track
+    pub fn sample(&self, time: f32) -> Option<T> {
+        let next = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.time <= time);
+        let (from, to) = match next {
+            0 => return self.keyframes.first().map(|keyframe| keyframe.value),
+            next if next == self.keyframes.len() => return Some(self.keyframes[next - 1].value),
+            next => (&self.keyframes[next - 1], &self.keyframes[next]),
+        };
+
+        let t = (time - from.time) / (to.time - from.time);
+        Some(match from.interpolation {
+            Interpolation::Step => from.value,
+            Interpolation::Linear => from.value.lerp(to.value, t),
+            Interpolation::CubicBezier(p1, p2) => from.value.lerp(to.value, ease(p1, p2, t)),
+            Interpolation::Slerp => from.value.slerp(to.value, t),
+        })
+    }
+}
+
+impl<T: Components> Track<T> {
+    /// Adds a keyframe from a `key` line's words after `key`
+    fn parse_key<'a>(self, mut words: impl Iterator<Item = &'a str>) -> io::Result<Self> {
+        let time = parse_numbers(&mut words, 1)?[0];
+        let value = T::from_components(&parse_numbers(&mut words, T::COUNT)?);
+        Ok(self.key(time, value, Interpolation::parse(words)?))
+    }
+
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        for keyframe in &self.keyframes {
+            write!(writer, "key {}", keyframe.time)?;
+            for component in keyframe.value.components() {
+                write!(writer, " {component}")?;
+            }
+            write!(writer, " ")?;
+            keyframe.interpolation.write(writer)?;
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Animatable> Default for Track<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What a track animates, objects are referred to by their index in the scene
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    Sphere(usize),
+    Cuboid(usize),
+    Shape(usize),
+    Medium(usize),
+    Light(usize),
+    Camera,
+}
+
+#[derive(Clone, Debug)]
+pub enum Channel {
+    /// Offset from where the scene places a target, or the camera's position
+    Position(Track<Vec3>),
+    /// Rotation about a target's own origin, or the camera's orientation
+    Rotation(Track<Quat>),
+    /// Scales spheres and cuboids along their own axes, keeping their distances conservative,
+    /// and multiplies the dimensions of media
+    Scale(Track<Vec3>),
+    /// Light colour and media scattering, objects have no colour of their own
+    Colour(Track<Vec3>),
+    /// Sphere and light radius
+    Radius(Track<f32>),
+    /// Multiplies light colour
+    Intensity(Track<f32>),
+}
+
+impl Target {
+    /// Reads a target from the words `write` produces, `sphere 0` or `camera`
+    fn parse<'a>(words: &mut impl Iterator<Item = &'a str>) -> io::Result<Self> {
+        let kind = words
+            .next()
+            .ok_or_else(|| invalid_data("Expected a target".into()))?;
+        if kind == "camera" {
+            return Ok(Self::Camera);
+        }
+
+        let index = words
+            .next()
+            .and_then(|word| word.parse().ok())
+            .ok_or_else(|| invalid_data(format!("Expected an index after {kind}")))?;
+        match kind {
+            "sphere" => Ok(Self::Sphere(index)),
+            "cuboid" => Ok(Self::Cuboid(index)),
+            "shape" => Ok(Self::Shape(index)),
+            "medium" => Ok(Self::Medium(index)),
+            "light" => Ok(Self::Light(index)),
+            _ => Err(invalid_data(format!("Unknown target {kind}"))),
+        }
+    }
+
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        match self {
+            Self::Sphere(index) => write!(writer, "sphere {index}"),
+            Self::Cuboid(index) => write!(writer, "cuboid {index}"),
+            Self::Shape(index) => write!(writer, "shape {index}"),
+            Self::Medium(index) => write!(writer, "medium {index}"),
+            Self::Light(index) => write!(writer, "light {index}"),
+            Self::Camera => write!(writer, "camera"),
+        }
+    }
+}
+
+impl Channel {
+    /// An empty track of the channel named `name`
+    fn parse(name: &str) -> io::Result<Self> {
+        match name {
+            "position" => Ok(Self::Position(Track::new())),
+            "rotation" => Ok(Self::Rotation(Track::new())),
+            "scale" => Ok(Self::Scale(Track::new())),
+            "colour" => Ok(Self::Colour(Track::new())),
+            "radius" => Ok(Self::Radius(Track::new())),
+            "intensity" => Ok(Self::Intensity(Track::new())),
+            _ => Err(invalid_data(format!("Unknown channel {name}"))),
+        }
+    }
+
+    fn parse_key<'a>(self, words: impl Iterator<Item = &'a str>) -> io::Result<Self> {
+        Ok(match self {
+            Self::Position(track) => Self::Position(track.parse_key(words)?),
+            Self::Rotation(track) => Self::Rotation(track.parse_key(words)?),
+            Self::Scale(track) => Self::Scale(track.parse_key(words)?),
+            Self::Colour(track) => Self::Colour(track.parse_key(words)?),
+            Self::Radius(track) => Self::Radius(track.parse_key(words)?),
+            Self::Intensity(track) => Self::Intensity(track.parse_key(words)?),
+        })
+    }
+
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        let name = match self {
+            Self::Position(_) => "position",
+            Self::Rotation(_) => "rotation",
+            Self::Scale(_) => "scale",
+            Self::Colour(_) => "colour",
+            Self::Radius(_) => "radius",
+            Self::Intensity(_) => "intensity",
+        };
+        writeln!(writer, " {name}")?;
+        match self {
+            Self::Position(track) | Self::Scale(track) | Self::Colour(track) => track.write(writer),
+            Self::Rotation(track) => track.write(writer),
+            Self::Radius(track) | Self::Intensity(track) => track.write(writer),
+        }
+    }
+
+    fn duration(&self) -> f32 {
+        match self {
+            Channel::Position(track) | Channel::Scale(track) | Channel::Colour(track) => {
+                track.duration()
+            }
+            Channel::Rotation(track) => track.duration(),
+            Channel::Radius(track) | Channel::Intensity(track) => track.duration(),
+        }
+    }
+}
+
+/// Keyframed tracks evaluated against a static scene, which is left untouched
+#[derive(Clone, Debug, Default)]
+pub struct Timeline {
+    tracks: Vec<(Target, Channel)>,
+    /// Playback wraps around at the end rather than stopping
+    pub looping: bool,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn track(mut self, target: Target, channel: Channel) -> Self {
+        self.tracks.push((target, channel));
+        self
+    }
+
+    pub fn looping(self) -> Self {
+        Self {
+            looping: true,
+            ..self
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tracks.is_empty()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::read(&fs::read_to_string(path)?)
+    }
+
+    /// Reads a timeline from text. Each track starts with a `track` line naming its target and
+    /// channel, such as `track sphere 0 position` or `track camera rotation`, followed by a
+    /// `key` line per keyframe holding its time, the value's components and the interpolation
+    /// to the next keyframe: `step`, `linear`, `slerp`, `ease-in-out` or `bezier` and its two
+    /// control points. Rotations are quaternions written x, y, z then w. A `loop` line makes
+    /// the timeline loop, and lines starting with `#` are comments.
+    pub fn read(text: &str) -> io::Result<Self> {
+        let mut timeline = Self::new();
+        for (number, line) in text.lines().enumerate() {
+            let mut words = line.split_whitespace();
+            let result = match words.next() {
+                None => Ok(()),
+                Some(word) if word.starts_with('#') => Ok(()),
+                Some("loop") => {
+                    timeline.looping = true;
+                    Ok(())
+                }
+                Some("track") => Target::parse(&mut words).and_then(|target| {
+                    let name = words
+                        .next()
+                        .ok_or_else(|| invalid_data("Expected a channel".into()))?;
+                    timeline.tracks.push((target, Channel::parse(name)?));
+                    Ok(())
+                }),
+                Some("key") => match timeline.tracks.pop() {
+                    Some((target, channel)) => channel.parse_key(words).map(|channel| {
+                        timeline.tracks.push((target, channel));
+                    }),
+                    None => Err(invalid_data("Keyframe before any track".into())),
+                },
+                Some(word) => Err(invalid_data(format!("Unknown line {word}"))),
+            };
+            result.map_err(|error| invalid_data(format!("Line {}: {error}", number + 1)))?;
+        }
+        Ok(timeline)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer)?;
+        writer.flush()
+    }
+
+    /// Writes the text `read` reads
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        if self.looping {
+            writeln!(writer, "loop")?;
+        }
+        for (target, channel) in &self.tracks {
+            write!(writer, "track ")?;
+            target.write(writer)?;
+            channel.write(writer)?;
+        }
+        Ok(())
+    }
+
+    /// Time of the last keyframe of any track
+    pub fn duration(&self) -> f32 {
+        self.tracks
+            .iter()
+            .map(|(_, channel)| channel.duration())
+            .fold(0.0, f32::max)
+    }
+
+    pub fn animates_camera(&self) -> bool {
+        self.tracks
+            .iter()
+            .any(|(target, _)| *target == Target::Camera)
+    }
+
+    /// Position and rotation of `target` at `time`, if either is animated
+    fn pose(&self, target: Target, time: f32) -> Option<(Vec3, Quat)> {
+        let mut position = None;
+        let mut rotation = None;
+        for (track_target, channel) in &self.tracks {
+            match channel {
+                Channel::Position(track) if *track_target == target => {
+                    position = track.sample(time)
+                }
+                Channel::Rotation(track) if *track_target == target => {
+                    rotation = track.sample(time)
+                }
+                _ => {}
+            }
+        }
+
+        (position.is_some() || rotation.is_some()).then(|| {
+            (
+                position.unwrap_or(Vec3::ZERO),
+                rotation.unwrap_or(Quat::IDENTITY),
+            )
+        })
+    }
+
+    /// A fresh copy of `scene` with every track applied at `time`
+    pub fn evaluate(
+        &self,
+        time: f32,
+        scene: &(SceneDescriptorBuilder, LightBuffers),
+    ) -> (SceneDescriptorBuilder, LightBuffers) {
+        let (mut scene, mut lights) = scene.clone();
+        let lights_slice = lights.lights_mut();
+
+        let mut posed = Vec::new();
+        for (target, _) in &self.tracks {
+            if !posed.contains(target) {
+                posed.push(*target);
+            }
+        }
+
+        for target in posed {
+            let Some((position, rotation)) = self.pose(target, time) else {
+                continue;
+            };
+
+            // Transforms map world space to local space, so the pose is undone after the
+            // scene's own placement
+            let pose = Mat4::from_rotation_translation(rotation, position).inverse();
+            match target {
+                Target::Sphere(index) => {
+                    if let Some(sphere) = scene.spheres.get_mut(index) {
+                        sphere.transform = pose * sphere.transform;
+                    }
+                }
+                Target::Cuboid(index) => {
+                    if let Some(cuboid) = scene.cuboids.get_mut(index) {
+                        cuboid.transform = pose * cuboid.transform;
+                    }
+                }
+                Target::Shape(index) => {
+                    if let Some(shape) = scene.shapes.get_mut(index) {
+                        shape.transform = pose * shape.transform;
+                    }
+                }
+                Target::Medium(index) => {
+                    if let Some(medium) = scene.media.get_mut(index) {
+                        medium.transform = pose * medium.transform;
+                    }
+                }
+                Target::Light(index) => {
+                    if let Some(light) = lights_slice.get_mut(index) {
+                        light.position += position;
+                    }
+                }
+                Target::Camera => {}
+            }
+        }
+
+        for (target, channel) in &self.tracks {
+            match (channel, *target) {
+                (Channel::Scale(track), Target::Sphere(index)) => {
+                    if let (Some(scale), Some(sphere)) =
+                        (track.sample(time), scene.spheres.get_mut(index))
+                    {
+                        *sphere = sphere.scale(scale);
+                    }
+                }
+                (Channel::Scale(track), Target::Cuboid(index)) => {
+                    if let (Some(scale), Some(cuboid)) =
+                        (track.sample(time), scene.cuboids.get_mut(index))
+                    {
+                        *cuboid = cuboid.scale(scale);
+                    }
+                }
+                (Channel::Scale(track), Target::Medium(index)) => {
+                    if let (Some(scale), Some(medium)) =
+                        (track.sample(time), scene.media.get_mut(index))
+                    {
+                        medium.dimensions *= scale;
+                    }
+                }
+                (Channel::Colour(track), Target::Light(index)) => {
+                    if let (Some(colour), Some(light)) =
+                        (track.sample(time), lights_slice.get_mut(index))
+                    {
+                        light.color = colour;
+                    }
+                }
+                (Channel::Colour(track), Target::Medium(index)) => {
+                    if let (Some(colour), Some(medium)) =
+                        (track.sample(time), scene.media.get_mut(index))
+                    {
+                        medium.scattering = colour;
+                    }
+                }
+                (Channel::Radius(track), Target::Sphere(index)) => {
+                    if let (Some(radius), Some(sphere)) =
+                        (track.sample(time), scene.spheres.get_mut(index))
+                    {
+                        sphere.radius = radius;
+                    }
+                }
+                (Channel::Radius(track), Target::Light(index)) => {
+                    if let (Some(radius), Some(light)) =
+                        (track.sample(time), lights_slice.get_mut(index))
+                    {
+                        light.radius = radius;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Intensity scales whatever colour the light ended up with
+        for (target, channel) in &self.tracks {
+            if let (Channel::Intensity(track), Target::Light(index)) = (channel, *target) {
+                if let (Some(intensity), Some(light)) =
+                    (track.sample(time), lights_slice.get_mut(index))
+                {
+                    light.color *= intensity;
+                }
+            }
+        }
+
+        (scene, lights)
+    }
+
+    /// `camera` moved to the animated camera pose at `time`, if there is one
+    pub fn camera(&self, time: f32, camera: Camera) -> Camera {
+        let mut camera = camera;
+        for (target, channel) in &self.tracks {
+            match (channel, *target) {
+                (Channel::Position(track), Target::Camera) => {
+                    camera.position = track.sample(time).unwrap_or(camera.position)
+                }
+                (Channel::Rotation(track), Target::Camera) => {
+                    camera.orientation = track.sample(time).unwrap_or(camera.orientation)
+                }
+                _ => {}
+            }
+        }
+        camera
+    }
+}
+
+/// Play, pause and scrub state of a timeline, starting paused at the beginning
+#[derive(Clone, Copy, Debug)]
+pub struct Playback {
+    pub time: f32,
+    pub playing: bool,
+    pub speed: f32,
+}
+
+impl Default for Playback {
+    fn default() -> Self {
+        Self {
+            time: 0.0,
+            playing: false,
+            speed: 1.0,
+        }
+    }
+}
+
+impl Playback {
+    /// Moves time on by `seconds` of real time when playing
+    pub fn advance(&mut self, seconds: f32, timeline: &Timeline) {
+        if self.playing {
+            self.seek(self.time + seconds * self.speed, timeline);
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.playing = !self.playing;
+    }
+
+    /// Jumps by `seconds`, backwards when negative
+    pub fn scrub(&mut self, seconds: f32, timeline: &Timeline) {
+        self.seek(self.time + seconds, timeline);
+    }
+
+    fn seek(&mut self, time: f32, timeline: &Timeline) {
+        let duration = timeline.duration();
+        self.time = if timeline.looping && duration > 0.0 {
+            time.rem_euclid(duration)
+        } else {
+            time.clamp(0.0, duration)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    fn ramp(interpolation: Interpolation) -> Track<f32> {
+        Track::new()
+            .key(1.0, 10.0, interpolation)
+            .key(3.0, 20.0, Interpolation::Linear)
+    }
+
+    #[test]
+    fn samples_hold_outside_the_track() {
+        let track = ramp(Interpolation::Linear);
+
+        assert_eq!(Track::<f32>::new().sample(0.0), None);
+        assert_eq!(track.sample(0.0), Some(10.0));
+        assert_eq!(track.sample(5.0), Some(20.0));
+    }
+
+    #[test]
+    fn samples_hit_keyframes_exactly() {
+        for interpolation in [
+            Interpolation::Step,
+            Interpolation::Linear,
+            Interpolation::EASE_IN_OUT,
+            Interpolation::Slerp,
+        ] {
+            let track = ramp(interpolation);
+            assert_eq!(track.sample(1.0), Some(10.0), "{interpolation:?}");
+            assert_eq!(track.sample(3.0), Some(20.0), "{interpolation:?}");
+        }
+    }
+
+    #[test]
+    fn step_holds_until_next_keyframe() {
+        let track = ramp(Interpolation::Step);
+
+        assert_eq!(track.sample(2.999), Some(10.0));
+        assert_eq!(track.sample(3.0), Some(20.0));
+    }
+
+    #[test]
+    fn keyframes_sort_by_time() {
+        let track = Track::new()
+            .key(2.0, 5.0, Interpolation::Linear)
+            .key(0.0, 1.0, Interpolation::Linear)
+            .key(2.0, 7.0, Interpolation::Linear);
+
+        assert_eq!(track.duration(), 2.0);
+        assert_eq!(track.sample(1.0), Some(3.0));
+        // Of keyframes at the same time the last added wins
+        assert_eq!(track.sample(2.0), Some(7.0));
+    }
+
+    #[test]
+    fn easing_runs_from_zero_to_one() {
+        let [p1, p2] = [Vec2::new(0.42, 0.0), Vec2::new(0.58, 1.0)];
+
+        assert!(ease(p1, p2, 0.0).abs() < 1e-5);
+        assert!((ease(p1, p2, 1.0) - 1.0).abs() < 1e-5);
+        assert!((ease(p1, p2, 0.5) - 0.5).abs() < 1e-5);
+        // Slow at either end
+        assert!(ease(p1, p2, 0.1) < 0.1);
+        assert!(ease(p1, p2, 0.9) > 0.9);
+        // A straight curve is linear
+        assert!((ease(Vec2::splat(0.25), Vec2::splat(0.75), 0.3) - 0.3).abs() < 1e-5);
+    }
+
+    #[test]
+    fn rotations_slerp() {
+        let track = Track::new()
+            .key(0.0, Quat::IDENTITY, Interpolation::Slerp)
+            .key(1.0, Quat::from_rotation_y(FRAC_PI_2), Interpolation::Slerp);
+
+        let halfway = track.sample(0.5).unwrap();
+        assert!(halfway.angle_between(Quat::from_rotation_y(FRAC_PI_2 / 2.0)) < 1e-4);
+    }
+
+    #[test]
+    fn playback_starts_paused_and_wraps_when_looping() {
+        let timeline = Timeline::new().track(
+            Target::Sphere(0),
+            Channel::Radius(ramp(Interpolation::Linear)),
+        );
+        let mut playback = Playback::default();
+
+        playback.advance(1.0, &timeline);
+        assert_eq!(playback.time, 0.0);
+
+        playback.toggle();
+        playback.advance(4.0, &timeline);
+        assert_eq!(playback.time, 3.0);
+
+        playback.scrub(-5.0, &timeline.clone().looping());
+        assert_eq!(playback.time, 1.0);
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let text = "loop\n\
+            track sphere 0 position\n\
+            key 0 0 0 0 ease-in-out\n\
+            key 2 0 1 0 linear\n\
+            track camera rotation\n\
+            key 0 0 0 0 1 slerp\n\
+            track light 1 intensity\n\
+            key 1 2 bezier 0.1 0.2 0.3 0.4\n";
+        let timeline = Timeline::read(text).unwrap();
+
+        assert!(timeline.looping);
+        assert!(timeline.animates_camera());
+        assert_eq!(timeline.duration(), 2.0);
+
+        let mut written = Vec::new();
+        timeline.write(&mut written).unwrap();
+        let mut rewritten = Vec::new();
+        Timeline::read(std::str::from_utf8(&written).unwrap())
+            .unwrap()
+            .write(&mut rewritten)
+            .unwrap();
+        assert_eq!(written, rewritten);
+    }
+
+    #[test]
+    fn read_reports_bad_lines() {
+        let error = Timeline::read("# comment\nkey 0 1 linear\n").unwrap_err();
+        assert!(error.to_string().starts_with("Line 2"));
+
+        assert!(Timeline::read("track sphere position\n").is_err());
+        assert!(Timeline::read("track sphere 0 spin\n").is_err());
+        assert!(Timeline::read("track sphere 0 radius\nkey 0 1 wobble\n").is_err());
+        assert!(Timeline::read("track sphere 0 position\nkey 0 1 2 linear\n").is_err());
+    }
+}
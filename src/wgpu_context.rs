@@ -1,29 +1,94 @@
+pub mod atlas;
+pub mod bake;
 pub mod buffers;
+pub mod gpu_timer;
+pub mod march_statistics;
+pub mod overlay;
+pub mod post_process;
+mod readback;
+pub mod temporal;
 
 use buffers::GPUBuffers;
-use glam::{quat, vec3};
+use glam::{quat, vec3, Vec2};
+use gpu_timer::GpuTimer;
+use image::RgbaImage;
+use march_statistics::{MarchStatistics, MarchStats};
+use overlay::Overlay;
+use post_process::{create_target, PostProcessSettings, PostProcessor, DEPTH_FORMAT, HDR_FORMAT};
+use readback::read_texture;
+pub use temporal::AntiAliasing;
+use temporal::{jitter_offset, ResolveMode, TemporalResolver};
 use wgpu::{
     BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, Features, Limits,
-    PipelineCompilationOptions, RenderPipeline, ShaderModule,
+    PipelineCompilationOptions, RenderPipeline, ShaderModule, TextureView,
 };
 use winit::{dpi::PhysicalSize, window::Window};
 
 use crate::{
-    camera::Camera, light_buffers::LightBuffers, make_scene,
-    scene_descriptor::SceneDescriptorBuilder,
+    camera::Camera, frame_timer::PassTimings, light_buffers::LightBuffers, make_scene,
+    render_settings::RenderSettings, scene_descriptor::SceneDescriptorBuilder,
 };
 
+const MIN_RENDER_SCALE: f32 = 0.1;
+const TILE_SIZE: u32 = 8;
+/// Pixels covered by each cone of the cone marching pre-pass, matches `CONE_TILE_SIZE`
+const CONE_TILE_SIZE: u32 = 8;
+const CONE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+/// The composite pass applies gamma itself, so the offscreen output is stored as is
+const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+/// Jittered samples accumulated for captures and exported frames, which have no history to
+/// resolve against
+pub const CAPTURE_SAMPLES: u32 = 8;
+
+/// How the main pass is run, chosen at startup
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderPath {
+    /// A full screen triangle shaded by the fragment shader
+    Fragment,
+    /// A compute shader dispatched in tiles, culling objects per tile
+    Compute,
+}
+
+pub struct ComputePath {
+    pipeline: wgpu::ComputePipeline,
+    output_layout: BindGroupLayout,
+}
+
 pub struct WgpuContext<'a> {
-    pub surface: wgpu::Surface<'a>,
+    /// Absent for headless contexts, which render into `offscreen` instead
+    pub surface: Option<wgpu::Surface<'a>>,
+    offscreen: Option<wgpu::Texture>,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
     pub render_pipeline: RenderPipeline,
+    pub cone_pipeline: RenderPipeline,
+    pub compute_path: Option<ComputePath>,
     pub size: (u32, u32),
 
     pub buffers: GPUBuffers,
 
     pub bind_group_layout: BindGroupLayout,
+    /// Cone pre-pass output and march statistics, bound to the main pass only
+    pub pass_layout: BindGroupLayout,
+
+    cone_view: TextureView,
+    pub march_statistics: MarchStatistics,
+
+    pub post_processor: PostProcessor,
+    pub post_process_settings: PostProcessSettings,
+    pub overlay: Overlay,
+
+    /// Present when the adapter supports timestamp queries
+    pub gpu_timer: Option<GpuTimer>,
+    gpu_timings: Option<PassTimings>,
+
+    pub temporal: TemporalResolver,
+    pub anti_aliasing: AntiAliasing,
+
+    pub render_settings: RenderSettings,
+    /// Fraction of the surface size the main pass renders at
+    render_scale: f32,
 }
 
 fn load_shaders(device: &wgpu::Device) -> (ShaderModule, ShaderModule) {
@@ -33,8 +98,140 @@ fn load_shaders(device: &wgpu::Device) -> (ShaderModule, ShaderModule) {
     (vertex_module, fragment_module)
 }
 
+/// One texel per cone, holding the distance every ray within it can skip
+fn create_cone_target(device: &wgpu::Device, render_size: (u32, u32)) -> TextureView {
+    let size = (
+        render_size.0.div_ceil(CONE_TILE_SIZE),
+        render_size.1.div_ceil(CONE_TILE_SIZE),
+    );
+    create_target(
+        device,
+        "Cone Target",
+        size,
+        CONE_FORMAT,
+        wgpu::TextureUsages::empty(),
+    )
+}
+
+fn create_compute_path(
+    device: &wgpu::Device,
+    bind_group_layout: &BindGroupLayout,
+    pass_layout: &BindGroupLayout,
+) -> ComputePath {
+    // The compute entry point reuses the scene code of the fragment shader
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("shaders/compute.wgsl"),
+        source: wgpu::ShaderSource::Wgsl(
+            concat!(
+                include_str!("shaders/frag.wgsl"),
+                include_str!("shaders/compute.wgsl")
+            )
+            .into(),
+        ),
+    });
+
+    let storage_texture = |binding, format| BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::WriteOnly,
+            format,
+            view_dimension: wgpu::TextureViewDimension::D2,
+        },
+        count: None,
+    };
+
+    let output_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("Compute Output Layout"),
+        entries: &[
+            storage_texture(0, HDR_FORMAT),
+            storage_texture(1, DEPTH_FORMAT),
+        ],
+    });
+
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Compute Pipeline Layout"),
+        bind_group_layouts: &[bind_group_layout, pass_layout, &output_layout],
+        push_constant_ranges: &[],
+    });
+
+    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Compute Pipeline"),
+        layout: Some(&layout),
+        module: &module,
+        entry_point: "compute_main",
+        compilation_options: PipelineCompilationOptions::default(),
+        cache: None,
+    });
+
+    ComputePath {
+        pipeline,
+        output_layout,
+    }
+}
+
+async fn request_adapter(
+    instance: &wgpu::Instance,
+    compatible_surface: Option<&wgpu::Surface<'_>>,
+) -> wgpu::Adapter {
+    // Request adapter with high perf power preference
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            compatible_surface,
+        })
+        .await
+        .expect("Failed to get requested adapter");
+
+    log::info!("{:?}", adapter.get_downlevel_capabilities());
+
+    log::info!("Backend: {:?}", adapter.get_info().backend);
+
+    adapter
+}
+
+async fn request_device(adapter: &wgpu::Adapter) -> (wgpu::Device, wgpu::Queue) {
+    // Pass timings are only collected where timestamp queries are available
+    let required_features = adapter.features() & Features::TIMESTAMP_QUERY;
+
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                required_features,
+                required_limits: Limits::default(),
+                memory_hints: wgpu::MemoryHints::Performance,
+            },
+            None,
+        )
+        .await
+        .expect("Failed to request device")
+}
+
+/// Output target of headless contexts, taking the place of the surface texture
+fn create_offscreen_target(
+    device: &wgpu::Device,
+    config: &wgpu::SurfaceConfiguration,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Offscreen Target"),
+        size: wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: config.format,
+        usage: config.usage,
+        view_formats: &[],
+    })
+}
+
 impl<'a> WgpuContext<'a> {
-    pub async fn new(window: &'a Window) -> Self {
+    pub async fn new(window: &'a Window, render_path: RenderPath) -> Self {
         let mut size = window.inner_size();
         size.width = size.width.max(1);
         size.height = size.height.max(1);
@@ -48,96 +245,71 @@ impl<'a> WgpuContext<'a> {
         let instance = wgpu::Instance::default();
         let surface = instance.create_surface(window).unwrap();
 
-        // Request adapter with high perf power preference
-        let adapter = instance
-            .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
-                force_fallback_adapter: false,
-                compatible_surface: Some(&surface),
-            })
-            .await
-            .expect("Failed to get requested adapter");
-
-        log::info!("{:?}", adapter.get_downlevel_capabilities());
-
-        log::info!("Backend: {:?}", adapter.get_info().backend);
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    label: None,
-                    required_features: Features::empty(),
-                    required_limits: Limits::default(),
-                    memory_hints: wgpu::MemoryHints::Performance,
-                },
-                None,
-            )
-            .await
-            .expect("Failed to request device");
+        let adapter = request_adapter(&instance, Some(&surface)).await;
+        let (device, queue) = request_device(&adapter).await;
 
         let mut surface_config = surface.get_default_config(&adapter, width, height).unwrap();
         surface_config.present_mode = wgpu::PresentMode::AutoVsync;
 
         surface.configure(&device, &surface_config);
 
-        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: None,
+        Self::create(device, queue, Some(surface), surface_config, render_path)
+    }
+
+    /// A context rendering into an offscreen texture rather than a window surface, for
+    /// benchmarks and image export
+    pub async fn headless(size: (u32, u32), render_path: RenderPath) -> Self {
+        let (width, height) = (size.0.max(1), size.1.max(1));
+
+        log::info!("Creating headless Wgpu Context: {width}x{height}");
+
+        let instance = wgpu::Instance::default();
+        let adapter = request_adapter(&instance, None).await;
+        let (device, queue) = request_device(&adapter).await;
+
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: OFFSCREEN_FORMAT,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::AutoVsync,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+        };
+
+        Self::create(device, queue, None, config, render_path)
+    }
+
+    fn create(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        surface: Option<wgpu::Surface<'a>>,
+        surface_config: wgpu::SurfaceConfiguration,
+        render_path: RenderPath,
+    ) -> Self {
+        let (width, height) = (surface_config.width, surface_config.height);
+
+        let bind_group_layout = GPUBuffers::create_layout(&device);
+
+        let pass_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Main Pass Layout"),
             entries: &[
                 BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
                     },
                     count: None,
                 },
                 BindGroupLayoutEntry {
                     binding: 1,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                BindGroupLayoutEntry {
-                    binding: 3,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                BindGroupLayoutEntry {
-                    binding: 4,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                BindGroupLayoutEntry {
-                    binding: 5,
-                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
                     ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
                         has_dynamic_offset: false,
                         min_binding_size: None,
                     },
@@ -149,11 +321,18 @@ impl<'a> WgpuContext<'a> {
         let (vertex_module, fragment_module) = load_shaders(&device);
 
         let render_pipeline = {
-            let frag_targets = [Some(wgpu::ColorTargetState {
-                format: surface_config.format,
-                blend: Some(wgpu::BlendState::REPLACE),
-                write_mask: wgpu::ColorWrites::ALL,
-            })];
+            let frag_targets = [
+                Some(wgpu::ColorTargetState {
+                    format: HDR_FORMAT,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+                Some(wgpu::ColorTargetState {
+                    format: DEPTH_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+            ];
 
             let fragment = Some(wgpu::FragmentState {
                 module: &fragment_module,
@@ -164,7 +343,7 @@ impl<'a> WgpuContext<'a> {
 
             let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Pipeline Layout"),
-                bind_group_layouts: &[&bind_group_layout],
+                bind_group_layouts: &[&bind_group_layout, &pass_layout],
                 push_constant_ranges: &[],
             });
 
@@ -191,10 +370,48 @@ impl<'a> WgpuContext<'a> {
             device.create_render_pipeline(desc)
         };
 
+        let cone_pipeline = {
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Cone Pipeline Layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Cone Pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &vertex_module,
+                    entry_point: "main",
+                    buffers: &[],
+                    compilation_options: PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &fragment_module,
+                    entry_point: "cone_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: CONE_FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: Default::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+
         let buffers = {
             let (scene, lights) = make_scene();
             GPUBuffers::create(
                 &device,
+                &queue,
                 (width, height),
                 scene,
                 lights,
@@ -208,33 +425,95 @@ impl<'a> WgpuContext<'a> {
             )
         };
 
+        let compute_path = match render_path {
+            RenderPath::Fragment => None,
+            RenderPath::Compute => Some(create_compute_path(
+                &device,
+                &bind_group_layout,
+                &pass_layout,
+            )),
+        };
+
+        let cone_view = create_cone_target(&device, (width, height));
+        let march_statistics = MarchStatistics::new(&device);
+
+        let temporal = TemporalResolver::new(&device, (width, height));
+        let post_processor = PostProcessor::new(
+            &device,
+            (width, height),
+            surface_config.format,
+            &temporal.history(),
+        );
+        let gpu_timer = GpuTimer::new(&device, &queue);
+        let overlay = Overlay::new(&device, surface_config.format);
+        let offscreen = match surface {
+            Some(_) => None,
+            None => Some(create_offscreen_target(&device, &surface_config)),
+        };
+
         Self {
             render_pipeline,
+            cone_pipeline,
+            compute_path,
             surface,
+            offscreen,
             device,
             queue,
             config: surface_config,
             size: (width, height),
             bind_group_layout,
+            pass_layout,
+            cone_view,
+            march_statistics,
             buffers,
+            post_processor,
+            post_process_settings: PostProcessSettings::default(),
+            overlay,
+            gpu_timer,
+            gpu_timings: None,
+            temporal,
+            anti_aliasing: AntiAliasing::Temporal,
+            render_settings: RenderSettings::default(),
+            render_scale: 1.0,
         }
     }
 
     pub fn render(
-        &self,
+        &mut self,
         scene: (SceneDescriptorBuilder, LightBuffers),
         camera: &Camera,
     ) -> Result<(), wgpu::SurfaceError> {
         let (scene, lights) = scene;
 
+        if let Some(timer) = &mut self.gpu_timer {
+            if let Some(timings) = timer.begin_frame(&self.device) {
+                self.gpu_timings = Some(timings);
+            }
+        }
+
+        let render_size = self.render_size();
+        self.buffers.update_buffers(
+            &self.device,
+            &self.queue,
+            render_size,
+            scene,
+            lights,
+            *camera,
+        );
         self.buffers
-            .update_buffers(&self.queue, self.size, scene, lights, *camera);
+            .update_render_settings(&self.queue, &self.render_settings);
 
-        let output_texture = self.surface.get_current_texture()?;
+        let output_texture = match &self.surface {
+            Some(surface) => Some(surface.get_current_texture()?),
+            None => None,
+        };
 
-        let view = output_texture
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        let view = match (&output_texture, &self.offscreen) {
+            (Some(output_texture), _) => &output_texture.texture,
+            (None, Some(offscreen)) => offscreen,
+            (None, None) => unreachable!("Contexts have either a surface or an offscreen target"),
+        }
+        .create_view(&wgpu::TextureViewDescriptor::default());
 
         let mut encoder = self
             .device
@@ -242,60 +521,301 @@ impl<'a> WgpuContext<'a> {
                 label: Some("Render Encoder"),
             });
 
-        {
-            let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                label: Some("Main Bind group"),
-                layout: &self.bind_group_layout,
+        let source = match self.anti_aliasing {
+            AntiAliasing::None => {
+                self.buffers.update_jitter(&self.queue, Vec2::ZERO);
+                self.encode_main_pass(&mut encoder);
+                self.temporal.reset();
+                &self.post_processor.hdr_view
+            }
+            AntiAliasing::Temporal => {
+                let jitter = self.temporal.next_jitter();
+                self.buffers.update_jitter(&self.queue, jitter);
+                self.encode_main_pass(&mut encoder);
+                self.temporal.encode(
+                    &self.device,
+                    &self.queue,
+                    &mut encoder,
+                    &self.post_processor.hdr_view,
+                    &self.post_processor.depth_view,
+                    camera,
+                    jitter,
+                    ResolveMode::Reproject,
+                    None,
+                    self.gpu_timer.as_ref(),
+                );
+                self.temporal.output()
+            }
+            AntiAliasing::Supersample(samples) => {
+                // Uniform writes apply per submission, so every sample is submitted on its own
+                for sample in 0..samples.max(1) {
+                    let jitter = jitter_offset(sample as usize);
+                    self.buffers.update_jitter(&self.queue, jitter);
+
+                    let mut sample_encoder =
+                        self.device
+                            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                                label: Some("Supersample Encoder"),
+                            });
+
+                    self.encode_main_pass(&mut sample_encoder);
+                    self.temporal.encode(
+                        &self.device,
+                        &self.queue,
+                        &mut sample_encoder,
+                        &self.post_processor.hdr_view,
+                        &self.post_processor.depth_view,
+                        camera,
+                        jitter,
+                        match sample {
+                            0 => ResolveMode::Reset,
+                            _ => ResolveMode::Accumulate,
+                        },
+                        Some(1.0 / (sample + 1) as f32),
+                        self.gpu_timer.as_ref(),
+                    );
+
+                    self.queue.submit(Some(sample_encoder.finish()));
+                }
+                self.temporal.output()
+            }
+        };
+
+        self.post_processor.encode(
+            &self.device,
+            &self.queue,
+            &mut encoder,
+            &self.post_process_settings,
+            source,
+            &view,
+            self.gpu_timer.as_ref(),
+        );
+
+        if self.overlay.enabled {
+            self.overlay.encode(
+                &self.device,
+                &self.queue,
+                &mut encoder,
+                &view,
+                self.size,
+                self.gpu_timer.as_ref(),
+            );
+        }
+
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.resolve(&mut encoder);
+        }
+
+        self.queue.submit(Some(encoder.finish()));
+        if let Some(output_texture) = output_texture {
+            output_texture.present();
+        }
+
+        if let Some(timer) = &mut self.gpu_timer {
+            timer.map();
+        }
+        Ok(())
+    }
+
+    /// Blocks until all submitted work has finished
+    pub fn wait_idle(&self) {
+        self.device.poll(wgpu::Maintain::Wait);
+    }
+
+    /// GPU pass timings of the most recent frame read back since the last call
+    pub fn take_gpu_timings(&mut self) -> Option<PassTimings> {
+        self.gpu_timings.take()
+    }
+
+    fn timestamp_writes(&self, label: &'static str) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        self.gpu_timer
+            .as_ref()
+            .and_then(|timer| timer.render_pass(label))
+    }
+
+    fn encode_main_pass(&self, encoder: &mut wgpu::CommandEncoder) {
+        let bind_group = self
+            .buffers
+            .bind_group(&self.device, &self.bind_group_layout);
+
+        if self.render_settings.cone_prepass != 0 {
+            let mut cone_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Cone Pass"),
+                timestamp_writes: self.timestamp_writes("Cone Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.cone_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+
+            cone_pass.set_pipeline(&self.cone_pipeline);
+            cone_pass.set_bind_group(0, &bind_group, &[]);
+            cone_pass.draw(0..3, 0..1);
+        }
+
+        let collect_stats = self.render_settings.collect_stats != 0;
+        if collect_stats {
+            self.march_statistics.clear(encoder);
+        }
+
+        let pass_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Main Pass Bind group"),
+            layout: &self.pass_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&self.cone_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.march_statistics.buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        if let Some(compute_path) = &self.compute_path {
+            let output_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Compute Output Bind group"),
+                layout: &compute_path.output_layout,
                 entries: &[
                     wgpu::BindGroupEntry {
                         binding: 0,
-                        resource: self.buffers.dimension_uniform.as_entire_binding(),
+                        resource: wgpu::BindingResource::TextureView(&self.post_processor.hdr_view),
                     },
                     wgpu::BindGroupEntry {
                         binding: 1,
-                        resource: self.buffers.scene_data.as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: self.buffers.light_data.as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 3,
-                        resource: self.buffers.camera_uniform.as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 4,
-                        resource: self.buffers.cuboids.as_entire_binding(),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 5,
-                        resource: self.buffers.spheres.as_entire_binding(),
+                        resource: wgpu::BindingResource::TextureView(
+                            &self.post_processor.depth_view,
+                        ),
                     },
                 ],
             });
 
+            let (width, height) = self.render_size();
+
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Compute Render Pass"),
+                timestamp_writes: self
+                    .gpu_timer
+                    .as_ref()
+                    .and_then(|timer| timer.compute_pass("Compute Render Pass")),
+            });
+
+            compute_pass.set_pipeline(&compute_path.pipeline);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.set_bind_group(1, &pass_bind_group, &[]);
+            compute_pass.set_bind_group(2, &output_bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                width.div_ceil(TILE_SIZE),
+                height.div_ceil(TILE_SIZE),
+                1,
+            );
+        } else {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
+                timestamp_writes: self.timestamp_writes("Render Pass"),
+                color_attachments: &[
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &self.post_processor.hdr_view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Store,
+                        },
+                    }),
+                    Some(wgpu::RenderPassColorAttachment {
+                        view: &self.post_processor.depth_view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Store,
+                        },
+                    }),
+                ],
                 ..Default::default()
             });
 
             render_pass.set_pipeline(&self.render_pipeline);
             render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.set_bind_group(1, &pass_bind_group, &[]);
 
             render_pass.draw(0..3, 0..1);
         }
 
-        self.queue.submit(Some(encoder.finish()));
-        output_texture.present();
-        Ok(())
+        if collect_stats {
+            self.march_statistics.resolve(encoder);
+        }
+    }
+
+    /// The last frame rendered by a headless context
+    pub fn read_output(&self) -> Option<RgbaImage> {
+        read_texture(&self.device, &self.queue, self.offscreen.as_ref()?)
+    }
+
+    /// Renders a single frame offscreen at `scale` times the surface size, or the largest
+    /// multiple that fits the device's texture size limit, and reads it back. The overlay is
+    /// left out and temporal anti-aliasing is replaced by supersampling, as there is no history
+    /// at the new size. Everything is restored afterwards.
+    pub fn capture(
+        &mut self,
+        scene: (SceneDescriptorBuilder, LightBuffers),
+        camera: &Camera,
+        scale: u32,
+    ) -> Option<RgbaImage> {
+        // One factor for both sides, so the largest texture keeps the surface's aspect ratio
+        let max_size = self.device.limits().max_texture_dimension_2d;
+        let fitted = scale
+            .min(max_size / self.size.0.max(1))
+            .min(max_size / self.size.1.max(1))
+            .max(1);
+        if fitted < scale {
+            log::warn!("Capture scale {scale} exceeds the texture size limit, using {fitted}");
+        }
+        let size = (self.size.0 * fitted, self.size.1 * fitted);
+
+        let mut config = self.config.clone();
+        config.usage = wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC;
+        config.width = size.0;
+        config.height = size.1;
+
+        let surface = self.surface.take();
+        let offscreen = self
+            .offscreen
+            .replace(create_offscreen_target(&self.device, &config));
+        let previous_size = std::mem::replace(&mut self.size, size);
+        let render_scale = std::mem::replace(&mut self.render_scale, 1.0);
+        let overlay = std::mem::replace(&mut self.overlay.enabled, false);
+        let anti_aliasing = self.anti_aliasing;
+        if anti_aliasing == AntiAliasing::Temporal {
+            self.anti_aliasing = AntiAliasing::Supersample(CAPTURE_SAMPLES);
+        }
+        self.resize_targets();
+
+        // Without a surface there is no surface error to fail on
+        let image = self
+            .render(scene, camera)
+            .ok()
+            .and_then(|_| self.read_output());
+
+        self.surface = surface;
+        self.offscreen = offscreen;
+        self.size = previous_size;
+        self.render_scale = render_scale;
+        self.overlay.enabled = overlay;
+        self.anti_aliasing = anti_aliasing;
+        self.resize_targets();
+
+        image
+    }
+
+    /// Step counts of the last frame rendered with `collect_stats` set, blocking on the GPU
+    pub fn read_march_stats(&self) -> MarchStats {
+        self.march_statistics.read(&self.device)
     }
 
     pub fn resize(&mut self, _window: &Window, new_size: PhysicalSize<u32>) {
@@ -303,6 +823,38 @@ impl<'a> WgpuContext<'a> {
         self.config.width = new_size.width.max(1);
         self.config.height = new_size.height.max(1);
 
-        self.surface.configure(&self.device, &self.config);
+        match &self.surface {
+            Some(surface) => surface.configure(&self.device, &self.config),
+            None => self.offscreen = Some(create_offscreen_target(&self.device, &self.config)),
+        }
+
+        self.size = (self.config.width, self.config.height);
+        self.resize_targets();
+    }
+
+    /// Sets the fraction of the surface size the main pass renders at, the result is upscaled
+    /// when compositing to the surface.
+    pub fn set_render_scale(&mut self, scale: f32) {
+        let scale = scale.clamp(MIN_RENDER_SCALE, 1.0);
+        if scale != self.render_scale {
+            self.render_scale = scale;
+            self.resize_targets();
+        }
+    }
+
+    pub fn render_size(&self) -> (u32, u32) {
+        (
+            ((self.size.0 as f32 * self.render_scale) as u32).max(1),
+            ((self.size.1 as f32 * self.render_scale) as u32).max(1),
+        )
+    }
+
+    fn resize_targets(&mut self) {
+        let render_size = self.render_size();
+        // The post processor keeps bind groups for the temporal targets, so follows them
+        self.temporal.resize(&self.device, render_size);
+        self.post_processor
+            .resize(&self.device, render_size, &self.temporal.history());
+        self.cone_view = create_cone_target(&self.device, render_size);
     }
 }
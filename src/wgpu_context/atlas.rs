@@ -0,0 +1,204 @@
+use std::sync::Arc;
+
+use glam::UVec3;
+use wgpu::{Device, Queue, TextureDimension};
+
+use crate::scene_descriptor::{
+    distance_grid::DistanceGrid,
+    heightmap::Heightmap,
+    objects::{Shape, ShapeKind},
+};
+
+pub const ATLAS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+/// Sampled data shapes read from an atlas
+pub trait AtlasSource {
+    /// Name used when warning it doesn't fit
+    const NAME: &'static str;
+    /// Kind of shape that samples it
+    const KIND: ShapeKind;
+    const DIMENSION: TextureDimension;
+
+    /// Texels along each axis, one along the axes the texture doesn't have
+    fn resolution(&self) -> UVec3;
+    /// x varies fastest, then y, then z
+    fn values(&self) -> &[f32];
+}
+
+impl AtlasSource for DistanceGrid {
+    const NAME: &'static str = "Distance grid";
+    const KIND: ShapeKind = ShapeKind::Grid;
+    const DIMENSION: TextureDimension = TextureDimension::D3;
+
+    fn resolution(&self) -> UVec3 {
+        self.resolution
+    }
+
+    fn values(&self) -> &[f32] {
+        &self.values
+    }
+}
+
+impl AtlasSource for Heightmap {
+    const NAME: &'static str = "Heightmap";
+    const KIND: ShapeKind = ShapeKind::Heightfield;
+    const DIMENSION: TextureDimension = TextureDimension::D2;
+
+    fn resolution(&self) -> UVec3 {
+        self.size.extend(1)
+    }
+
+    fn values(&self) -> &[f32] {
+        &self.values
+    }
+}
+
+/// Every source of one kind stacked along the texture's last axis, so `map()` can sample any
+/// of them through a single binding. 32 bit float textures aren't filterable everywhere, so
+/// the shader loads texels and interpolates them itself.
+pub struct Atlas<T> {
+    pub view: wgpu::TextureView,
+    sources: Vec<Arc<T>>,
+    /// Offset of each source along the stacking axis, absent for sources that didn't fit
+    origins: Vec<Option<u32>>,
+}
+
+impl<T: AtlasSource> Atlas<T> {
+    /// Axis sources are stacked along
+    const AXIS: usize = match T::DIMENSION {
+        TextureDimension::D3 => 2,
+        _ => 1,
+    };
+
+    pub fn new(device: &Device, queue: &Queue, sources: &[Arc<T>]) -> Self {
+        let limit = match T::DIMENSION {
+            TextureDimension::D3 => device.limits().max_texture_dimension_3d,
+            _ => device.limits().max_texture_dimension_2d,
+        };
+
+        let mut origins = Vec::with_capacity(sources.len());
+        let mut size = UVec3::ONE;
+        let mut depth = 0;
+        for source in sources {
+            let resolution = source.resolution();
+            let mut stacked = resolution;
+            stacked[Self::AXIS] += depth;
+            if stacked.max_element() > limit {
+                log::warn!(
+                    "{} of {}x{}x{} doesn't fit its atlas, skipping it",
+                    T::NAME,
+                    resolution.x,
+                    resolution.y,
+                    resolution.z
+                );
+                origins.push(None);
+                continue;
+            }
+
+            origins.push(Some(depth));
+            size = size.max(resolution);
+            depth += resolution[Self::AXIS];
+        }
+        size[Self::AXIS] = depth.max(1);
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(&format!("{} Atlas", T::NAME)),
+            size: wgpu::Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: size.z,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: T::DIMENSION,
+            format: ATLAS_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        for (source, origin) in sources.iter().zip(&origins) {
+            let Some(origin) = origin else {
+                continue;
+            };
+            let resolution = source.resolution();
+            let mut offset = UVec3::ZERO;
+            offset[Self::AXIS] = *origin;
+
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: offset.x,
+                        y: offset.y,
+                        z: offset.z,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                bytemuck::cast_slice(source.values()),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(resolution.x * 4),
+                    rows_per_image: Some(resolution.y),
+                },
+                wgpu::Extent3d {
+                    width: resolution.x,
+                    height: resolution.y,
+                    depth_or_array_layers: resolution.z,
+                },
+            );
+        }
+
+        Self {
+            view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+            sources: sources.to_vec(),
+            origins,
+        }
+    }
+
+    /// Rebuilds the atlas when the scene's sources are not the ones it holds
+    pub fn update(&mut self, device: &Device, queue: &Queue, sources: &[Arc<T>]) {
+        let unchanged = self.sources.len() == sources.len()
+            && self
+                .sources
+                .iter()
+                .zip(sources)
+                .all(|(held, source)| Arc::ptr_eq(held, source));
+
+        if !unchanged {
+            *self = Self::new(device, queue, sources);
+        }
+    }
+
+    /// Copies of the shapes with the regions of the atlas holding their sources filled in,
+    /// empty for sources that are missing or didn't fit. Other kinds of shape are left as they
+    /// are.
+    pub fn place(&self, shapes: &[Shape]) -> Vec<Shape> {
+        shapes
+            .iter()
+            .map(|shape| {
+                if shape.shape_kind() != T::KIND {
+                    return *shape;
+                }
+
+                let origin = self.origins.get(shape.source as usize).copied().flatten();
+                let (region_origin, region_size) = match origin {
+                    Some(origin) => {
+                        let mut region_origin = UVec3::ZERO;
+                        region_origin[Self::AXIS] = origin;
+                        (
+                            region_origin,
+                            self.sources[shape.source as usize].resolution(),
+                        )
+                    }
+                    None => (UVec3::ZERO, UVec3::ZERO),
+                };
+                Shape {
+                    region_origin,
+                    region_size,
+                    ..*shape
+                }
+            })
+            .collect()
+    }
+}
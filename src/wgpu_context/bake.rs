@@ -0,0 +1,171 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{Quat, UVec3, Vec3};
+use wgpu::{util::DeviceExt, BindGroupLayoutDescriptor, BindGroupLayoutEntry};
+
+use super::{buffers::GPUBuffers, request_adapter, request_device};
+use crate::{
+    camera::Camera,
+    light_buffers::LightBufferBuilder,
+    scene_descriptor::{distance_grid::DistanceGrid, SceneDescriptorBuilder},
+};
+
+/// Matches `BAKE_WORKGROUP_SIZE`
+const BAKE_WORKGROUP_SIZE: u32 = 4;
+
+/// Matches `Bake` in the shader
+#[repr(C, align(16))]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct BakeUniform {
+    origin: Vec3,
+    _padding: u32,
+    cell_size: Vec3,
+    _padding_2: u32,
+    resolution: UVec3,
+    _padding_3: u32,
+}
+
+/// Fills in the values of `grid` by evaluating `map()` for `scene` at each sample with a
+/// compute pass, on a device of its own as scenes are baked before any renderer exists.
+/// Blocks until the values are read back.
+pub async fn bake_grid(scene: &SceneDescriptorBuilder, grid: DistanceGrid) -> DistanceGrid {
+    let instance = wgpu::Instance::default();
+    let adapter = request_adapter(&instance, None).await;
+    let (device, queue) = request_device(&adapter).await;
+
+    // Lights and camera are bound but play no part in distances
+    let buffers = GPUBuffers::create(
+        &device,
+        &queue,
+        (1, 1),
+        scene.clone(),
+        LightBufferBuilder::new().build(),
+        Camera::new(0.5, Vec3::ZERO, Quat::IDENTITY, 0.001, 1000.0),
+    );
+    let scene_layout = GPUBuffers::create_layout(&device);
+    let scene_bind_group = buffers.bind_group(&device, &scene_layout);
+
+    let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("shaders/bake.wgsl"),
+        source: wgpu::ShaderSource::Wgsl(
+            concat!(
+                include_str!("../shaders/frag.wgsl"),
+                include_str!("../shaders/bake.wgsl")
+            )
+            .into(),
+        ),
+    });
+
+    let buffer_entry = |binding, ty| BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+
+    let bake_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("Bake Layout"),
+        entries: &[
+            buffer_entry(0, wgpu::BufferBindingType::Uniform),
+            buffer_entry(1, wgpu::BufferBindingType::Storage { read_only: false }),
+        ],
+    });
+    // Nothing of the main pass group is used, so it is left empty
+    let pass_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("Empty Pass Layout"),
+        entries: &[],
+    });
+
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Bake Pipeline Layout"),
+        bind_group_layouts: &[&scene_layout, &pass_layout, &bake_layout],
+        push_constant_ranges: &[],
+    });
+
+    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("Bake Pipeline"),
+        layout: Some(&layout),
+        module: &module,
+        entry_point: "bake_main",
+        compilation_options: wgpu::PipelineCompilationOptions::default(),
+        cache: None,
+    });
+
+    let uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Bake Buffer"),
+        contents: bytemuck::bytes_of(&BakeUniform {
+            origin: grid.bounds.min,
+            cell_size: grid.cell_size(),
+            resolution: grid.resolution,
+            ..Zeroable::zeroed()
+        }),
+        usage: wgpu::BufferUsages::UNIFORM,
+    });
+
+    let size = grid.resolution.element_product() as u64 * size_of::<f32>() as u64;
+    let values = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Bake Values Buffer"),
+        size,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    });
+    let readback = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Bake Readback Buffer"),
+        size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let pass_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Empty Pass Bind Group"),
+        layout: &pass_layout,
+        entries: &[],
+    });
+    let bake_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Bake Bind Group"),
+        layout: &bake_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: values.as_entire_binding(),
+            },
+        ],
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Bake Encoder"),
+    });
+    {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Bake Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&pipeline);
+        pass.set_bind_group(0, &scene_bind_group, &[]);
+        pass.set_bind_group(1, &pass_bind_group, &[]);
+        pass.set_bind_group(2, &bake_bind_group, &[]);
+
+        let workgroups = grid
+            .resolution
+            .map(|axis| axis.div_ceil(BAKE_WORKGROUP_SIZE));
+        pass.dispatch_workgroups(workgroups.x, workgroups.y, workgroups.z);
+    }
+    encoder.copy_buffer_to_buffer(&values, 0, &readback, 0, size);
+    queue.submit(Some(encoder.finish()));
+
+    let slice = readback.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| {});
+    device.poll(wgpu::Maintain::Wait);
+
+    let values = bytemuck::cast_slice(&slice.get_mapped_range()).to_vec();
+    readback.unmap();
+
+    DistanceGrid { values, ..grid }
+}
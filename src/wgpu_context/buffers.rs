@@ -1,9 +1,24 @@
-use wgpu::{util::DeviceExt, Device};
+use std::sync::Arc;
 
+use bytemuck::Pod;
+use glam::Vec2;
+use wgpu::{
+    util::DeviceExt, BindGroup, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry,
+    Device,
+};
+
+use super::atlas::Atlas;
 use crate::{
-    camera::Camera, light_buffers::LightBuffers, scene_descriptor::SceneDescriptorBuilder,
+    bvh::{bounding_spheres, Bvh},
+    camera::Camera,
+    light_buffers::LightBuffers,
+    render_settings::RenderSettings,
+    scene_descriptor::{distance_grid::DistanceGrid, heightmap::Heightmap, SceneDescriptorBuilder},
 };
 
+/// Storage buffers can't be bound empty, so each one holds at least this many bytes.
+const MIN_STORAGE_SIZE: usize = 256;
+
 pub struct GPUBuffers {
     pub dimension_uniform: wgpu::Buffer,
     pub scene_data: wgpu::Buffer,
@@ -11,11 +26,77 @@ pub struct GPUBuffers {
     pub spheres: wgpu::Buffer,
     pub light_data: wgpu::Buffer,
     pub camera_uniform: wgpu::Buffer,
+    pub fog_uniform: wgpu::Buffer,
+    pub media: wgpu::Buffer,
+    pub jitter_uniform: wgpu::Buffer,
+    pub render_settings_uniform: wgpu::Buffer,
+    pub bvh_nodes: wgpu::Buffer,
+    pub bvh_primitives: wgpu::Buffer,
+    pub object_bounds: wgpu::Buffer,
+    pub shapes: wgpu::Buffer,
+    pub volume_atlas: Atlas<DistanceGrid>,
+    pub heightmap_atlas: Atlas<Heightmap>,
+    /// Scene the BVH and object bounds were last built from
+    bounded_scene: SceneDescriptorBuilder,
+}
+
+fn create_storage(device: &Device, label: &str, contents: &[u8]) -> wgpu::Buffer {
+    let mut padded = contents.to_vec();
+    padded.resize(contents.len().max(MIN_STORAGE_SIZE), 0);
+
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(label),
+        contents: &padded,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+/// The scene's objects of one kind followed by its operands of that kind, which `map()` finds
+/// past the scene's count
+fn with_operands<T: Copy>(objects: &[T], operands: &[T]) -> Vec<T> {
+    [objects, operands].concat()
+}
+
+fn same_bytes<T: Pod>(a: &[T], b: &[T]) -> bool {
+    bytemuck::cast_slice::<T, u8>(a) == bytemuck::cast_slice::<T, u8>(b)
+}
+
+fn same_sources<T>(a: &[Arc<T>], b: &[Arc<T>]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| Arc::ptr_eq(a, b))
+}
+
+/// Whether two scenes have the same objects, and so the same BVH and bounds. Shapes sampling
+/// grids and heightmaps are bounded by them, which are compared by identity like the atlases do.
+fn same_objects(a: &SceneDescriptorBuilder, b: &SceneDescriptorBuilder) -> bool {
+    same_bytes(&a.spheres, &b.spheres)
+        && same_bytes(&a.cuboids, &b.cuboids)
+        && same_bytes(&a.shapes, &b.shapes)
+        && same_bytes(&a.operands.spheres, &b.operands.spheres)
+        && same_bytes(&a.operands.cuboids, &b.operands.cuboids)
+        && same_bytes(&a.operands.shapes, &b.operands.shapes)
+        && same_sources(&a.grids, &b.grids)
+        && same_sources(&a.heightmaps, &b.heightmaps)
+}
+
+/// Writes `contents` to a storage buffer, replacing the buffer when it has outgrown it.
+fn write_storage(
+    device: &Device,
+    queue: &wgpu::Queue,
+    buffer: &mut wgpu::Buffer,
+    label: &str,
+    contents: &[u8],
+) {
+    if contents.len() as u64 > buffer.size() {
+        *buffer = create_storage(device, label, contents);
+    } else if !contents.is_empty() {
+        queue.write_buffer(buffer, 0, contents);
+    }
 }
 
 impl GPUBuffers {
     pub fn update_buffers(
-        &self,
+        &mut self,
+        device: &Device,
         queue: &wgpu::Queue,
         dimensions: (u32, u32),
         scene: SceneDescriptorBuilder,
@@ -32,14 +113,331 @@ impl GPUBuffers {
             0,
             bytemuck::bytes_of(&scene.length_descriptor()),
         );
-        queue.write_buffer(&self.cuboids, 0, bytemuck::cast_slice(&scene.cuboids));
-        queue.write_buffer(&self.spheres, 0, bytemuck::cast_slice(&scene.spheres));
+        self.volume_atlas.update(device, queue, &scene.grids);
+        self.heightmap_atlas
+            .update(device, queue, &scene.heightmaps);
+
+        write_storage(
+            device,
+            queue,
+            &mut self.cuboids,
+            "Cuboid Buffer",
+            bytemuck::cast_slice(&with_operands(&scene.cuboids, &scene.operands.cuboids)),
+        );
+        write_storage(
+            device,
+            queue,
+            &mut self.spheres,
+            "Sphere Buffer",
+            bytemuck::cast_slice(&with_operands(&scene.spheres, &scene.operands.spheres)),
+        );
+        write_storage(
+            device,
+            queue,
+            &mut self.shapes,
+            "Shape Buffer",
+            bytemuck::cast_slice(
+                &self.heightmap_atlas.place(
+                    &self
+                        .volume_atlas
+                        .place(&with_operands(&scene.shapes, &scene.operands.shapes)),
+                ),
+            ),
+        );
         queue.write_buffer(&self.light_data, 0, bytemuck::bytes_of(&lights));
         queue.write_buffer(&self.camera_uniform, 0, bytemuck::bytes_of(&camera));
+        queue.write_buffer(&self.fog_uniform, 0, bytemuck::bytes_of(&scene.fog));
+        queue.write_buffer(&self.media, 0, bytemuck::bytes_of(&scene.media_buffer()));
+
+        // Building the BVH is the bulk of the CPU work here, and most frames don't move anything
+        if !same_objects(&self.bounded_scene, &scene) {
+            let bvh = Bvh::build(&scene);
+            write_storage(
+                device,
+                queue,
+                &mut self.bvh_nodes,
+                "BVH Node Buffer",
+                bytemuck::cast_slice(&bvh.nodes),
+            );
+            write_storage(
+                device,
+                queue,
+                &mut self.bvh_primitives,
+                "BVH Primitive Buffer",
+                bytemuck::cast_slice(&bvh.primitives),
+            );
+            write_storage(
+                device,
+                queue,
+                &mut self.object_bounds,
+                "Object Bounds Buffer",
+                bytemuck::cast_slice(&bounding_spheres(&scene)),
+            );
+            self.bounded_scene = scene;
+        }
+    }
+
+    /// Layout of the scene bind group, shared by every pass that evaluates `map()`
+    pub fn create_layout(device: &Device) -> BindGroupLayout {
+        device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: None,
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 9,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 10,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 11,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 12,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 13,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 14,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 15,
+                    visibility: wgpu::ShaderStages::FRAGMENT | wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    pub fn bind_group(&self, device: &Device, layout: &BindGroupLayout) -> BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Main Bind group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.dimension_uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.scene_data.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.light_data.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.camera_uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: self.cuboids.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: self.spheres.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: self.fog_uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: self.media.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: self.jitter_uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: self.render_settings_uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: self.bvh_nodes.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 11,
+                    resource: self.bvh_primitives.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 12,
+                    resource: self.object_bounds.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 13,
+                    resource: self.shapes.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 14,
+                    resource: wgpu::BindingResource::TextureView(&self.volume_atlas.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 15,
+                    resource: wgpu::BindingResource::TextureView(&self.heightmap_atlas.view),
+                },
+            ],
+        })
+    }
+
+    pub fn update_jitter(&self, queue: &wgpu::Queue, jitter: Vec2) {
+        queue.write_buffer(
+            &self.jitter_uniform,
+            0,
+            bytemuck::bytes_of(&[jitter.x, jitter.y, 0.0, 0.0]),
+        );
+    }
+
+    pub fn update_render_settings(&self, queue: &wgpu::Queue, settings: &RenderSettings) {
+        queue.write_buffer(
+            &self.render_settings_uniform,
+            0,
+            bytemuck::bytes_of(settings),
+        );
     }
 
     pub fn create(
         device: &Device,
+        queue: &wgpu::Queue,
         dimensions: (u32, u32),
         scene: SceneDescriptorBuilder,
         lights: LightBuffers,
@@ -70,15 +468,68 @@ impl GPUBuffers {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let cuboids = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Cuboid Buffer"),
-            contents: bytemuck::cast_slice(&scene.cuboids),
+        let cuboids = create_storage(
+            device,
+            "Cuboid Buffer",
+            bytemuck::cast_slice(&with_operands(&scene.cuboids, &scene.operands.cuboids)),
+        );
+
+        let spheres = create_storage(
+            device,
+            "Sphere Buffer",
+            bytemuck::cast_slice(&with_operands(&scene.spheres, &scene.operands.spheres)),
+        );
+
+        let bvh = Bvh::build(&scene);
+
+        let bvh_nodes = create_storage(device, "BVH Node Buffer", bytemuck::cast_slice(&bvh.nodes));
+
+        let bvh_primitives = create_storage(
+            device,
+            "BVH Primitive Buffer",
+            bytemuck::cast_slice(&bvh.primitives),
+        );
+
+        let object_bounds = create_storage(
+            device,
+            "Object Bounds Buffer",
+            bytemuck::cast_slice(&bounding_spheres(&scene)),
+        );
+
+        let volume_atlas = Atlas::new(device, queue, &scene.grids);
+        let heightmap_atlas = Atlas::new(device, queue, &scene.heightmaps);
+
+        let shapes =
+            create_storage(
+                device,
+                "Shape Buffer",
+                bytemuck::cast_slice(&heightmap_atlas.place(
+                    &volume_atlas.place(&with_operands(&scene.shapes, &scene.operands.shapes)),
+                )),
+            );
+
+        let fog_uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Fog Buffer"),
+            contents: bytemuck::bytes_of(&scene.fog),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let jitter_uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Jitter Buffer"),
+            contents: bytemuck::bytes_of(&[0.0f32; 4]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let spheres = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Sphere Buffer"),
-            contents: bytemuck::cast_slice(&scene.spheres),
+        let render_settings_uniform =
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Render Settings Buffer"),
+                contents: bytemuck::bytes_of(&RenderSettings::default()),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            });
+
+        let media = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Media Buffer"),
+            contents: bytemuck::bytes_of(&scene.media_buffer()),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
@@ -89,6 +540,17 @@ impl GPUBuffers {
             camera_uniform,
             cuboids,
             spheres,
+            fog_uniform,
+            media,
+            jitter_uniform,
+            render_settings_uniform,
+            bvh_nodes,
+            bvh_primitives,
+            object_bounds,
+            shapes,
+            volume_atlas,
+            heightmap_atlas,
+            bounded_scene: scene,
         }
     }
 }
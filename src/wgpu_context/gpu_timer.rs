@@ -0,0 +1,182 @@
+use std::{
+    cell::RefCell,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use wgpu::{CommandEncoder, Device, Queue};
+
+use crate::frame_timer::PassTimings;
+
+/// Passes timed per frame, any beyond this go untimed
+const MAX_TIMED_PASSES: u32 = 32;
+
+/// Measures GPU passes with timestamp queries, reading each frame's results back
+/// asynchronously so rendering never waits on them.
+pub struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+
+    /// Labels of the passes timed in the frame being recorded
+    labels: RefCell<Vec<&'static str>>,
+    /// Labels of the frame whose timestamps are being read back
+    pending: Option<Vec<&'static str>>,
+    /// Set between resolving a frame and starting to map its timestamps
+    unmapped: bool,
+    /// Outcome of mapping the readback buffer, once it has finished
+    mapped: Arc<Mutex<Option<Result<(), wgpu::BufferAsyncError>>>>,
+}
+
+impl GpuTimer {
+    /// Returns `None` when the device was created without timestamp queries
+    pub fn new(device: &Device, queue: &Queue) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let size = (MAX_TIMED_PASSES * 2) as u64 * wgpu::QUERY_SIZE as u64;
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Pass Timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: MAX_TIMED_PASSES * 2,
+        });
+
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp Resolve Buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Timestamp Readback Buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            period: queue.get_timestamp_period(),
+            labels: RefCell::new(Vec::new()),
+            pending: None,
+            unmapped: false,
+            mapped: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Starts recording a frame, returning the timings of an earlier frame once they are available
+    pub fn begin_frame(&mut self, device: &Device) -> Option<PassTimings> {
+        self.labels.get_mut().clear();
+
+        self.pending.as_ref()?;
+        device.poll(wgpu::Maintain::Poll);
+        let mapped = self.mapped.lock().unwrap().take()?;
+
+        // Dropping the frame frees the readback buffer, so later frames are still timed
+        let labels = self.pending.take()?;
+        if let Err(error) = mapped {
+            log::warn!("Failed to read back GPU timestamps: {error}");
+            return None;
+        }
+
+        let timings = {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let timestamps: &[u64] = bytemuck::cast_slice(&data);
+
+            // Passes sharing a label, such as each level of the bloom pyramid, are summed
+            let mut timings: PassTimings = Vec::new();
+            for (index, label) in labels.into_iter().enumerate() {
+                let ticks = timestamps[index * 2 + 1].saturating_sub(timestamps[index * 2]);
+                let time = Duration::from_nanos((ticks as f64 * self.period as f64) as u64);
+                match timings.iter_mut().find(|(existing, _)| *existing == label) {
+                    Some((_, total)) => *total += time,
+                    None => timings.push((label, time)),
+                }
+            }
+            timings
+        };
+        self.readback_buffer.unmap();
+
+        Some(timings)
+    }
+
+    /// Timestamp writes for a render pass, or `None` when the frame is not being timed
+    pub fn render_pass(&self, label: &'static str) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        let index = self.allocate(label)?;
+        Some(wgpu::RenderPassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(index * 2),
+            end_of_pass_write_index: Some(index * 2 + 1),
+        })
+    }
+
+    /// Timestamp writes for a compute pass, or `None` when the frame is not being timed
+    pub fn compute_pass(
+        &self,
+        label: &'static str,
+    ) -> Option<wgpu::ComputePassTimestampWrites<'_>> {
+        let index = self.allocate(label)?;
+        Some(wgpu::ComputePassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(index * 2),
+            end_of_pass_write_index: Some(index * 2 + 1),
+        })
+    }
+
+    fn allocate(&self, label: &'static str) -> Option<u32> {
+        // The readback buffer cannot be written while the previous frame is still mapped
+        if self.pending.is_some() {
+            return None;
+        }
+
+        let mut labels = self.labels.borrow_mut();
+        let index = labels.len() as u32;
+        if index >= MAX_TIMED_PASSES {
+            return None;
+        }
+        labels.push(label);
+        Some(index)
+    }
+
+    /// Copies the frame's timestamps out, after every timed pass has been recorded
+    pub fn resolve(&mut self, encoder: &mut CommandEncoder) {
+        let labels = std::mem::take(self.labels.get_mut());
+        if labels.is_empty() {
+            return;
+        }
+
+        let count = labels.len() as u32 * 2;
+        encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            count as u64 * wgpu::QUERY_SIZE as u64,
+        );
+        self.pending = Some(labels);
+        self.unmapped = true;
+    }
+
+    /// Starts reading back the resolved timestamps, after the frame has been submitted
+    pub fn map(&mut self) {
+        if !self.unmapped {
+            return;
+        }
+        self.unmapped = false;
+
+        let mapped = self.mapped.clone();
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                *mapped.lock().unwrap() = Some(result)
+            });
+    }
+}
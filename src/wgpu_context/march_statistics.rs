@@ -0,0 +1,78 @@
+use std::fmt::Display;
+
+use bytemuck::{Pod, Zeroable};
+use wgpu::{CommandEncoder, Device};
+
+/// Counters the main pass accumulates when `RenderSettings::collect_stats` is set
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Pod, Zeroable)]
+pub struct MarchStats {
+    pub pixels: u32,
+    pub primary_steps: u32,
+}
+
+impl MarchStats {
+    pub fn average_primary_steps(&self) -> f32 {
+        self.primary_steps as f32 / self.pixels.max(1) as f32
+    }
+}
+
+impl Display for MarchStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Primary march steps: {:.1} per pixel over {} pixels",
+            self.average_primary_steps(),
+            self.pixels
+        )
+    }
+}
+
+pub struct MarchStatistics {
+    pub buffer: wgpu::Buffer,
+    staging: wgpu::Buffer,
+}
+
+impl MarchStatistics {
+    pub fn new(device: &Device) -> Self {
+        let size = std::mem::size_of::<MarchStats>() as u64;
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("March Statistics Buffer"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("March Statistics Staging Buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self { buffer, staging }
+    }
+
+    pub fn clear(&self, encoder: &mut CommandEncoder) {
+        encoder.clear_buffer(&self.buffer, 0, None);
+    }
+
+    /// Copies the counters out for a later `read`, after the passes that accumulate them
+    pub fn resolve(&self, encoder: &mut CommandEncoder) {
+        encoder.copy_buffer_to_buffer(&self.buffer, 0, &self.staging, 0, self.buffer.size());
+    }
+
+    /// Blocks until the last resolved counters are available
+    pub fn read(&self, device: &Device) -> MarchStats {
+        let slice = self.staging.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        device.poll(wgpu::Maintain::Wait);
+
+        let stats = *bytemuck::from_bytes(&slice.get_mapped_range());
+        self.staging.unmap();
+        stats
+    }
+}
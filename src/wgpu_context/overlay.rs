@@ -0,0 +1,231 @@
+mod font;
+
+use std::{collections::VecDeque, time::Duration};
+
+use bytemuck::{Pod, Zeroable};
+use glam::{vec2, Vec2};
+use wgpu::{util::DeviceExt, BindGroupLayout, CommandEncoder, Device, RenderPipeline, TextureView};
+
+use super::{
+    gpu_timer::GpuTimer,
+    post_process::{create_pipeline, fullscreen_pass},
+};
+
+const MAX_COLUMNS: usize = 48;
+const MAX_ROWS: usize = 12;
+const GRAPH_SAMPLES: usize = 160;
+/// Frame time marked on the graph, 60 fps
+const TARGET_FRAME_MS: f32 = 1000.0 / 60.0;
+
+#[repr(C, align(16))]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct OverlayUniform {
+    columns: u32,
+    rows: u32,
+    scale: f32,
+    graph_samples: u32,
+    graph_max: f32,
+    graph_target: f32,
+    _padding: [u32; 2],
+}
+
+/// Text and a CPU/GPU frame time graph drawn over the final image.
+pub struct Overlay {
+    pub enabled: bool,
+    lines: Vec<String>,
+    /// CPU and GPU milliseconds per frame, newest last
+    samples: VecDeque<Vec2>,
+
+    uniform: wgpu::Buffer,
+    text: wgpu::Buffer,
+    glyphs: wgpu::Buffer,
+    graph: wgpu::Buffer,
+    layout: BindGroupLayout,
+    pipeline: RenderPipeline,
+}
+
+impl Overlay {
+    pub fn new(device: &Device, output_format: wgpu::TextureFormat) -> Self {
+        let vertex_module =
+            device.create_shader_module(wgpu::include_wgsl!("../shaders/fullscreen.wgsl"));
+        let overlay_module =
+            device.create_shader_module(wgpu::include_wgsl!("../shaders/overlay.wgsl"));
+
+        let storage = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Overlay Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1),
+                storage(2),
+                storage(3),
+            ],
+        });
+
+        let pipeline = create_pipeline(
+            device,
+            "Overlay Pipeline",
+            &layout,
+            &vertex_module,
+            &overlay_module,
+            "main",
+            output_format,
+            wgpu::BlendState::ALPHA_BLENDING,
+        );
+
+        let uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Overlay Buffer"),
+            contents: bytemuck::bytes_of(&OverlayUniform::zeroed()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let text = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Overlay Text Buffer"),
+            contents: bytemuck::cast_slice(&[0u32; MAX_COLUMNS * MAX_ROWS]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let glyphs = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Overlay Glyph Buffer"),
+            contents: bytemuck::cast_slice(&font::glyph_rows()),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let graph = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Overlay Graph Buffer"),
+            contents: bytemuck::cast_slice(&[Vec2::NEG_ONE; GRAPH_SAMPLES]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        Self {
+            enabled: false,
+            lines: Vec::new(),
+            samples: VecDeque::with_capacity(GRAPH_SAMPLES),
+            uniform,
+            text,
+            glyphs,
+            graph,
+            layout,
+            pipeline,
+        }
+    }
+
+    /// Replaces the displayed text, lines and characters past the panel size are cut off
+    pub fn set_text(&mut self, text: &str) {
+        self.lines = text.lines().take(MAX_ROWS).map(str::to_owned).collect();
+    }
+
+    /// Adds a frame to the graph. GPU times are only read back for some frames, the others
+    /// repeat the last measurement.
+    pub fn push_sample(&mut self, cpu_time: Duration, gpu_time: Option<Duration>) {
+        let previous_gpu = self.samples.back().map_or(-1.0, |sample| sample.y);
+        if self.samples.len() >= GRAPH_SAMPLES {
+            self.samples.pop_front();
+        }
+
+        let ms = |duration: Duration| duration.as_secs_f32() * 1000.0;
+        self.samples
+            .push_back(vec2(ms(cpu_time), gpu_time.map_or(previous_gpu, ms)));
+    }
+
+    /// Draws the overlay onto `output`, blending over what is already there
+    pub fn encode(
+        &self,
+        device: &Device,
+        queue: &wgpu::Queue,
+        encoder: &mut CommandEncoder,
+        output: &TextureView,
+        output_size: (u32, u32),
+        timer: Option<&GpuTimer>,
+    ) {
+        let columns = self
+            .lines
+            .iter()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0)
+            .min(MAX_COLUMNS);
+
+        let mut text = vec![0u32; columns * self.lines.len()];
+        for (row, line) in self.lines.iter().enumerate() {
+            for (column, character) in line.chars().take(columns).enumerate() {
+                text[row * columns + column] = font::glyph_index(character);
+            }
+        }
+        if !text.is_empty() {
+            queue.write_buffer(&self.text, 0, bytemuck::cast_slice(&text));
+        }
+
+        // Oldest first, padded at the start until the graph fills up
+        let mut graph = vec![Vec2::NEG_ONE; GRAPH_SAMPLES - self.samples.len()];
+        graph.extend(self.samples.iter());
+        queue.write_buffer(&self.graph, 0, bytemuck::cast_slice(&graph));
+
+        let slowest = self.samples.iter().fold(0.0f32, |slowest, sample| {
+            slowest.max(sample.x).max(sample.y)
+        });
+
+        let uniform = OverlayUniform {
+            columns: columns as u32,
+            rows: self.lines.len() as u32,
+            scale: (output_size.1 as f32 / 540.0).round().max(1.0),
+            graph_samples: GRAPH_SAMPLES as u32,
+            graph_max: (slowest * 1.1).max(TARGET_FRAME_MS * 2.0),
+            graph_target: TARGET_FRAME_MS,
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.uniform, 0, bytemuck::bytes_of(&uniform));
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Overlay Bind Group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.text.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.glyphs.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.graph.as_entire_binding(),
+                },
+            ],
+        });
+
+        fullscreen_pass(
+            encoder,
+            "Overlay Pass",
+            output,
+            wgpu::LoadOp::Load,
+            &self.pipeline,
+            &bind_group,
+            timer,
+        );
+    }
+}
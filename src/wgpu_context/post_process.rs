@@ -0,0 +1,676 @@
+use bytemuck::{Pod, Zeroable};
+use wgpu::{
+    util::DeviceExt, BindGroup, BindGroupLayout, CommandEncoder, Device, Id,
+    PipelineCompilationOptions, RenderPipeline, ShaderModule, TextureFormat, TextureView,
+};
+
+use super::gpu_timer::GpuTimer;
+
+pub const HDR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+pub const DEPTH_FORMAT: TextureFormat = TextureFormat::R32Float;
+
+const BLOOM_LEVELS: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PostEffect {
+    Bloom = 1,
+    DepthOfField = 2,
+    ChromaticAberration = 4,
+    Vignette = 8,
+}
+
+/// Settings that can be changed while running, one selected at a time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PostParameter {
+    BloomThreshold,
+    BloomIntensity,
+    Aperture,
+    VignetteStrength,
+    ChromaticAberration,
+}
+
+impl PostParameter {
+    pub fn next(self) -> Self {
+        match self {
+            Self::BloomThreshold => Self::BloomIntensity,
+            Self::BloomIntensity => Self::Aperture,
+            Self::Aperture => Self::VignetteStrength,
+            Self::VignetteStrength => Self::ChromaticAberration,
+            Self::ChromaticAberration => Self::BloomThreshold,
+        }
+    }
+
+    /// Change of one step and the range the value is kept in
+    fn step_and_range(self) -> (f32, f32, f32) {
+        match self {
+            Self::BloomThreshold => (0.1, 0.0, 10.0),
+            Self::BloomIntensity => (0.05, 0.0, 2.0),
+            Self::Aperture => (0.1, 0.0, 4.0),
+            Self::VignetteStrength => (0.05, 0.0, 1.0),
+            Self::ChromaticAberration => (0.001, 0.0, 0.05),
+        }
+    }
+}
+
+#[repr(C, align(16))]
+#[derive(Clone, Copy, Debug)]
+pub struct PostProcessSettings {
+    pub bloom_threshold: f32,
+    pub bloom_knee: f32,
+    pub bloom_intensity: f32,
+    pub focus_distance: f32,
+    pub aperture: f32,
+    pub max_blur: f32,
+    pub chromatic_aberration: f32,
+    pub vignette_strength: f32,
+    pub vignette_radius: f32,
+    enabled: u32,
+    _padding: [u32; 2],
+}
+
+unsafe impl Pod for PostProcessSettings {}
+unsafe impl Zeroable for PostProcessSettings {}
+
+impl PostProcessSettings {
+    pub fn is_enabled(&self, effect: PostEffect) -> bool {
+        self.enabled & effect as u32 != 0
+    }
+
+    pub fn set_enabled(&mut self, effect: PostEffect, enabled: bool) {
+        if enabled {
+            self.enabled |= effect as u32;
+        } else {
+            self.enabled &= !(effect as u32);
+        }
+    }
+
+    pub fn toggle(&mut self, effect: PostEffect) {
+        self.set_enabled(effect, !self.is_enabled(effect));
+    }
+
+    pub fn get(&self, parameter: PostParameter) -> f32 {
+        match parameter {
+            PostParameter::BloomThreshold => self.bloom_threshold,
+            PostParameter::BloomIntensity => self.bloom_intensity,
+            PostParameter::Aperture => self.aperture,
+            PostParameter::VignetteStrength => self.vignette_strength,
+            PostParameter::ChromaticAberration => self.chromatic_aberration,
+        }
+    }
+
+    /// Moves `parameter` by `steps` of its step size, keeping it within its range
+    pub fn adjust(&mut self, parameter: PostParameter, steps: f32) {
+        let (step, min, max) = parameter.step_and_range();
+        let value = (self.get(parameter) + steps * step).clamp(min, max);
+        match parameter {
+            PostParameter::BloomThreshold => self.bloom_threshold = value,
+            PostParameter::BloomIntensity => self.bloom_intensity = value,
+            PostParameter::Aperture => self.aperture = value,
+            PostParameter::VignetteStrength => self.vignette_strength = value,
+            PostParameter::ChromaticAberration => self.chromatic_aberration = value,
+        }
+    }
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> Self {
+        Self {
+            bloom_threshold: 1.0,
+            bloom_knee: 0.5,
+            bloom_intensity: 0.3,
+            focus_distance: 10.0,
+            aperture: 0.5,
+            max_blur: 12.0,
+            chromatic_aberration: 0.004,
+            vignette_strength: 0.6,
+            vignette_radius: 0.4,
+            enabled: PostEffect::Bloom as u32 | PostEffect::Vignette as u32,
+            _padding: [0; 2],
+        }
+    }
+}
+
+/// Bind groups of the passes reading the image being post processed
+struct SourceBindGroups {
+    source: Id<TextureView>,
+    prefilter: BindGroup,
+    dof: BindGroup,
+    composite: BindGroup,
+}
+
+/// Bind groups of the passes reading only the post processor's own targets
+struct TargetBindGroups {
+    downsample: Vec<BindGroup>,
+    upsample: Vec<BindGroup>,
+    dof_composite: BindGroup,
+}
+
+/// Resources shared by every pass's bind group
+struct Bindings {
+    sampler: wgpu::Sampler,
+    settings_uniform: wgpu::Buffer,
+
+    single_layout: BindGroupLayout,
+    dual_layout: BindGroupLayout,
+    depth_layout: BindGroupLayout,
+}
+
+/// Offscreen targets and the chain of full screen passes that turn the
+/// main render pass output into the final image.
+pub struct PostProcessor {
+    pub hdr_view: TextureView,
+    pub depth_view: TextureView,
+    dof_view: TextureView,
+    bloom_views: Vec<TextureView>,
+
+    /// Bind groups only depend on the targets, so are made again only when they're resized
+    target_bind_groups: TargetBindGroups,
+    source_bind_groups: Vec<SourceBindGroups>,
+
+    bindings: Bindings,
+
+    prefilter_pipeline: RenderPipeline,
+    downsample_pipeline: RenderPipeline,
+    upsample_pipeline: RenderPipeline,
+    dof_pipeline: RenderPipeline,
+    composite_pipeline: RenderPipeline,
+}
+
+pub(super) fn create_target(
+    device: &Device,
+    label: &str,
+    size: (u32, u32),
+    format: TextureFormat,
+    extra_usage: wgpu::TextureUsages,
+) -> TextureView {
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size.0.max(1),
+                height: size.1.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | wgpu::TextureUsages::TEXTURE_BINDING
+                | extra_usage,
+            view_formats: &[],
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+/// Layout of `textures.len()` textures followed by a sampler and the settings uniform.
+pub(super) fn create_layout(device: &Device, label: &str, textures: &[bool]) -> BindGroupLayout {
+    let mut entries: Vec<_> = textures
+        .iter()
+        .enumerate()
+        .map(|(binding, filterable)| wgpu::BindGroupLayoutEntry {
+            binding: binding as u32,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float {
+                    filterable: *filterable,
+                },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        })
+        .collect();
+
+    entries.push(wgpu::BindGroupLayoutEntry {
+        binding: textures.len() as u32,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    });
+
+    entries.push(wgpu::BindGroupLayoutEntry {
+        binding: textures.len() as u32 + 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    });
+
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some(label),
+        entries: &entries,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) fn create_pipeline(
+    device: &Device,
+    label: &str,
+    layout: &BindGroupLayout,
+    vertex_module: &ShaderModule,
+    fragment_module: &ShaderModule,
+    entry_point: &str,
+    format: TextureFormat,
+    blend: wgpu::BlendState,
+) -> RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: vertex_module,
+            entry_point: "main",
+            buffers: &[],
+            compilation_options: PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: fragment_module,
+            entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(blend),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: Default::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+pub(super) fn fullscreen_pass(
+    encoder: &mut CommandEncoder,
+    label: &'static str,
+    target: &TextureView,
+    load: wgpu::LoadOp<wgpu::Color>,
+    pipeline: &RenderPipeline,
+    bind_group: &BindGroup,
+    timer: Option<&GpuTimer>,
+) {
+    let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        timestamp_writes: timer.and_then(|timer| timer.render_pass(label)),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load,
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        ..Default::default()
+    });
+
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, bind_group, &[]);
+    pass.draw(0..3, 0..1);
+}
+
+impl Bindings {
+    fn bind_group(
+        &self,
+        device: &Device,
+        layout: &BindGroupLayout,
+        textures: &[&TextureView],
+    ) -> BindGroup {
+        let mut entries: Vec<_> = textures
+            .iter()
+            .enumerate()
+            .map(|(binding, view)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: wgpu::BindingResource::TextureView(view),
+            })
+            .collect();
+
+        entries.push(wgpu::BindGroupEntry {
+            binding: textures.len() as u32,
+            resource: wgpu::BindingResource::Sampler(&self.sampler),
+        });
+
+        entries.push(wgpu::BindGroupEntry {
+            binding: textures.len() as u32 + 1,
+            resource: self.settings_uniform.as_entire_binding(),
+        });
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Post Bind Group"),
+            layout,
+            entries: &entries,
+        })
+    }
+
+    fn target_bind_groups(
+        &self,
+        device: &Device,
+        dof_view: &TextureView,
+        bloom_views: &[TextureView],
+    ) -> TargetBindGroups {
+        let single = |view| self.bind_group(device, &self.single_layout, &[view]);
+
+        TargetBindGroups {
+            // Each downsample reads the level above it and each upsample the level below
+            downsample: bloom_views[..bloom_views.len() - 1]
+                .iter()
+                .map(single)
+                .collect(),
+            upsample: bloom_views[1..].iter().map(single).collect(),
+            dof_composite: self.bind_group(device, &self.dual_layout, &[dof_view, &bloom_views[0]]),
+        }
+    }
+
+    fn source_bind_groups(
+        &self,
+        device: &Device,
+        source: &TextureView,
+        depth_view: &TextureView,
+        bloom_views: &[TextureView],
+    ) -> SourceBindGroups {
+        SourceBindGroups {
+            source: source.global_id(),
+            prefilter: self.bind_group(device, &self.single_layout, &[source]),
+            dof: self.bind_group(device, &self.depth_layout, &[source, depth_view]),
+            composite: self.bind_group(device, &self.dual_layout, &[source, &bloom_views[0]]),
+        }
+    }
+}
+
+impl PostProcessor {
+    /// `sources` are the images besides `hdr_view` that `encode` will be given, see `resize`
+    pub fn new(
+        device: &Device,
+        size: (u32, u32),
+        output_format: TextureFormat,
+        sources: &[&TextureView],
+    ) -> Self {
+        let vertex_module =
+            device.create_shader_module(wgpu::include_wgsl!("../shaders/fullscreen.wgsl"));
+        let bloom_module =
+            device.create_shader_module(wgpu::include_wgsl!("../shaders/bloom.wgsl"));
+        let dof_module = device.create_shader_module(wgpu::include_wgsl!("../shaders/dof.wgsl"));
+        let composite_module =
+            device.create_shader_module(wgpu::include_wgsl!("../shaders/composite.wgsl"));
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Post Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let settings_uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Post Process Settings Buffer"),
+            contents: bytemuck::bytes_of(&PostProcessSettings::default()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bindings = Bindings {
+            sampler,
+            settings_uniform,
+            single_layout: create_layout(device, "Post Single Layout", &[true]),
+            dual_layout: create_layout(device, "Post Dual Layout", &[true, true]),
+            depth_layout: create_layout(device, "Post Depth Layout", &[true, false]),
+        };
+
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::REPLACE,
+        };
+
+        let prefilter_pipeline = create_pipeline(
+            device,
+            "Bloom Prefilter Pipeline",
+            &bindings.single_layout,
+            &vertex_module,
+            &bloom_module,
+            "prefilter",
+            HDR_FORMAT,
+            wgpu::BlendState::REPLACE,
+        );
+
+        let downsample_pipeline = create_pipeline(
+            device,
+            "Bloom Downsample Pipeline",
+            &bindings.single_layout,
+            &vertex_module,
+            &bloom_module,
+            "downsample",
+            HDR_FORMAT,
+            wgpu::BlendState::REPLACE,
+        );
+
+        let upsample_pipeline = create_pipeline(
+            device,
+            "Bloom Upsample Pipeline",
+            &bindings.single_layout,
+            &vertex_module,
+            &bloom_module,
+            "upsample",
+            HDR_FORMAT,
+            additive,
+        );
+
+        let dof_pipeline = create_pipeline(
+            device,
+            "Depth Of Field Pipeline",
+            &bindings.depth_layout,
+            &vertex_module,
+            &dof_module,
+            "main",
+            HDR_FORMAT,
+            wgpu::BlendState::REPLACE,
+        );
+
+        let composite_pipeline = create_pipeline(
+            device,
+            "Composite Pipeline",
+            &bindings.dual_layout,
+            &vertex_module,
+            &composite_module,
+            "main",
+            output_format,
+            wgpu::BlendState::REPLACE,
+        );
+
+        let (hdr_view, depth_view, dof_view, bloom_views) = Self::create_targets(device, size);
+        let target_bind_groups = bindings.target_bind_groups(device, &dof_view, &bloom_views);
+        let source_bind_groups = std::iter::once(&hdr_view)
+            .chain(sources.iter().copied())
+            .map(|source| bindings.source_bind_groups(device, source, &depth_view, &bloom_views))
+            .collect();
+
+        Self {
+            hdr_view,
+            depth_view,
+            dof_view,
+            bloom_views,
+            target_bind_groups,
+            source_bind_groups,
+            bindings,
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            dof_pipeline,
+            composite_pipeline,
+        }
+    }
+
+    fn create_targets(
+        device: &Device,
+        size: (u32, u32),
+    ) -> (TextureView, TextureView, TextureView, Vec<TextureView>) {
+        // The main pass output is also written as storage by the compute render path
+        let main_usage = wgpu::TextureUsages::STORAGE_BINDING;
+        let hdr_view = create_target(device, "HDR Target", size, HDR_FORMAT, main_usage);
+        let depth_view = create_target(device, "Depth Target", size, DEPTH_FORMAT, main_usage);
+        let dof_view = create_target(
+            device,
+            "Depth Of Field Target",
+            size,
+            HDR_FORMAT,
+            wgpu::TextureUsages::empty(),
+        );
+
+        let bloom_views = (1..=BLOOM_LEVELS)
+            .map(|level| {
+                create_target(
+                    device,
+                    "Bloom Target",
+                    (size.0 >> level, size.1 >> level),
+                    HDR_FORMAT,
+                    wgpu::TextureUsages::empty(),
+                )
+            })
+            .collect();
+
+        (hdr_view, depth_view, dof_view, bloom_views)
+    }
+
+    /// Recreates the targets at `size`, along with the bind groups reading them. `sources` are
+    /// the images besides `hdr_view` that `encode` will be given, so their bind groups are made
+    /// up front too.
+    pub fn resize(&mut self, device: &Device, size: (u32, u32), sources: &[&TextureView]) {
+        (
+            self.hdr_view,
+            self.depth_view,
+            self.dof_view,
+            self.bloom_views,
+        ) = Self::create_targets(device, size);
+
+        self.target_bind_groups =
+            self.bindings
+                .target_bind_groups(device, &self.dof_view, &self.bloom_views);
+
+        self.source_bind_groups = std::iter::once(&self.hdr_view)
+            .chain(sources.iter().copied())
+            .map(|source| {
+                self.bindings.source_bind_groups(
+                    device,
+                    source,
+                    &self.depth_view,
+                    &self.bloom_views,
+                )
+            })
+            .collect();
+    }
+
+    /// Records every enabled pass, reading `source` and the depth target and writing to `output`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn encode(
+        &self,
+        device: &Device,
+        queue: &wgpu::Queue,
+        encoder: &mut CommandEncoder,
+        settings: &PostProcessSettings,
+        source: &TextureView,
+        output: &TextureView,
+        timer: Option<&GpuTimer>,
+    ) {
+        queue.write_buffer(
+            &self.bindings.settings_uniform,
+            0,
+            bytemuck::bytes_of(settings),
+        );
+
+        // Sources not given to `resize` still work, only without their bind groups cached
+        let uncached;
+        let source_bind_groups = match self
+            .source_bind_groups
+            .iter()
+            .find(|bind_groups| bind_groups.source == source.global_id())
+        {
+            Some(bind_groups) => bind_groups,
+            None => {
+                uncached = self.bindings.source_bind_groups(
+                    device,
+                    source,
+                    &self.depth_view,
+                    &self.bloom_views,
+                );
+                &uncached
+            }
+        };
+        let targets = &self.target_bind_groups;
+
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+
+        if settings.is_enabled(PostEffect::Bloom) {
+            fullscreen_pass(
+                encoder,
+                "Bloom Prefilter Pass",
+                &self.bloom_views[0],
+                clear,
+                &self.prefilter_pipeline,
+                &source_bind_groups.prefilter,
+                timer,
+            );
+
+            for level in 1..self.bloom_views.len() {
+                fullscreen_pass(
+                    encoder,
+                    "Bloom Downsample Pass",
+                    &self.bloom_views[level],
+                    clear,
+                    &self.downsample_pipeline,
+                    &targets.downsample[level - 1],
+                    timer,
+                );
+            }
+
+            for level in (0..self.bloom_views.len() - 1).rev() {
+                fullscreen_pass(
+                    encoder,
+                    "Bloom Upsample Pass",
+                    &self.bloom_views[level],
+                    wgpu::LoadOp::Load,
+                    &self.upsample_pipeline,
+                    &targets.upsample[level],
+                    timer,
+                );
+            }
+        }
+
+        let composite_bind_group = if settings.is_enabled(PostEffect::DepthOfField) {
+            fullscreen_pass(
+                encoder,
+                "Depth Of Field Pass",
+                &self.dof_view,
+                clear,
+                &self.dof_pipeline,
+                &source_bind_groups.dof,
+                timer,
+            );
+            &targets.dof_composite
+        } else {
+            &source_bind_groups.composite
+        };
+
+        fullscreen_pass(
+            encoder,
+            "Composite Pass",
+            output,
+            clear,
+            &self.composite_pipeline,
+            composite_bind_group,
+            timer,
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use image::RgbaImage;
+use wgpu::{Device, Queue, Texture};
+
+/// Copies an 8 bit colour texture back to the CPU, blocking until the GPU is done with it.
+/// Returns `None` for formats other than RGBA or BGRA with 8 bits per channel.
+pub fn read_texture(device: &Device, queue: &Queue, texture: &Texture) -> Option<RgbaImage> {
+    let swizzle = match texture.format() {
+        wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => false,
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => true,
+        format => {
+            log::error!("Can't read back {format:?} textures");
+            return None;
+        }
+    };
+
+    let (width, height) = (texture.width(), texture.height());
+    // Rows of buffer copies must be aligned to 256 bytes
+    let row_bytes = width * 4;
+    let padded_row_bytes = row_bytes.next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Readback Buffer"),
+        size: padded_row_bytes as u64 * height as u64,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    encoder.copy_texture_to_buffer(
+        texture.as_image_copy(),
+        wgpu::ImageCopyBuffer {
+            buffer: &buffer,
+            layout: wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_row_bytes),
+                rows_per_image: Some(height),
+            },
+        },
+        texture.size(),
+    );
+    queue.submit(Some(encoder.finish()));
+
+    let slice = buffer.slice(..);
+    slice.map_async(wgpu::MapMode::Read, |_| {});
+    device.poll(wgpu::Maintain::Wait);
+
+    let mut pixels = Vec::with_capacity((row_bytes * height) as usize);
+    for row in slice.get_mapped_range().chunks(padded_row_bytes as usize) {
+        for pixel in row[..row_bytes as usize].chunks_exact(4) {
+            let (r, b) = match swizzle {
+                true => (pixel[2], pixel[0]),
+                false => (pixel[0], pixel[2]),
+            };
+            pixels.extend_from_slice(&[r, pixel[1], b, pixel[3]]);
+        }
+    }
+    buffer.unmap();
+
+    RgbaImage::from_raw(width, height, pixels)
+}
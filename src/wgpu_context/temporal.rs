@@ -0,0 +1,238 @@
+use bytemuck::{Pod, Zeroable};
+use glam::{vec2, Vec2};
+use wgpu::{util::DeviceExt, BindGroupLayout, CommandEncoder, Device, RenderPipeline, TextureView};
+
+use crate::camera::Camera;
+
+use super::{
+    gpu_timer::GpuTimer,
+    post_process::{create_layout, create_pipeline, create_target, fullscreen_pass, HDR_FORMAT},
+};
+
+const JITTER_SEQUENCE_LENGTH: usize = 16;
+const HISTORY_BLEND: f32 = 0.1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AntiAliasing {
+    /// One ray through every pixel center
+    None,
+    /// One jittered ray per frame, accumulated with reprojected history
+    Temporal,
+    /// Average of several jittered frames, for stills
+    Supersample(u32),
+}
+
+impl AntiAliasing {
+    pub fn next(self) -> Self {
+        match self {
+            Self::None => Self::Temporal,
+            Self::Temporal => Self::Supersample(4),
+            Self::Supersample(_) => Self::None,
+        }
+    }
+}
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResolveMode {
+    Reproject = 0,
+    Accumulate = 1,
+    Reset = 2,
+}
+
+#[repr(C, align(16))]
+#[derive(Clone, Copy, Debug)]
+struct ResolveUniform {
+    current: Camera,
+    previous: Camera,
+    jitter: Vec2,
+    blend: f32,
+    mode: u32,
+}
+
+unsafe impl Pod for ResolveUniform {}
+unsafe impl Zeroable for ResolveUniform {}
+
+fn halton(mut index: usize, base: usize) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// Sub-pixel offset in pixels, in the range -0.5..0.5
+pub fn jitter_offset(frame: usize) -> Vec2 {
+    let index = frame % JITTER_SEQUENCE_LENGTH + 1;
+    vec2(halton(index, 2), halton(index, 3)) - 0.5
+}
+
+/// Blends jittered frames into a history buffer, reprojecting the history
+/// with the previous frame's camera.
+pub struct TemporalResolver {
+    history_views: [TextureView; 2],
+    current: usize,
+    frame: usize,
+    previous_camera: Option<Camera>,
+
+    sampler: wgpu::Sampler,
+    uniform: wgpu::Buffer,
+    layout: BindGroupLayout,
+    pipeline: RenderPipeline,
+}
+
+impl TemporalResolver {
+    pub fn new(device: &Device, size: (u32, u32)) -> Self {
+        let vertex_module =
+            device.create_shader_module(wgpu::include_wgsl!("../shaders/fullscreen.wgsl"));
+        let resolve_module =
+            device.create_shader_module(wgpu::include_wgsl!("../shaders/taa.wgsl"));
+
+        let layout = create_layout(device, "Temporal Resolve Layout", &[true, false, true]);
+
+        let pipeline = create_pipeline(
+            device,
+            "Temporal Resolve Pipeline",
+            &layout,
+            &vertex_module,
+            &resolve_module,
+            "main",
+            HDR_FORMAT,
+            wgpu::BlendState::REPLACE,
+        );
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Temporal Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Temporal Resolve Buffer"),
+            contents: bytemuck::bytes_of(&ResolveUniform::zeroed()),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        Self {
+            history_views: Self::create_history(device, size),
+            current: 0,
+            frame: 0,
+            previous_camera: None,
+            sampler,
+            uniform,
+            layout,
+            pipeline,
+        }
+    }
+
+    fn create_history(device: &Device, size: (u32, u32)) -> [TextureView; 2] {
+        let usage = wgpu::TextureUsages::empty();
+        [
+            create_target(device, "History Target", size, HDR_FORMAT, usage),
+            create_target(device, "History Target", size, HDR_FORMAT, usage),
+        ]
+    }
+
+    pub fn resize(&mut self, device: &Device, size: (u32, u32)) {
+        self.history_views = Self::create_history(device, size);
+        self.previous_camera = None;
+    }
+
+    /// Discards the history, so the next resolve starts from the current frame alone
+    pub fn reset(&mut self) {
+        self.previous_camera = None;
+    }
+
+    /// Jitter to apply to the next frame rendered
+    pub fn next_jitter(&mut self) -> Vec2 {
+        self.frame = self.frame.wrapping_add(1);
+        jitter_offset(self.frame)
+    }
+
+    /// Every target `output` can return
+    pub fn history(&self) -> [&TextureView; 2] {
+        [&self.history_views[0], &self.history_views[1]]
+    }
+
+    /// The most recently resolved frame
+    pub fn output(&self) -> &TextureView {
+        &self.history_views[self.current]
+    }
+
+    /// Resolves `source` into the next history target.
+    ///
+    /// The uniform is written immediately, so only one resolve may be encoded per submission.
+    #[allow(clippy::too_many_arguments)]
+    pub fn encode(
+        &mut self,
+        device: &Device,
+        queue: &wgpu::Queue,
+        encoder: &mut CommandEncoder,
+        source: &TextureView,
+        depth: &TextureView,
+        camera: &Camera,
+        jitter: Vec2,
+        mode: ResolveMode,
+        blend: Option<f32>,
+        timer: Option<&GpuTimer>,
+    ) {
+        let mode = match self.previous_camera {
+            None => ResolveMode::Reset,
+            Some(_) => mode,
+        };
+
+        let uniform = ResolveUniform {
+            current: *camera,
+            previous: self.previous_camera.unwrap_or(*camera),
+            jitter,
+            blend: blend.unwrap_or(HISTORY_BLEND),
+            mode: mode as u32,
+        };
+        queue.write_buffer(&self.uniform, 0, bytemuck::bytes_of(&uniform));
+
+        let previous = self.current;
+        self.current = 1 - self.current;
+        self.previous_camera = Some(*camera);
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Temporal Resolve Bind Group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(depth),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&self.history_views[previous]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: self.uniform.as_entire_binding(),
+                },
+            ],
+        });
+
+        fullscreen_pass(
+            encoder,
+            "Temporal Resolve Pass",
+            &self.history_views[self.current],
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+            &self.pipeline,
+            &bind_group,
+            timer,
+        );
+    }
+}